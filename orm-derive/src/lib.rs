@@ -6,16 +6,28 @@ use syn::{parse_macro_input, Attribute, DeriveInput, Field, NestedMeta, };
 use syn::punctuated::Punctuated;
 use syn::token::{Comma};
 
-#[proc_macro_derive(Object, attributes(table_name, column_name))]
+#[proc_macro_derive(Object, attributes(table_name, column_name, index, readonly, null_as_default, check, strict, yorm))]
 pub fn derive_object(input: TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     let type_name = &input.ident;
-    let table_name = if input.attrs.is_empty() {
-        type_name.clone()
-    } else {
-        syn::Ident::new(&get_attribute_ident(&input.attrs[0]), syn::__private::Span::call_site())
+    // #[yorm(table = PATH)] names the table by a &'static str constant.
+    let table_name = match (find_attribute(&input.attrs, "table_name"), get_yorm_value(&input.attrs, "table")) {
+        (Some(_), Some(_)) => panic!("expected either #[table_name(...)] or #[yorm(table = ...)]"),
+        (Some(attr), None) => {
+            let table_name = syn::Ident::new(&get_attribute_ident(attr), syn::__private::Span::call_site());
+            quote! { stringify!(#table_name) }
+        }
+        (None, Some(syn::Expr::Lit(syn::ExprLit {lit: syn::Lit::Str(lit_str), ..}))) => {
+            let table_name = lit_str.value();
+            quote! { #table_name }
+        }
+        (None, Some(syn::Expr::Path(syn::ExprPath {path, ..}))) => quote! { #path },
+        (None, Some(_)) => panic!("expected #[yorm(table = \"...\")] or #[yorm(table = PATH)]"),
+        (None, None) => quote! { stringify!(#type_name) },
     };
+    let has_explicit_table_name = find_attribute(&input.attrs, "table_name").is_some()
+        || get_yorm_value(&input.attrs, "table").is_some();
 
 
     let struct_ = match input.data {
@@ -23,48 +35,176 @@ pub fn derive_object(input: TokenStream) -> proc_macro::TokenStream {
         _ => panic!("Not implemented for not structs"),
     };
     let named_fields = match struct_.fields {
-        syn::Fields::Named(fields) => Some(fields.named),
+        syn::Fields::Named(fields) => Some(sort_fields(fields.named)),
         syn::Fields::Unit => None,
         _ => panic!("Not implemented for other type of fields"),
     };
 
+    check_limits(&input.attrs, type_name, named_fields.as_ref());
+
     let field_names = make_field_names(named_fields.as_ref());
     let column_names = make_column_names(named_fields.as_ref());
     let column_types = make_column_types(named_fields.as_ref());
+    let column_readonly = make_column_readonly(named_fields.as_ref());
+    let column_nullability = make_column_nullability(named_fields.as_ref());
 
     let as_row = make_as_row(named_fields.as_ref());
     let from_row = make_from_row(named_fields.as_ref());
+    let is_fieldless = named_fields.as_ref().map_or(true, |fields| fields.is_empty());
+    let indexes = make_indexes(&input.attrs, named_fields.as_ref());
+    let checks = make_checks(&input.attrs, named_fields.as_ref());
+    let is_strict = find_attribute(&input.attrs, "strict").is_some();
+    let is_existing_table = has_yorm_flag(&input.attrs, "existing_table");
+    let visible_when = match get_yorm_option(&input.attrs, "visible_when") {
+        None => quote! { None },
+        Some(syn::Lit::Str(lit_str)) => {
+            let predicate = lit_str.value();
+            quote! { Some(#predicate) }
+        }
+        _ => panic!("expected #[yorm(visible_when = \"...\")]"),
+    };
+    let expires_at = match get_yorm_option(&input.attrs, "expires_at") {
+        None => quote! { None },
+        Some(syn::Lit::Str(lit_str)) => {
+            let field_name = lit_str.value();
+            let column_name = get_column_name(named_fields.as_ref(), &field_name);
+            quote! { Some(#column_name) }
+        }
+        _ => panic!("expected #[yorm(expires_at = \"field\")]"),
+    };
+    let connection_type = match get_yorm_option(&input.attrs, "connection_type") {
+        None => quote! { ReadWrite },
+        Some(syn::Lit::Str(lit_str)) if lit_str.value() == "ReadWrite" => quote! { ReadWrite },
+        Some(syn::Lit::Str(lit_str)) if lit_str.value() == "ReadOnly" => quote! { ReadOnly },
+        _ => panic!("expected #[yorm(connection_type = \"ReadWrite|ReadOnly\")]"),
+    };
 
     let expanded = quote! {
-        impl Object for #type_name {
-            fn as_row(&self) -> orm::storage::Row {
+        impl ::orm::object::Object for #type_name {
+            fn as_row(&self) -> ::orm::storage::Row {
                 vec![#as_row]
             }
-            fn from_row(mut row: orm::storage::Row) -> Self {
+            fn from_row(mut row: ::orm::storage::Row) -> Self {
                 Self { #from_row }
             }
             fn table_name() -> &'static str {
-                stringify!(#table_name)
+                #table_name
             }
             fn type_name() -> &'static str {
                 stringify!(#type_name)
             }
-            fn field_names() -> std::vec::Vec<&'static str> {
+            fn has_explicit_table_name() -> bool {
+                #has_explicit_table_name
+            }
+            fn field_names() -> ::std::vec::Vec<&'static str> {
                 vec![#field_names]
             }
-            fn column_names() -> std::vec::Vec<&'static str> {
+            fn column_names() -> ::std::vec::Vec<&'static str> {
                 vec![#column_names]
             }
-            fn column_types() -> std::vec::Vec<DataType> {
+            fn column_types() -> ::std::vec::Vec<::orm::data::DataType> {
                 vec![#column_types]
             }
+            fn column_readonly() -> ::std::vec::Vec<bool> {
+                vec![#column_readonly]
+            }
+            fn column_nullability() -> ::std::vec::Vec<bool> {
+                vec![#column_nullability]
+            }
+            fn is_fieldless() -> bool {
+                #is_fieldless
+            }
+            fn indexes() -> ::std::vec::Vec<::orm::object::Index> {
+                vec![#indexes]
+            }
+            fn is_strict() -> bool {
+                #is_strict
+            }
+            fn is_existing_table() -> bool {
+                #is_existing_table
+            }
+            fn checks() -> ::std::vec::Vec<&'static str> {
+                vec![#checks]
+            }
+            fn visible_when() -> ::std::option::Option<&'static str> {
+                #visible_when
+            }
+            fn expires_at() -> ::std::option::Option<&'static str> {
+                #expires_at
+            }
+            fn connection_type() -> ::orm::ConnectionType {
+                ::orm::ConnectionType::#connection_type
+            }
         }
     };
-    TokenStream::from(expanded)
+    // #[yorm(debug_expand)] keeps the generated impl as a string, for
+    // inspecting it and for the expansion snapshots in tests/expand.
+    let debug_expand = match has_yorm_flag(&input.attrs, "debug_expand") {
+        false => quote! {},
+        true => {
+            let expansion = expanded.to_string();
+            quote! {
+                impl #type_name {
+                    #[doc(hidden)]
+                    pub const YORM_EXPANSION: &'static str = #expansion;
+                }
+            }
+        }
+    };
+    TokenStream::from(quote! { #expanded #debug_expand })
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
 
+// Orders the fields, and so the columns, by #[yorm(column_order = N)]. Fields
+// without it keep their declaration order after all the ordered ones.
+fn sort_fields(fields: Punctuated<Field, Comma>) -> Punctuated<Field, Comma> {
+    let mut fields = fields
+        .into_iter()
+        .map(|field| {
+            let order = match get_yorm_option(&field.attrs, "column_order") {
+                None => None,
+                Some(syn::Lit::Int(lit_int)) => Some(lit_int.base10_parse::<i64>().unwrap()),
+                _ => panic!("expected #[yorm(column_order = N)]"),
+            };
+            (order, field)
+        })
+        .collect::<Vec<_>>();
+    fields.sort_by_key(|(order, _)| order.map_or((1, 0), |order| (0, order)));
+    fields.into_iter().map(|(_, field)| field).collect()
+}
+
+// The same limits as orm::object::MAX_IDENTIFIER_LEN and MAX_COLUMNS, for
+// the names known at compile time; a table name given by a path is checked
+// when the table is first used.
+const MAX_IDENTIFIER_LEN: usize = 128;
+const MAX_COLUMNS: usize = 998;
+
+fn check_limits(attrs: &[Attribute], type_name: &syn::Ident, named_fields: Option<&Punctuated<Field, Comma>>) {
+    let table_name = match (find_attribute(attrs, "table_name"), get_yorm_value(attrs, "table")) {
+        (Some(attr), _) => Some(get_attribute_ident(attr)),
+        (None, Some(syn::Expr::Lit(syn::ExprLit {lit: syn::Lit::Str(lit_str), ..}))) => Some(lit_str.value()),
+        (None, Some(_)) => None,
+        (None, None) => Some(type_name.to_string()),
+    };
+    let mut identifiers = table_name.into_iter().collect::<Vec<_>>();
+    for field in named_fields.into_iter().flatten() {
+        identifiers.push(match find_attribute(&field.attrs, "column_name") {
+            Some(attr) => get_attribute_ident(attr),
+            None => field.ident.as_ref().unwrap().to_string(),
+        });
+    }
+    for identifier in identifiers {
+        if identifier.chars().count() > MAX_IDENTIFIER_LEN {
+            panic!("identifier '{}' is longer than {} characters", identifier, MAX_IDENTIFIER_LEN);
+        }
+    }
+    let columns = named_fields.map_or(0, |fields| fields.len());
+    if columns > MAX_COLUMNS {
+        panic!("{} has {} columns, more than {}", type_name, columns, MAX_COLUMNS);
+    }
+}
+
 fn make_field_names(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__private::TokenStream {
     if named_fields.is_none() {
         return quote! {};
@@ -89,10 +229,9 @@ fn make_column_names(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::
         .unwrap()
         .iter()
         .map(|p| {
-            let column_name = if p.attrs.is_empty() {
-                p.ident.as_ref().unwrap().clone()
-            } else {
-                syn::Ident::new(&get_attribute_ident(&p.attrs[0]), syn::__private::Span::call_site())
+            let column_name = match find_attribute(&p.attrs, "column_name") {
+                None => p.ident.as_ref().unwrap().clone(),
+                Some(attr) => syn::Ident::new(&get_attribute_ident(attr), syn::__private::Span::call_site()),
             };
             quote! {
                 stringify!(#column_name)
@@ -127,6 +266,14 @@ fn make_from_row(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__pr
         .rev()
         .map(|p| {
             let ident = p.ident.as_ref().unwrap();
+            if find_attribute(&p.attrs, "null_as_default").is_some() {
+                return quote! {
+                    #ident: match row.pop().unwrap() {
+                        ::orm::data::Value::Null => ::std::default::Default::default(),
+                        value => value.into(),
+                    }
+                };
+            }
             quote! {
                 #ident: row.pop().unwrap().into()
             }
@@ -143,6 +290,15 @@ fn make_column_types(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::
         .unwrap()
         .iter()
         .map(|p| {
+            if let Some(custom) = get_yorm_option(&p.attrs, "with") {
+                let custom = match custom {
+                    syn::Lit::Str(lit_str) => lit_str.parse::<syn::Path>().expect("expected a path in #[yorm(with = \"...\")]"),
+                    _ => panic!("expected #[yorm(with = \"path::TO_CUSTOM_TYPE\")]"),
+                };
+                return quote! {
+                    ::orm::data::DataType::Custom(&#custom)
+                };
+            }
             let ident = p.ty.to_token_stream();
             quote! {
                 stringify!(#ident).into()
@@ -151,8 +307,149 @@ fn make_column_types(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::
     quote! { #(#recurse,)* }
 }
 
+// #[null_as_default] columns are read as nullable, and NULL becomes the
+// Default of the field; the field itself is never written as NULL.
+fn make_column_nullability(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__private::TokenStream {
+    if named_fields.is_none() {
+        return quote! {};
+    }
+    let recurse = named_fields
+        .unwrap()
+        .iter()
+        .map(|p| {
+            let nullable = find_attribute(&p.attrs, "null_as_default").is_some();
+            quote! {
+                #nullable
+            }
+        });
+    quote! { #(#recurse,)* }
+}
+
+fn make_column_readonly(named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__private::TokenStream {
+    if named_fields.is_none() {
+        return quote! {};
+    }
+    let recurse = named_fields
+        .unwrap()
+        .iter()
+        .map(|p| {
+            let readonly = find_attribute(&p.attrs, "readonly").is_some();
+            quote! {
+                #readonly
+            }
+        });
+    quote! { #(#recurse,)* }
+}
+
+fn make_indexes(attrs: &[Attribute], named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__private::TokenStream {
+    let recurse = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("index"))
+        .map(|attr| {
+            let nested = match attr.parse_meta().unwrap() {
+                syn::Meta::List(syn::MetaList {nested, ..}) => nested,
+                _ => panic!("expected #[index(field, ...)]"),
+            };
+            let mut columns = Vec::new();
+            let mut predicate = quote! { None };
+            for meta in nested {
+                match meta {
+                    NestedMeta::Meta(syn::Meta::Path(path)) => {
+                        let field_name = path.get_ident().expect("expected a field name in #[index]").to_string();
+                        columns.push(get_column_name(named_fields, &field_name));
+                    }
+                    NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {path, lit: syn::Lit::Str(lit_str), ..}))
+                        if path.is_ident("where") =>
+                    {
+                        let value = lit_str.value();
+                        predicate = quote! { Some(#value) };
+                    }
+                    _ => panic!("expected a field name or where = \"...\" in #[index]"),
+                }
+            }
+            if columns.is_empty() {
+                panic!("#[index] needs at least one field");
+            }
+            quote! {
+                ::orm::object::Index {
+                    columns: vec![#(#columns,)*],
+                    predicate: #predicate,
+                }
+            }
+        });
+    quote! { #(#recurse,)* }
+}
+
+// #[check("...")] on the struct first, then on the fields in order.
+fn make_checks(attrs: &[Attribute], named_fields: Option<&Punctuated<Field, Comma>>) -> quote::__private::TokenStream {
+    let recurse = attrs
+        .iter()
+        .chain(named_fields.into_iter().flatten().flat_map(|p| p.attrs.iter()))
+        .filter(|attr| attr.path.is_ident("check"))
+        .map(|attr| {
+            let expression = get_attribute_ident(attr);
+            quote! {
+                #expression
+            }
+        });
+    quote! { #(#recurse,)* }
+}
+
 ///////////////////////////////////////////////////////////////////////////////////////////////////
 
+fn find_attribute<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attrs.iter().find(|attr| attr.path.is_ident(name))
+}
+
+// The options of every #[yorm(...)], parsed as expressions rather than as a
+// Meta, since a value may be a path as well as a literal.
+fn get_yorm_options(attrs: &[Attribute]) -> Vec<syn::Expr> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("yorm"))
+        .flat_map(|attr| {
+            attr.parse_args_with(Punctuated::<syn::Expr, Comma>::parse_terminated)
+                .expect("expected #[yorm(option = value, ...)]")
+        })
+        .collect()
+}
+
+fn is_ident(expr: &syn::Expr, name: &str) -> bool {
+    matches!(expr, syn::Expr::Path(syn::ExprPath {path, ..}) if path.is_ident(name))
+}
+
+// Value of `name` in #[yorm(name = value, ...)].
+fn get_yorm_value(attrs: &[Attribute], name: &str) -> Option<syn::Expr> {
+    get_yorm_options(attrs).into_iter().find_map(|option| match option {
+        syn::Expr::Assign(syn::ExprAssign {left, right, ..}) if is_ident(&left, name) => Some(*right),
+        _ => None,
+    })
+}
+
+// Literal value of `name` in #[yorm(name = value, ...)].
+fn get_yorm_option(attrs: &[Attribute], name: &str) -> Option<syn::Lit> {
+    get_yorm_value(attrs, name).map(|value| match value {
+        syn::Expr::Lit(syn::ExprLit {lit, ..}) => lit,
+        _ => panic!("expected a literal in #[yorm({} = ...)]", name),
+    })
+}
+
+// Whether #[yorm(..., name, ...)] is set.
+fn has_yorm_flag(attrs: &[Attribute], name: &str) -> bool {
+    get_yorm_options(attrs).iter().any(|option| is_ident(option, name))
+}
+
+fn get_column_name(named_fields: Option<&Punctuated<Field, Comma>>, field_name: &str) -> String {
+    let field = named_fields
+        .into_iter()
+        .flatten()
+        .find(|p| p.ident.as_ref().unwrap() == field_name)
+        .unwrap_or_else(|| panic!("no field named {}", field_name));
+    match find_attribute(&field.attrs, "column_name") {
+        None => field_name.to_owned(),
+        Some(attr) => get_attribute_ident(attr),
+    }
+}
 
 fn get_attribute_ident(attr: &Attribute) -> String {
     match attr.parse_meta().unwrap() {
@@ -1,16 +1,228 @@
 #![forbid(unsafe_code)]
-use crate::{storage::StorageTransaction, Result, Transaction};
-use std::path::Path;
+use crate::error::TableNotFoundError;
+use crate::Error;
+use crate::{
+    data::Value,
+    object::{Object, Schema, TableNaming},
+    storage::{RootTransaction, SqlRewriter, StorageTransaction},
+    timing::{QueryStats, QueryTiming},
+    Result, Transaction,
+};
+use rusqlite::{ErrorCode, OpenFlags, TransactionBehavior};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::Path,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionType {
+    ReadWrite,
+    ReadOnly,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Supplies the values of :name placeholders in #[yorm(visible_when = "...")].
+pub trait SecurityContext {
+    fn current_values(&self) -> HashMap<String, Value<'static>>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The current time for #[yorm(expires_at = "...")], replaceable in tests.
+pub trait Clock {
+    fn now_millis(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_millis() as i64
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// How far a WAL checkpoint goes, see PRAGMA wal_checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckpointMode {
+    // As much as possible without waiting for readers or writers.
+    Passive,
+    // Waits for the writers, then copies the whole WAL into the database.
+    Full,
+    // Full, then waits for the readers so that the WAL is restarted.
+    Restart,
+    // Restart, then truncates the WAL file to zero bytes.
+    Truncate,
+}
+
+impl CheckpointMode {
+    fn sql_keyword(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+// Pages are 0 when the database isn't in WAL mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointResult {
+    // A reader or writer kept the checkpoint from completing.
+    pub busy: bool,
+    pub wal_pages: u64,
+    pub checkpointed_pages: u64,
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
 trait StorageConnection {
-    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
+    fn new_transaction(
+        &mut self,
+        sql_rewriter: Option<Rc<SqlRewriter>>,
+        query_stats: Option<Rc<RefCell<QueryStats>>>,
+        behavior: TransactionBehavior,
+    ) -> Result<Box<dyn StorageTransaction + '_>>;
+
+    fn sqlite_version(&self) -> (u32, u32, u32);
+    fn compile_options(&self) -> Result<Vec<String>>;
+    fn list_tables(&self) -> Result<Vec<String>>;
+    // The schema of the table, None if there is no such table.
+    fn table_info(&self, table_name: &str) -> Result<Option<Schema>>;
+
+    fn set_page_size(&mut self, page_size: u32) -> Result<u32>;
+    fn enable_wal(&mut self) -> Result<()>;
+    fn set_wal_autocheckpoint(&mut self, pages: u32) -> Result<()>;
+    fn wal_checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult>;
 }
 
 impl StorageConnection for rusqlite::Connection {
-    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
-        Ok(Box::new(self.transaction()?))
+    fn new_transaction(
+        &mut self,
+        sql_rewriter: Option<Rc<SqlRewriter>>,
+        query_stats: Option<Rc<RefCell<QueryStats>>>,
+        behavior: TransactionBehavior,
+    ) -> Result<Box<dyn StorageTransaction + '_>> {
+        let transaction = self
+            .transaction_with_behavior(behavior)
+            .map_err(lock_conflict)?;
+        Ok(Box::new(RootTransaction::new(
+            transaction,
+            sql_rewriter,
+            query_stats,
+        )))
+    }
+
+    fn sqlite_version(&self) -> (u32, u32, u32) {
+        let number = rusqlite::version_number() as u32;
+        (number / 1_000_000, number / 1_000 % 1_000, number % 1_000)
+    }
+
+    fn compile_options(&self) -> Result<Vec<String>> {
+        let mut stmt = self.prepare("PRAGMA compile_options;")?;
+        let options = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(options)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>> {
+        let mut stmt = self.prepare(
+            "SELECT name FROM sqlite_master \
+                WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(names)
+    }
+
+    fn table_info(&self, table_name: &str) -> Result<Option<Schema>> {
+        let mut stmt =
+            self.prepare("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?);")?;
+        let columns = stmt
+            .query_map([table_name], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get::<_, i64>(3)? > 0,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        match columns.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(Schema::new_from_table_info(table_name, &columns))),
+        }
+    }
+
+    fn set_page_size(&mut self, page_size: u32) -> Result<u32> {
+        self.execute_batch(&format!("PRAGMA page_size = {};", page_size))?;
+        let page_size: i64 = self.query_row("PRAGMA page_size;", [], |row| row.get(0))?;
+        Ok(page_size as u32)
+    }
+
+    fn enable_wal(&mut self) -> Result<()> {
+        // Answers the journal mode it ended up in, e.g. "memory" in memory.
+        let mode: String = self
+            .query_row("PRAGMA journal_mode = WAL;", [], |row| row.get(0))
+            .map_err(lock_conflict)?;
+        match mode.eq_ignore_ascii_case("wal") {
+            true => Ok(()),
+            false => Err(Error::Storage(
+                format!("journal mode stayed {}", mode).into(),
+            )),
+        }
+    }
+
+    fn set_wal_autocheckpoint(&mut self, pages: u32) -> Result<()> {
+        self.query_row(
+            &format!("PRAGMA wal_autocheckpoint = {};", pages),
+            [],
+            |_| Ok(()),
+        )?;
+        Ok(())
+    }
+
+    fn wal_checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult> {
+        let query = format!("PRAGMA wal_checkpoint({});", mode.sql_keyword());
+        let (busy, wal_pages, checkpointed_pages) = self
+            .query_row(&query, [], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .map_err(lock_conflict)?;
+        Ok(CheckpointResult {
+            busy: busy != 0,
+            wal_pages: wal_pages.max(0) as u64,
+            checkpointed_pages: checkpointed_pages.max(0) as u64,
+        })
+    }
+}
+
+// Another connection holding a lock longer than the busy timeout.
+fn lock_conflict(err: rusqlite::Error) -> Error {
+    match err {
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked,
+                ..
+            },
+            _,
+        ) => Error::LockConflict,
+        err => err.into(),
     }
 }
 
@@ -18,22 +230,296 @@ impl StorageConnection for rusqlite::Connection {
 
 pub struct Connection {
     inner: Box<dyn StorageConnection>,
+    connection_type: ConnectionType,
+    security_context: Option<Rc<dyn SecurityContext>>,
+    clock: Rc<dyn Clock>,
+    strict_schema: bool,
+    max_rows_per_query: Option<u64>,
+    analyze_threshold: Option<u64>,
+    sql_rewriter: Option<Rc<SqlRewriter>>,
+    table_naming: TableNaming,
+    // Schemas of the registered types, by table name.
+    schemas: HashMap<&'static str, Schema>,
+    query_stats: Option<Rc<RefCell<QueryStats>>>,
 }
 
 impl Connection {
+    fn new(inner: Box<dyn StorageConnection>, connection_type: ConnectionType) -> Self {
+        Self {
+            inner,
+            connection_type,
+            security_context: None,
+            clock: Rc::new(SystemClock),
+            strict_schema: false,
+            max_rows_per_query: None,
+            analyze_threshold: None,
+            sql_rewriter: None,
+            table_naming: TableNaming::TypeName,
+            schemas: HashMap::new(),
+            query_stats: None,
+        }
+    }
+
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
-        })
+        Ok(Self::new(
+            Box::new(rusqlite::Connection::open(path)?),
+            ConnectionType::ReadWrite,
+        ))
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(
+            Box::new(rusqlite::Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY,
+            )?),
+            ConnectionType::ReadOnly,
+        ))
     }
 
     pub fn open_in_memory() -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
-        })
+        Ok(Self::new(
+            Box::new(rusqlite::Connection::open_in_memory()?),
+            ConnectionType::ReadWrite,
+        ))
+    }
+
+    pub fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
+    pub fn with_security_context<C: SecurityContext + 'static>(mut self, context: C) -> Self {
+        self.security_context = Some(Rc::new(context));
+        self
+    }
+
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Rc::new(clock);
+        self
+    }
+
+    // Checks an existing table against the schema before the first insert of
+    // each type in a transaction, so that a table with a column of the wrong
+    // type fails with SchemaMismatch before any data is written.
+    pub fn with_strict_schema(mut self) -> Self {
+        self.strict_schema = true;
+        self
+    }
+
+    // A safeguard against loading a huge table by mistake: queries of the
+    // transactions (query_scalar*, load_subtree) which return more than
+    // max_rows rows fail with ResultTooLarge instead.
+    pub fn with_max_rows_per_query(mut self, max_rows: u64) -> Self {
+        self.max_rows_per_query = Some(max_rows);
+        self
+    }
+
+    // Runs ANALYZE on commit for every table which got more than threshold
+    // rows created in the transaction, so that bulk loads keep the query
+    // planner statistics up to date.
+    pub fn with_analyze_threshold(mut self, threshold: u64) -> Self {
+        self.analyze_threshold = Some(threshold);
+        self
+    }
+
+    // Names the tables of the types without an explicit name, i.e. neither
+    // #[table_name] nor #[yorm(table = ...)], which otherwise are named after
+    // the type. Changing it for an existing database points those types at
+    // other tables; nothing is renamed. Types registered before are renamed
+    // as well, so the order of the two calls doesn't matter.
+    pub fn with_table_naming(mut self, naming: TableNaming) -> Self {
+        self.table_naming = naming;
+        self.schemas = self
+            .schemas
+            .drain()
+            .map(|(_, schema)| {
+                let schema = schema.with_table_naming(naming);
+                (schema.get_table_name(), schema)
+            })
+            .collect();
+        self
+    }
+
+    // Advanced: every statement the ORM generates is passed through the
+    // rewriter before it's prepared, e.g. to add INDEXED BY hints. The result
+    // is run as it is, so a broken rewrite breaks every query; raw SQL passed
+    // to query_scalar* isn't rewritten.
+    pub fn set_sql_rewriter(&mut self, rewriter: Box<dyn Fn(&str) -> String>) {
+        self.sql_rewriter = Some(Rc::from(rewriter));
+    }
+
+    // Times every statement the ORM generates, aggregated by its shape (the
+    // SQL without the values), for at most max_shapes shapes: past that, the
+    // least recently run shape is dropped. Raw SQL isn't timed.
+    pub fn enable_query_timing(&mut self, max_shapes: usize) {
+        match &self.query_stats {
+            Some(stats) => stats.borrow_mut().set_max_shapes(max_shapes),
+            None => self.query_stats = Some(Rc::new(RefCell::new(QueryStats::new(max_shapes)))),
+        }
+    }
+
+    // Calls callback the first time a statement shape runs for longer than
+    // threshold, e.g. to log it with its EXPLAIN QUERY PLAN. Enables query
+    // timing for 256 shapes unless it's on already; a shape dropped and timed
+    // again is reported again.
+    pub fn set_slow_query_callback(
+        &mut self,
+        threshold: Duration,
+        callback: Box<dyn Fn(&QueryTiming)>,
+    ) {
+        if self.query_stats.is_none() {
+            self.enable_query_timing(256);
+        }
+        if let Some(stats) = &self.query_stats {
+            stats
+                .borrow_mut()
+                .set_slow_query_callback(threshold, Rc::from(callback));
+        }
+    }
+
+    // The shapes which ran for threshold or longer at least once, slowest first.
+    pub fn slow_queries(&self, threshold: Duration) -> Vec<QueryTiming> {
+        self.query_stats
+            .as_ref()
+            .map_or_else(Vec::new, |stats| stats.borrow().slow_queries(threshold))
+    }
+
+    // Makes the schema of T available by its table name, for generic code
+    // which only has the name, e.g. to open it with Transaction::dyn_table.
+    // A later type of the same table replaces the earlier one.
+    // Fails with IncompatibleSqliteVersion if the SQLite linked is too old
+    // for the type.
+    pub fn register<T: Object>(&mut self) -> Result<()> {
+        let schema = <T as Object>::describe().with_table_naming(self.table_naming);
+        schema.check_sqlite_version(self.inner.sqlite_version())?;
+        self.schemas.insert(schema.get_table_name(), schema);
+        Ok(())
+    }
+
+    // Only the types passed to register are known here.
+    pub fn schema_for(&self, table_name: &str) -> Option<&Schema> {
+        self.schemas.get(table_name)
     }
 
     pub fn new_transaction(&mut self) -> Result<Transaction<'_>> {
-        Ok(Transaction::new(self.inner.new_transaction()?))
+        self.begin(TransactionBehavior::Deferred)
+    }
+
+    // Starts with BEGIN IMMEDIATE, taking the write lock of the whole database
+    // up front rather than on the first write; fails with LockConflict while
+    // another connection holds it. Needed by Transaction::get_for_update.
+    pub fn new_immediate_transaction(&mut self) -> Result<Transaction<'_>> {
+        self.begin(TransactionBehavior::Immediate)
+    }
+
+    // Runs f in a transaction begun with BEGIN EXCLUSIVE, which unlike BEGIN
+    // IMMEDIATE also keeps other connections from reading, except in WAL
+    // mode. Commits if f succeeds and rolls back if it fails; fails with
+    // LockConflict while another connection holds a lock.
+    pub fn transaction_exclusive<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<R>,
+    {
+        let transaction = self.begin(TransactionBehavior::Exclusive)?;
+        match f(&transaction) {
+            Ok(result) => {
+                transaction.commit()?;
+                Ok(result)
+            }
+            Err(err) => {
+                // f's error is the one worth reporting.
+                let _ = transaction.rollback();
+                Err(err)
+            }
+        }
+    }
+
+    fn begin(&mut self, behavior: TransactionBehavior) -> Result<Transaction<'_>> {
+        let sqlite_version = self.inner.sqlite_version();
+        for schema in self.schemas.values() {
+            schema.check_sqlite_version(sqlite_version)?;
+        }
+        let mut transaction = Transaction::new(self.inner.new_transaction(
+            self.sql_rewriter.clone(),
+            self.query_stats.clone(),
+            behavior,
+        )?);
+        // An exclusive transaction holds the write lock as well.
+        transaction.set_immediate(!matches!(behavior, TransactionBehavior::Deferred));
+        transaction.set_connection_type(self.connection_type);
+        transaction.set_strict_schema(self.strict_schema);
+        transaction.set_max_rows_per_query(self.max_rows_per_query);
+        transaction.set_analyze_threshold(self.analyze_threshold);
+        transaction.set_table_naming(self.table_naming);
+        transaction.set_sqlite_version(sqlite_version);
+        transaction.set_clock(self.clock.clone());
+        if let Some(context) = &self.security_context {
+            transaction.set_security_context(context.clone());
+        }
+        Ok(transaction)
+    }
+
+    pub fn sqlite_version(&self) -> (u32, u32, u32) {
+        self.inner.sqlite_version()
+    }
+
+    // Sets the page size of a fresh database, before anything is written to
+    // it; an existing database keeps its page size, which SQLite ignores the
+    // PRAGMA for. Returns whether the page size took effect. Fails with
+    // InvalidPageSize unless it's a power of two from 512 to 65536.
+    pub fn set_page_size(&mut self, page_size: u32) -> Result<bool> {
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            return Err(Error::InvalidPageSize(page_size));
+        }
+        Ok(self.inner.set_page_size(page_size)? == page_size)
+    }
+
+    // Switches a file database to write-ahead logging, which persists in the file.
+    pub fn enable_wal(&mut self) -> Result<()> {
+        self.inner.enable_wal()
+    }
+
+    // Checkpoints automatically once the WAL has grown past pages pages
+    // (1000 by default); 0 turns it off.
+    pub fn set_wal_autocheckpoint(&mut self, pages: u32) -> Result<()> {
+        self.inner.set_wal_autocheckpoint(pages)
+    }
+
+    // Copies the WAL back into the database. It takes &mut self, so no
+    // transaction of this connection is open meanwhile; readers of other
+    // connections make it report busy rather than fail. Truncate keeps the
+    // -wal file from growing without bound over heavy write sessions.
+    pub fn wal_checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult> {
+        self.inner.wal_checkpoint(mode)
+    }
+
+    // Tables of the database, ORM-managed or not, by name; SQLite's own
+    // sqlite_* tables are left out.
+    pub fn list_tables(&self) -> Result<Vec<String>> {
+        self.inner.list_tables()
+    }
+
+    // The schema of an existing table, whether yorm created it or not, built
+    // with Schema::new_from_table_info.
+    pub fn table_info(&self, table_name: &str) -> Result<Schema> {
+        match self.inner.table_info(table_name)? {
+            Some(schema) => Ok(schema),
+            None => Err(Error::TableNotFound(Box::new(TableNotFoundError {
+                table_name: table_name.to_owned(),
+            }))),
+        }
+    }
+
+    pub fn compile_options(&self) -> Result<Vec<String>> {
+        self.inner.compile_options()
+    }
+
+    // Checks an option SQLite was compiled with, e.g. "ENABLE_FTS5".
+    // Options with a value ("THREADSAFE=1") match by their name.
+    pub fn has_feature(&self, feature: &str) -> Result<bool> {
+        Ok(self
+            .compile_options()?
+            .iter()
+            .any(|option| option == feature || option.split('=').next() == Some(feature)))
     }
 }
@@ -0,0 +1,133 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    data::Value,
+    error::{Error, Result},
+    storage::{Row, RowSlice},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    pub(crate) fn sql_keyword(self) -> &'static str {
+        match self {
+            Direction::Asc => "ASC",
+            Direction::Desc => "DESC",
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Where the next page starts: the ordering values of the last row of the
+// previous one, its id last. Clients get it as an opaque URL-safe string.
+#[derive(Clone)]
+pub struct Cursor {
+    values: Row<'static>,
+}
+
+const TAG_NULL: u8 = 0;
+const TAG_INT64: u8 = 1;
+const TAG_FLOAT64: u8 = 2;
+const TAG_FALSE: u8 = 3;
+const TAG_TRUE: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_BYTES: u8 = 6;
+
+impl Cursor {
+    pub(crate) fn new(values: Row<'static>) -> Self {
+        Self { values }
+    }
+
+    pub(crate) fn values(&self) -> &RowSlice<'static> {
+        self.values.as_slice()
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::new();
+        for value in self.values.iter() {
+            match value {
+                Value::Null => bytes.push(TAG_NULL),
+                Value::Int64(num) => {
+                    bytes.push(TAG_INT64);
+                    bytes.extend_from_slice(&num.to_le_bytes());
+                }
+                Value::Float64(num) => {
+                    bytes.push(TAG_FLOAT64);
+                    bytes.extend_from_slice(&num.to_le_bytes());
+                }
+                Value::Bool(false) => bytes.push(TAG_FALSE),
+                Value::Bool(true) => bytes.push(TAG_TRUE),
+                Value::String(str) => {
+                    bytes.push(TAG_STRING);
+                    push_with_len(&mut bytes, str.as_bytes());
+                }
+                Value::Bytes(blob) => {
+                    bytes.push(TAG_BYTES);
+                    push_with_len(&mut bytes, blob);
+                }
+            }
+        }
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(str: &str) -> Result<Cursor> {
+        let bytes = base64::decode_config(str, base64::URL_SAFE_NO_PAD)
+            .map_err(|err| Error::InvalidCursor(err.to_string()))?;
+        let mut reader = Reader(bytes.as_slice());
+        let mut values = Vec::new();
+        while let Some(tag) = reader.take(1) {
+            let value = match tag[0] {
+                TAG_NULL => Value::Null,
+                TAG_INT64 => i64::from_le_bytes(reader.take_array()?).into(),
+                TAG_FLOAT64 => f64::from_le_bytes(reader.take_array()?).into(),
+                TAG_FALSE => false.into(),
+                TAG_TRUE => true.into(),
+                TAG_STRING => String::from_utf8(reader.take_with_len()?.to_vec())
+                    .map_err(|_| Error::InvalidCursor("invalid UTF-8 in a string".to_owned()))?
+                    .into(),
+                TAG_BYTES => reader.take_with_len()?.to_vec().into(),
+                tag => return Err(Error::InvalidCursor(format!("unknown value tag {}", tag))),
+            };
+            values.push(value);
+        }
+        Ok(Cursor { values })
+    }
+}
+
+fn push_with_len(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        match self.take(N) {
+            Some(bytes) => Ok(bytes.try_into().unwrap()),
+            None => Err(Error::InvalidCursor("truncated value".to_owned())),
+        }
+    }
+
+    fn take_with_len(&mut self) -> Result<&'a [u8]> {
+        let len = u32::from_le_bytes(self.take_array()?) as usize;
+        self.take(len)
+            .ok_or_else(|| Error::InvalidCursor("truncated value".to_owned()))
+    }
+}
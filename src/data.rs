@@ -1,7 +1,8 @@
 #![forbid(unsafe_code)]
 
+use crate::error::ParseError;
 use rusqlite::{types::ToSqlOutput, ToSql};
-use std::{borrow::Cow, fmt};
+use std::{borrow::Cow, fmt, time::Duration};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -21,8 +22,78 @@ impl ObjectId {
     pub fn into_i64(&self) -> i64 {
         self.0
     }
+
+    // The UUIDv7 with the id as its millisecond timestamp and zero random
+    // bits, so that the UUIDs sort like the ids. None unless the id fits the
+    // 48-bit timestamp, i.e. is in 0..2^48.
+    #[cfg(feature = "uuid")]
+    pub fn try_into_uuid(&self) -> Option<uuid::Uuid> {
+        if !(0..1 << 48).contains(&self.0) {
+            return None;
+        }
+        let mut bytes = [0; 16];
+        bytes[..6].copy_from_slice(&self.0.to_be_bytes()[2..]);
+        bytes[6] = 0x70;
+        bytes[8] = 0x80;
+        Some(uuid::Uuid::from_bytes(bytes))
+    }
+
+    // The millisecond timestamp of a UUIDv7. The 74 random bits are dropped,
+    // so UUIDs of the same millisecond give the same id; the inverse of
+    // try_into_uuid only.
+    #[cfg(feature = "uuid")]
+    pub fn from_uuid(uuid: uuid::Uuid) -> ObjectId {
+        let mut bytes = [0; 8];
+        bytes[2..].copy_from_slice(&uuid.as_bytes()[..6]);
+        ObjectId(i64::from_be_bytes(bytes))
+    }
+
+    // URL-safe representation with the 0-9A-Za-z alphabet. SQLite never
+    // assigns negative ids; one set by hand is written with a leading '-',
+    // which from_base62 rejects, so it doesn't round-trip.
+    pub fn to_base62(&self) -> String {
+        let mut n = self.0.unsigned_abs();
+        let mut digits = Vec::new();
+        loop {
+            digits.push(BASE62_ALPHABET[(n % 62) as usize]);
+            n /= 62;
+            if n == 0 {
+                break;
+            }
+        }
+        if self.0 < 0 {
+            digits.push(b'-');
+        }
+        digits.iter().rev().map(|&digit| digit as char).collect()
+    }
+
+    pub fn from_base62(str: &str) -> Result<ObjectId, ParseError> {
+        if str.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        if str.starts_with('-') {
+            return Err(ParseError::Negative);
+        }
+        let mut n: i64 = 0;
+        for c in str.chars() {
+            let digit = match c {
+                '0'..='9' => c as i64 - '0' as i64,
+                'A'..='Z' => c as i64 - 'A' as i64 + 10,
+                'a'..='z' => c as i64 - 'a' as i64 + 36,
+                _ => return Err(ParseError::InvalidCharacter(c)),
+            };
+            n = n
+                .checked_mul(62)
+                .and_then(|n| n.checked_add(digit))
+                .ok_or(ParseError::Overflow)?;
+        }
+        Ok(ObjectId(n))
+    }
 }
 
+const BASE62_ALPHABET: &[u8; 62] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
 impl From<i64> for ObjectId {
     fn from(n: i64) -> Self {
         ObjectId::new(n)
@@ -36,13 +107,27 @@ impl ToSql for ObjectId {
 }
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DataType {
     String,
     Bytes,
     Int64,
     Float64,
     Bool,
+    Custom(&'static CustomType),
+}
+
+impl fmt::Debug for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataType::String => write!(f, "String"),
+            DataType::Bytes => write!(f, "Bytes"),
+            DataType::Int64 => write!(f, "Int64"),
+            DataType::Float64 => write!(f, "Float64"),
+            DataType::Bool => write!(f, "Bool"),
+            DataType::Custom(custom) => write!(f, "{}", custom.name),
+        }
+    }
 }
 
 impl From<DataType> for &'static str {
@@ -53,6 +138,72 @@ impl From<DataType> for &'static str {
             DataType::Int64 => "BIGINT",
             DataType::Float64 => "REAL",
             DataType::Bool => "TINYINT",
+            DataType::Custom(custom) => custom.sql_type,
+        }
+    }
+}
+
+// A column kind outside of the built-in ones (a decimal, a timestamp, ...),
+// stored as a built-in wire type. The Rust type converts into and from a Value
+// of the wire type, like the built-in ones do, and the field is marked with
+// #[yorm(with = "path::TO_THE_STATIC")].
+#[derive(Debug, PartialEq, Eq)]
+pub struct CustomType {
+    // Shown in error messages instead of the wire type.
+    pub name: &'static str,
+    // Declared type of the column; SQLite derives the column affinity from it,
+    // so it should agree with the wire type (e.g. contain "TEXT" for String).
+    pub sql_type: &'static str,
+    pub wire_type: DataType,
+}
+
+// std::time::Duration, stored as its whole nanoseconds, which i64 holds for
+// up to about 292 years.
+pub static DURATION: CustomType = CustomType {
+    name: "Duration",
+    sql_type: "BIGINT",
+    wire_type: DataType::Int64,
+};
+
+impl DataType {
+    // Type names allowed in STRICT tables, which reject BIGINT and TINYINT.
+    pub fn strict_type_name(self) -> &'static str {
+        match self.wire_type() {
+            DataType::String => "TEXT",
+            DataType::Bytes => "BLOB",
+            DataType::Int64 | DataType::Bool => "INT",
+            DataType::Float64 => "REAL",
+            DataType::Custom(_) => unreachable!(),
+        }
+    }
+
+    // The built-in type values of this type are stored as.
+    pub fn wire_type(self) -> DataType {
+        match self {
+            DataType::Custom(custom) => custom.wire_type.wire_type(),
+            data_type => data_type,
+        }
+    }
+}
+
+impl DataType {
+    // The type of the values of a column declared as declared_type, following
+    // SQLite's column affinity rules, except for TINYINT which yorm declares
+    // bools as. NUMERIC affinity (DECIMAL, DATE, ...) holds integers and
+    // reals, so it's Float64 like REAL.
+    pub fn from_declared_type(declared_type: &str) -> DataType {
+        let declared_type = declared_type.to_ascii_uppercase();
+        let has = |affix: &str| declared_type.contains(affix);
+        if declared_type == "TINYINT" {
+            DataType::Bool
+        } else if has("INT") {
+            DataType::Int64
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            DataType::String
+        } else if has("BLOB") || declared_type.is_empty() {
+            DataType::Bytes
+        } else {
+            DataType::Float64
         }
     }
 }
@@ -65,6 +216,9 @@ impl From<&str> for DataType {
             "i64" => DataType::Int64,
             "f64" => DataType::Float64,
             "bool" => DataType::Bool,
+            "Duration" | "time :: Duration" | "std :: time :: Duration" => {
+                DataType::Custom(&DURATION)
+            }
             t => panic!("Not supported type {}", t),
         }
     }
@@ -84,12 +238,85 @@ impl From<&str> for DataType {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, PartialEq)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
     Bytes(Cow<'a, [u8]>),
     Int64(i64),
     Float64(f64),
     Bool(bool),
+    Null,
+}
+
+impl<'a> Value<'a> {
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::String(_) => Some(DataType::String),
+            Value::Bytes(_) => Some(DataType::Bytes),
+            Value::Int64(_) => Some(DataType::Int64),
+            Value::Float64(_) => Some(DataType::Float64),
+            Value::Bool(_) => Some(DataType::Bool),
+            Value::Null => None,
+        }
+    }
+
+    // Rough in-memory size: the enum itself plus the string or blob payload.
+    pub fn approximate_byte_size(&self) -> usize {
+        let payload = match self {
+            Value::String(cow) => cow.len(),
+            Value::Bytes(cow) => cow.len(),
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + payload
+    }
+}
+
+// SQL-literal-like rendering for logs. The formatter precision, if any, caps
+// the number of characters of a string or bytes of a blob which are printed.
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(cow) => {
+                let shown = match f.precision() {
+                    Some(max_len) => cow
+                        .char_indices()
+                        .nth(max_len)
+                        .map_or(cow.len(), |(i, _)| i),
+                    None => cow.len(),
+                };
+                f.write_str("'")?;
+                for (i, part) in cow[..shown].split('\'').enumerate() {
+                    if i > 0 {
+                        f.write_str("''")?;
+                    }
+                    f.write_str(part)?;
+                }
+                f.write_str("'")?;
+                if shown < cow.len() {
+                    write!(f, "... ({} chars)", cow.chars().count())?;
+                }
+                Ok(())
+            }
+            Value::Bytes(cow) => {
+                let shown = f
+                    .precision()
+                    .map_or(cow.len(), |max_len| max_len.min(cow.len()));
+                f.write_str("X'")?;
+                for byte in &cow[..shown] {
+                    write!(f, "{:02X}", byte)?;
+                }
+                f.write_str("'")?;
+                if shown < cow.len() {
+                    write!(f, "... ({} bytes)", cow.len())?;
+                }
+                Ok(())
+            }
+            Value::Int64(n) => write!(f, "{}", n),
+            Value::Float64(n) => write!(f, "{}", n),
+            Value::Bool(b) => f.write_str(if *b { "TRUE" } else { "FALSE" }),
+            Value::Null => f.write_str("NULL"),
+        }
+    }
 }
 
 impl<'a> From<String> for Value<'a> {
@@ -122,6 +349,14 @@ impl<'a> From<f64> for Value<'a> {
     }
 }
 
+// A Duration too long for i64 nanoseconds becomes Null, which fails the
+// insert or the update with ValueOutOfRange rather than storing a wrong value.
+impl<'a> From<Duration> for Value<'a> {
+    fn from(duration: Duration) -> Self {
+        i64::try_from(duration.as_nanos()).map_or(Value::Null, Value::Int64)
+    }
+}
+
 impl<'a> ToSql for Value<'a> {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         match self {
@@ -130,6 +365,21 @@ impl<'a> ToSql for Value<'a> {
             Value::Int64(n) => n.to_sql(),
             Value::Float64(n) => n.to_sql(),
             Value::Bool(b) => b.to_sql(),
+            Value::Null => rusqlite::types::Null.to_sql(),
+        }
+    }
+}
+
+// Booleans become integers, the same as they are stored.
+impl<'a> From<Value<'a>> for rusqlite::types::Value {
+    fn from(value: Value<'a>) -> Self {
+        match value {
+            Value::String(cow) => rusqlite::types::Value::Text(cow.into_owned()),
+            Value::Bytes(cow) => rusqlite::types::Value::Blob(cow.into_owned()),
+            Value::Int64(num) => rusqlite::types::Value::Integer(num),
+            Value::Float64(num) => rusqlite::types::Value::Real(num),
+            Value::Bool(flag) => rusqlite::types::Value::Integer(flag as i64),
+            Value::Null => rusqlite::types::Value::Null,
         }
     }
 }
@@ -178,3 +428,105 @@ impl<'a> From<Value<'a>> for f64 {
         }
     }
 }
+
+impl<'a> From<Value<'a>> for Duration {
+    fn from(value: Value<'a>) -> Self {
+        match value {
+            Value::Int64(nanos) if nanos >= 0 => Duration::from_nanos(nanos as u64),
+            _ => panic!("Wrong type extracted from Value"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Fallible extraction of a value whose type isn't known up front, such as a
+// column of a raw query result. Unlike the From<Value> conversions, a value
+// of another type is None rather than a panic.
+pub trait FromValue: Sized {
+    const TYPE_NAME: &'static str;
+
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl FromValue for String {
+    const TYPE_NAME: &'static str = "String";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::String(cow) => Some(cow.into_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    const TYPE_NAME: &'static str = "Bytes";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bytes(cow) => Some(cow.into_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i64 {
+    const TYPE_NAME: &'static str = "Int64";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int64(num) => Some(num),
+            _ => None,
+        }
+    }
+}
+
+// SQLite returns integers for REAL expressions with integral results.
+impl FromValue for f64 {
+    const TYPE_NAME: &'static str = "Float64";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Float64(num) => Some(num),
+            Value::Int64(num) => Some(num as f64),
+            _ => None,
+        }
+    }
+}
+
+// SQLite has no boolean type, booleans are stored as 0 and 1.
+impl FromValue for bool {
+    const TYPE_NAME: &'static str = "Bool";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(flag) => Some(flag),
+            Value::Int64(0) => Some(false),
+            Value::Int64(1) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Duration {
+    const TYPE_NAME: &'static str = "Duration";
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int64(nanos) if nanos >= 0 => Some(Duration::from_nanos(nanos as u64)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    const TYPE_NAME: &'static str = T::TYPE_NAME;
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
@@ -12,16 +12,80 @@ pub enum Error {
     #[error(transparent)]
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
+    ValueOutOfRange(Box<ValueOutOfRangeError>),
+    #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    TableNotFound(Box<TableNotFoundError>),
+    #[error(transparent)]
+    SchemaMismatch(Box<SchemaMismatchError>),
+    #[error(transparent)]
+    EnsureTable(Box<EnsureTableError>),
+    #[error(transparent)]
+    IndexConflict(Box<IndexConflictError>),
+    #[error(transparent)]
+    QueryColumn(Box<QueryColumnError>),
+    #[error(transparent)]
+    RowWidth(Box<RowWidthError>),
+    #[error(transparent)]
+    IdentifierTooLong(Box<IdentifierTooLongError>),
+    #[error(transparent)]
+    TooManyColumns(Box<TooManyColumnsError>),
+    #[error(transparent)]
+    IncompatibleSqliteVersion(Box<IncompatibleSqliteVersionError>),
+    #[error(transparent)]
+    ResultTooLarge(Box<ResultTooLargeError>),
+    #[error(transparent)]
+    Constraint(Box<ConstraintError>),
+    #[error(transparent)]
+    NestedConflict(Box<NestedConflictError>),
+    #[error(transparent)]
+    Csv(Box<CsvError>),
+    #[error("security context has no value for ':{0}'")]
+    MissingContextValue(String),
+    #[error("'{0}' is not an integer column of the table")]
+    InvalidParentColumn(String),
+    #[error("'{0}' is not a column of the table")]
+    UnknownColumn(String),
+    #[error("'{0}' is not a numeric column of the table")]
+    NonNumericColumn(String),
+    #[error("no open savepoint '{0}'")]
+    UnknownSavepoint(String),
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
+    #[error("cannot parse CREATE TABLE: {0}")]
+    InvalidDdl(String),
+    #[error("invalid page size {0}: expected a power of two from 512 to 65536")]
+    InvalidPageSize(u32),
+    #[error("invalid JSON: {0}")]
+    Json(String),
+    #[error("type {0} has no columns but isn't declared fieldless")]
+    EmptySchema(&'static str),
+    #[error("the statement returned no rows")]
+    NoRows,
     #[error("database is locked")]
     LockConflict,
+    #[error("get_for_update needs a transaction begun with new_immediate_transaction")]
+    ImmediateRequired,
+    #[error("database is read-only")]
+    ReadOnly,
     #[error("storage error: {0}")]
     Storage(#[source] Box<dyn std::error::Error>),
 }
 
 impl From<rusqlite::Error> for Error {
     fn from(err: rusqlite::Error) -> Self {
-        Error::Storage(Box::new(err))
+        match err {
+            // Any write to a database opened read-only, e.g. raw DML.
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::ReadOnly,
+                    ..
+                },
+                _,
+            ) => Error::ReadOnly,
+            err => Error::Storage(Box::new(err)),
+        }
     }
 }
 
@@ -36,10 +100,23 @@ pub struct NotFoundError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Error, Debug)]
+#[error(
+    "nested transaction '{savepoint}' wrote {type_name} {object_id}, \
+    which its parent changed after it began"
+)]
+pub struct NestedConflictError {
+    pub savepoint: String,
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Error, Debug)]
 #[error(
     "invalid type for {type_name}::{attr_name}: expected equivalent of {expected_type:?}, \
-    got {got_type} (table: {table_name}, column: {column_name})"
+    got {got_type} value {value} (table: {table_name}, column: {column_name})"
 )]
 pub struct UnexpectedTypeError {
     pub type_name: &'static str,
@@ -48,6 +125,23 @@ pub struct UnexpectedTypeError {
     pub column_name: &'static str,
     pub expected_type: DataType,
     pub got_type: String,
+    // The offending value, see storage::render_offending_value.
+    pub value: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error(
+    "value of {type_name}::{attr_name} doesn't fit a {column_type:?} column \
+    (table: {table_name}, column: {column_name})"
+)]
+pub struct ValueOutOfRangeError {
+    pub type_name: &'static str,
+    pub attr_name: &'static str,
+    pub table_name: &'static str,
+    pub column_name: &'static str,
+    pub column_type: DataType,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -66,4 +160,146 @@ pub struct MissingColumnError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Error, Debug)]
+#[error("table is not found: '{table_name}'")]
+pub struct TableNotFoundError {
+    pub table_name: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("schema mismatch in table '{table_name}': {}", .mismatches.join("; "))]
+pub struct SchemaMismatchError {
+    pub table_name: String,
+    pub mismatches: Vec<String>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("cannot ensure table '{table_name}': {source}")]
+pub struct EnsureTableError {
+    pub table_name: &'static str,
+    #[source]
+    pub source: Error,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// existing is the definition as stored by SQLite.
+#[derive(Error, Debug)]
+#[error("index '{index_name}' exists with another definition: {existing}")]
+pub struct IndexConflictError {
+    pub index_name: String,
+    pub table_name: &'static str,
+    pub existing: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("query result column {column}: expected {expected}, got {got}")]
+pub struct QueryColumnError {
+    pub column: usize,
+    pub expected: &'static str,
+    pub got: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("expected {expected} columns in a row of '{table_name}', got {got}")]
+pub struct RowWidthError {
+    pub table_name: &'static str,
+    pub expected: usize,
+    pub got: usize,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("identifier '{identifier}' is longer than {limit} characters")]
+pub struct IdentifierTooLongError {
+    pub identifier: String,
+    pub limit: usize,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("table '{table_name}' has {columns} columns, more than {limit}")]
+pub struct TooManyColumnsError {
+    pub table_name: &'static str,
+    pub columns: usize,
+    pub limit: usize,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error(
+    "table '{table_name}' needs SQLite {}.{}.{} or newer, found {}.{}.{}",
+    required.0, required.1, required.2, found.0, found.1, found.2
+)]
+pub struct IncompatibleSqliteVersionError {
+    pub table_name: String,
+    pub required: (u32, u32, u32),
+    pub found: (u32, u32, u32),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Of an object query, table is set; of a raw one, query is, with its SQL.
+#[derive(Error, Debug)]
+#[error(
+    "more than {limit} rows in the result of {}: read them with Transaction::page \
+    or stream them with Transaction::for_each instead",
+    match (.table, .query) {
+        (Some(table), _) => format!("table '{}'", table),
+        (None, query) => format!("query '{}'", query.as_deref().unwrap_or_default()),
+    }
+)]
+pub struct ResultTooLargeError {
+    pub table: Option<String>,
+    pub query: Option<String>,
+    pub limit: u64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// check is the expression as reported by SQLite, which for the checks of
+// the schema is the expression they were declared with.
+#[derive(Error, Debug)]
+#[error("check constraint failed (table: {table_name}): {check}")]
+pub struct ConstraintError {
+    pub table_name: &'static str,
+    pub check: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("invalid CSV at line {line}: {message}")]
+pub struct CsvError {
+    pub line: u64,
+    pub message: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("empty object id")]
+    Empty,
+    #[error("invalid character '{0}' in object id")]
+    InvalidCharacter(char),
+    #[error("object id is negative")]
+    Negative,
+    #[error("object id is too large")]
+    Overflow,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,94 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    data::Value,
+    error::{CsvError, Error, Result},
+    object::Schema,
+    storage::StorageTransaction,
+};
+use std::io::Write;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+pub struct CsvExportOptions {
+    pub delimiter: u8,
+    // A header of the field names, which import_csv matches columns by.
+    pub include_header: bool,
+    // The id as the first column, named "id".
+    pub include_id_column: bool,
+}
+
+impl Default for CsvExportOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            include_header: true,
+            include_id_column: false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn export_csv<W: Write>(
+    storage: &dyn StorageTransaction,
+    schema: &Schema,
+    predicate: &str,
+    params: &[Value<'static>],
+    writer: W,
+    opts: CsvExportOptions,
+) -> Result<u64> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(opts.delimiter)
+        .from_writer(writer);
+    if opts.include_header {
+        let mut header = Vec::new();
+        if opts.include_id_column {
+            header.push("id");
+        }
+        header.extend((0..schema.columns_count()).map(|i| schema.get_nth_field_name(i)));
+        writer
+            .write_record(&header)
+            .map_err(|err| csv_error(1, err))?;
+    }
+
+    let mut written = 0;
+    storage.select_each(schema, predicate, params, "id", None, &mut |id, row| {
+        let mut record = Vec::new();
+        if opts.include_id_column {
+            record.push(id.to_string().into_bytes());
+        }
+        record.extend(row.into_iter().map(value_to_field));
+        written += 1;
+        writer
+            .write_record(&record)
+            .map_err(|err| csv_error(written + opts.include_header as u64, err))
+    })?;
+    writer.flush().map_err(|err| {
+        Error::Csv(Box::new(CsvError {
+            line: written + opts.include_header as u64,
+            message: err.to_string(),
+        }))
+    })?;
+    Ok(written)
+}
+
+// Fields import_csv reads back into the same values; NULL is an empty field.
+fn value_to_field(value: Value) -> Vec<u8> {
+    match value {
+        Value::String(str) => str.into_owned().into_bytes(),
+        Value::Bytes(blob) => blob.into_owned(),
+        Value::Int64(num) => num.to_string().into_bytes(),
+        Value::Float64(num) => num.to_string().into_bytes(),
+        Value::Bool(bool) => bool.to_string().into_bytes(),
+        Value::Null => Vec::new(),
+    }
+}
+
+fn csv_error(line: u64, err: csv::Error) -> Error {
+    Error::Csv(Box::new(CsvError {
+        line,
+        message: err.to_string(),
+    }))
+}
@@ -0,0 +1,253 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    data::Value,
+    error::{Error, Result},
+    object::Schema,
+    storage::{Row, RowSlice},
+};
+use std::ops::Not;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Parameters a statement may have in any SQLite: SQLITE_MAX_VARIABLE_NUMBER
+// before 3.32 raised its default.
+pub const MAX_PARAMS: usize = 999;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A WHERE clause over the columns of one table, rendered to SQL with bound
+// parameters. Columns are the column names, not the field names.
+#[derive(Clone)]
+pub enum Filter {
+    Compare(String, &'static str, Value<'static>),
+    In(String, Vec<Value<'static>>),
+    IsNull(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), "=", value.into())
+    }
+
+    pub fn ne<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), "<>", value.into())
+    }
+
+    pub fn lt<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), "<", value.into())
+    }
+
+    pub fn le<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), "<=", value.into())
+    }
+
+    pub fn gt<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), ">", value.into())
+    }
+
+    pub fn ge<V: Into<Value<'static>>>(column: &str, value: V) -> Self {
+        Filter::Compare(column.to_owned(), ">=", value.into())
+    }
+
+    pub fn is_in<V: Into<Value<'static>>, I: IntoIterator<Item = V>>(
+        column: &str,
+        values: I,
+    ) -> Self {
+        Filter::In(
+            column.to_owned(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    pub fn is_null(column: &str) -> Self {
+        Filter::IsNull(column.to_owned())
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    pub fn params_count(&self) -> usize {
+        match self {
+            Filter::Compare(..) => 1,
+            Filter::In(_, values) => values.len(),
+            Filter::IsNull(_) => 0,
+            Filter::And(filters) | Filter::Or(filters) => {
+                filters.iter().map(Filter::params_count).sum()
+            }
+            Filter::Not(filter) => filter.params_count(),
+        }
+    }
+
+    // Fails with UnknownColumn on the first column which isn't one of schema
+    // (or its id), since a misspelt column would otherwise still be valid
+    // SQL: SQLite reads an unknown double-quoted name as a string literal.
+    pub fn check_columns(&self, schema: &Schema) -> Result<()> {
+        match self {
+            Filter::Compare(column, ..) | Filter::In(column, _) | Filter::IsNull(column) => {
+                let known = column == "id"
+                    || (0..schema.columns_count()).any(|i| schema.get_nth_column_name(i) == column);
+                if !known {
+                    return Err(Error::UnknownColumn(column.clone()));
+                }
+                Ok(())
+            }
+            Filter::And(filters) | Filter::Or(filters) => filters
+                .iter()
+                .try_for_each(|filter| filter.check_columns(schema)),
+            Filter::Not(filter) => filter.check_columns(schema),
+        }
+    }
+
+    // The SQL of the clause (without WHERE), and its parameters in order.
+    pub fn to_sql(&self) -> (String, Row<'static>) {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+        self.render(&mut sql, &mut params);
+        (sql, params)
+    }
+
+    // Splits the clause into several, each with at most max_params parameters,
+    // which together match the same rows. Only the largest IN list which the
+    // whole filter depends on (the filter itself or a term of a top-level AND)
+    // is split; if that isn't enough, the result is a single oversized clause.
+    pub fn to_sql_chunks(&self, max_params: usize) -> Vec<(String, Row<'static>)> {
+        let total = self.params_count();
+        if total <= max_params {
+            return vec![self.to_sql()];
+        }
+        let terms = match self {
+            Filter::And(filters) => filters.as_slice(),
+            filter => std::slice::from_ref(filter),
+        };
+        let largest = terms
+            .iter()
+            .enumerate()
+            .filter_map(|(i, term)| match term {
+                Filter::In(column, values) => Some((i, column, values)),
+                _ => None,
+            })
+            .max_by_key(|(_, _, values)| values.len());
+        let (i, column, values) = match largest {
+            Some(largest) => largest,
+            None => return vec![self.to_sql()],
+        };
+        let others = total - values.len();
+        if others >= max_params {
+            return vec![self.to_sql()];
+        }
+        values
+            .chunks(max_params - others)
+            .map(|chunk| {
+                let mut chunk_terms = terms.to_vec();
+                chunk_terms[i] = Filter::In(column.clone(), chunk.to_vec());
+                match self {
+                    Filter::And(_) => Filter::And(chunk_terms),
+                    _ => chunk_terms.pop().unwrap(),
+                }
+                .to_sql()
+            })
+            .collect()
+    }
+
+    fn render(&self, sql: &mut String, params: &mut Row<'static>) {
+        match self {
+            Filter::Compare(column, op, value) => {
+                push_column(sql, column);
+                sql.push(' ');
+                sql.push_str(op);
+                sql.push_str(" ?");
+                params.push(value.clone());
+            }
+            Filter::In(_, values) if values.is_empty() => sql.push('0'),
+            Filter::In(column, values) => {
+                push_column(sql, column);
+                sql.push_str(" IN (");
+                push_params(sql, params, values);
+                sql.push(')');
+            }
+            Filter::IsNull(column) => {
+                push_column(sql, column);
+                sql.push_str(" IS NULL");
+            }
+            Filter::And(filters) => render_all(sql, params, filters, " AND ", "1"),
+            Filter::Or(filters) => render_all(sql, params, filters, " OR ", "0"),
+            Filter::Not(filter) => {
+                sql.push_str("NOT (");
+                filter.render(sql, params);
+                sql.push(')');
+            }
+        }
+    }
+}
+
+impl From<&Filter> for Filter {
+    fn from(filter: &Filter) -> Self {
+        filter.clone()
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+fn render_all(
+    sql: &mut String,
+    params: &mut Row<'static>,
+    filters: &[Filter],
+    separator: &str,
+    empty: &str,
+) {
+    if filters.is_empty() {
+        sql.push_str(empty);
+        return;
+    }
+    sql.push('(');
+    for (i, filter) in filters.iter().enumerate() {
+        if i > 0 {
+            sql.push_str(separator);
+        }
+        filter.render(sql, params);
+    }
+    sql.push(')');
+}
+
+fn push_params(sql: &mut String, params: &mut Row<'static>, values: &RowSlice<'static>) {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            sql.push_str(", ");
+        }
+        sql.push('?');
+        params.push(value.clone());
+    }
+}
+
+fn push_column(sql: &mut String, column: &str) {
+    sql.push('"');
+    sql.push_str(&column.replace('"', "\"\""));
+    sql.push('"');
+}
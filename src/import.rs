@@ -0,0 +1,268 @@
+#![forbid(unsafe_code)]
+
+#[cfg(feature = "csv-import")]
+use crate::error::CsvError;
+use crate::{
+    data::{DataType, ObjectId, Value},
+    error::{Error, Result},
+    object::Schema,
+    storage::{Row, StorageTransaction},
+};
+#[cfg(feature = "csv-import")]
+use std::io::Read;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(feature = "csv-import")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    // Skip the invalid rows.
+    Skip,
+    // Fail on the first invalid row; the rows before it stay inserted.
+    Stop,
+    // Skip the invalid rows and return their errors.
+    Collect,
+}
+
+#[cfg(feature = "csv-import")]
+#[derive(Clone, Debug)]
+pub struct CsvImportOptions {
+    pub delimiter: u8,
+    // With a header, columns are matched to the fields by name (field or
+    // column name); without one, they must be in the order of the fields.
+    pub has_header: bool,
+    // Data rows to skip before importing, after the header.
+    pub skip_rows: usize,
+    pub on_error: OnError,
+}
+
+#[cfg(feature = "csv-import")]
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_header: true,
+            skip_rows: 0,
+            on_error: OnError::Stop,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportResult {
+    pub imported: u64,
+    // Invalid rows, with OnError::Skip and OnError::Collect.
+    pub skipped: u64,
+    // Line numbers of the invalid rows and why, with OnError::Collect.
+    pub errors: Vec<(usize, Error)>,
+    // Of the imported rows, in order.
+    pub ids: Vec<ObjectId>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(feature = "csv-import")]
+pub(crate) fn import_csv<R: Read>(
+    storage: &dyn StorageTransaction,
+    schema: &Schema,
+    reader: R,
+    opts: CsvImportOptions,
+) -> Result<ImportResult> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(opts.delimiter)
+        .has_headers(opts.has_header)
+        .from_reader(reader);
+    let positions = match opts.has_header {
+        true => {
+            let headers = reader.headers().map_err(|err| csv_error(1, err))?;
+            header_positions(schema, headers)?
+        }
+        false => (0..schema.columns_count()).collect(),
+    };
+
+    let mut result = ImportResult {
+        imported: 0,
+        skipped: 0,
+        errors: Vec::new(),
+        ids: Vec::new(),
+    };
+    for record in reader.records().skip(opts.skip_rows) {
+        let (line, row) = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line());
+                (line, parse_record(schema, &positions, &record, line))
+            }
+            Err(err) => {
+                let line = err.position().map_or(0, |position| position.line());
+                (line, Err(csv_error(line, err)))
+            }
+        };
+        let inserted = row.and_then(|row| storage.insert_row(schema, row.as_slice()));
+        match (inserted, opts.on_error) {
+            (Ok(id), _) => {
+                result.imported += 1;
+                result.ids.push(id);
+            }
+            (Err(err), OnError::Stop) => return Err(err),
+            (Err(_), OnError::Skip) => result.skipped += 1,
+            (Err(err), OnError::Collect) => {
+                result.skipped += 1;
+                result.errors.push((line as usize, err));
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(feature = "csv-import")]
+fn header_positions(schema: &Schema, headers: &csv::StringRecord) -> Result<Vec<usize>> {
+    (0..schema.columns_count())
+        .map(|i| {
+            let field_name = schema.get_nth_field_name(i);
+            let column_name = schema.get_nth_column_name(i);
+            headers
+                .iter()
+                .position(|header| header == field_name || header == column_name)
+                .ok_or_else(|| {
+                    Error::Csv(Box::new(CsvError {
+                        line: 1,
+                        message: format!("no column for field '{}'", field_name),
+                    }))
+                })
+        })
+        .collect()
+}
+
+#[cfg(feature = "csv-import")]
+fn parse_record(
+    schema: &Schema,
+    positions: &[usize],
+    record: &csv::StringRecord,
+    line: u64,
+) -> Result<Row<'static>> {
+    let mut row = Vec::new();
+    for (i, &position) in positions.iter().enumerate() {
+        let raw = record.get(position).unwrap_or("");
+        let data_type = schema.get_types()[i];
+        let value = if schema.is_nth_column_nullable(i) && raw.is_empty() {
+            Some(Value::Null)
+        } else {
+            parse_value(data_type, raw)
+        };
+        match value {
+            Some(value) => row.push(value),
+            None => {
+                return Err(Error::Csv(Box::new(CsvError {
+                    line,
+                    message: format!(
+                        "invalid {:?} value '{}' for field '{}'",
+                        data_type,
+                        raw,
+                        schema.get_nth_field_name(i)
+                    ),
+                })))
+            }
+        }
+    }
+    Ok(row)
+}
+
+#[cfg(feature = "csv-import")]
+fn parse_value(data_type: DataType, raw: &str) -> Option<Value<'static>> {
+    match data_type.wire_type() {
+        DataType::String => Some(raw.to_owned().into()),
+        DataType::Bytes => Some(raw.as_bytes().to_vec().into()),
+        DataType::Int64 => raw.trim().parse::<i64>().ok().map(Into::into),
+        DataType::Float64 => raw.trim().parse::<f64>().ok().map(Into::into),
+        DataType::Bool => match raw.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Some(true.into()),
+            "false" | "0" => Some(false.into()),
+            _ => None,
+        },
+        DataType::Custom(_) => unreachable!(),
+    }
+}
+
+#[cfg(feature = "csv-import")]
+fn csv_error(line: u64, err: csv::Error) -> Error {
+    Error::Csv(Box::new(CsvError {
+        line,
+        message: err.to_string(),
+    }))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Stops at the first invalid element; the ones before it stay inserted.
+#[cfg(feature = "serde-json")]
+pub(crate) fn import_json(
+    storage: &dyn StorageTransaction,
+    schema: &Schema,
+    json: &str,
+) -> Result<ImportResult> {
+    let json = serde_json::from_str::<serde_json::Value>(json)
+        .map_err(|err| Error::Json(err.to_string()))?;
+    let elements = json
+        .as_array()
+        .ok_or_else(|| Error::Json("expected an array of objects".to_owned()))?;
+    let mut result = ImportResult {
+        imported: 0,
+        skipped: 0,
+        errors: Vec::new(),
+        ids: Vec::new(),
+    };
+    for (element_index, element) in elements.iter().enumerate() {
+        let object = element
+            .as_object()
+            .ok_or_else(|| Error::Json(format!("element {} is not an object", element_index)))?;
+        let mut row: Row<'static> = Vec::new();
+        for i in 0..schema.columns_count() {
+            let column_name = schema.get_nth_column_name(i);
+            let json_value = object.get(column_name).unwrap_or(&serde_json::Value::Null);
+            let value = match json_value {
+                serde_json::Value::Null if schema.is_nth_column_nullable(i) => Some(Value::Null),
+                json_value => json_to_value(schema.get_types()[i], json_value),
+            };
+            match value {
+                Some(value) => row.push(value),
+                None => {
+                    return Err(Error::Json(format!(
+                        "element {}: invalid {:?} value {} for column '{}'",
+                        element_index,
+                        schema.get_types()[i],
+                        json_value,
+                        column_name
+                    )))
+                }
+            }
+        }
+        result.ids.push(storage.insert_row(schema, row.as_slice())?);
+        result.imported += 1;
+    }
+    Ok(result)
+}
+
+// Bytes are an array of numbers, or a string taken as its UTF-8 bytes.
+#[cfg(feature = "serde-json")]
+fn json_to_value(data_type: DataType, json_value: &serde_json::Value) -> Option<Value<'static>> {
+    match data_type.wire_type() {
+        DataType::String => json_value.as_str().map(|str| str.to_owned().into()),
+        DataType::Bytes => match json_value {
+            serde_json::Value::String(str) => Some(str.as_bytes().to_vec().into()),
+            serde_json::Value::Array(items) => items
+                .iter()
+                .map(|item| {
+                    item.as_u64()
+                        .filter(|&byte| byte <= 255)
+                        .map(|byte| byte as u8)
+                })
+                .collect::<Option<Vec<u8>>>()
+                .map(Into::into),
+            _ => None,
+        },
+        DataType::Int64 => json_value.as_i64().map(Into::into),
+        DataType::Float64 => json_value.as_f64().map(Into::into),
+        DataType::Bool => json_value.as_bool().map(Into::into),
+        DataType::Custom(_) => unreachable!(),
+    }
+}
@@ -1,16 +1,35 @@
 #![forbid(unsafe_code)]
 mod connection;
+mod cursor;
 mod error;
+mod timing;
 mod transaction;
 
 pub mod data;
+#[cfg(feature = "csv-import")]
+pub mod export;
+pub mod filter;
+#[cfg(any(feature = "csv-import", feature = "serde-json"))]
+pub mod import;
 pub mod object;
+pub mod prelude;
 pub mod storage;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 
-pub use connection::Connection;
+pub use connection::{
+    CheckpointMode, CheckpointResult, Clock, Connection, ConnectionType, SecurityContext,
+    SystemClock,
+};
+pub use cursor::{Cursor, Direction};
 pub use data::ObjectId;
-pub use error::{Error, Result};
-pub use object::Object;
-pub use transaction::{ObjectState, Transaction, Tx};
+pub use error::{Error, ParseError, Result};
+pub use filter::Filter;
+pub use object::{row_eq, row_hash, Object, TableNaming};
+pub use timing::{QueryTiming, HISTOGRAM_BOUNDS};
+pub use transaction::{
+    AnyTx, CacheEvent, CacheEventType, ColumnStats, CommitSummary, DynTable, ObjectState,
+    Transaction, Tx, TxWeak,
+};
 
 pub use orm_derive::Object;
@@ -1,7 +1,41 @@
 #![forbid(unsafe_code)]
 
-use crate::{data::DataType, storage::Row};
-use std::any::Any;
+use crate::{
+    connection::ConnectionType,
+    data::{DataType, Value, DURATION},
+    error::{
+        Error, IdentifierTooLongError, IncompatibleSqliteVersionError, MissingColumnError, Result,
+        TooManyColumnsError, UnexpectedTypeError, ValueOutOfRangeError,
+    },
+    filter::MAX_PARAMS,
+    storage::{self, Row, RowSlice},
+};
+use std::{
+    any::Any,
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    fmt::Write,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Well within what SQLite accepts, so that an oversized schema fails with
+// an error naming what's too big rather than somewhere in SQLite. The
+// derive checks the same limits at compile time.
+pub const MAX_IDENTIFIER_LEN: usize = 128;
+// An UPDATE binds every column and the id.
+pub const MAX_COLUMNS: usize = MAX_PARAMS - 1;
+
+pub(crate) fn check_identifier(identifier: &str) -> Result<()> {
+    if identifier.chars().count() > MAX_IDENTIFIER_LEN {
+        return Err(Error::IdentifierTooLong(Box::new(IdentifierTooLongError {
+            identifier: identifier.to_owned(),
+            limit: MAX_IDENTIFIER_LEN,
+        })));
+    }
+    Ok(())
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -9,24 +43,123 @@ pub trait Object: Any + Sized {
     fn as_row(&self) -> Row;
     fn from_row(row: Row) -> Self;
 
+    // Checks the row against the schema before handing it to from_row,
+    // so that a malformed row is reported with the field and the column.
+    fn try_from_row(schema: &Schema, row: Row) -> Result<Self> {
+        schema.check_row(row.as_slice())?;
+        Ok(Self::from_row(row))
+    }
+
     fn table_name() -> &'static str;
     fn type_name() -> &'static str;
 
+    // False if table_name is just the type name, which a TableNaming set on
+    // the connection replaces; a name given by #[table_name] or
+    // #[yorm(table = ...)], or by a hand-written impl, is kept as it is.
+    fn has_explicit_table_name() -> bool {
+        true
+    }
+
     // Field name, Column name, Type
     fn field_names() -> Vec<&'static str>;
     fn column_names() -> Vec<&'static str>;
     fn column_types() -> Vec<DataType>;
 
+    // Columns which may hold NULL, read back as Value::Null.
+    fn column_nullability() -> Vec<bool> {
+        vec![false; Self::column_types().len()]
+    }
+
+    // Columns which are read but never written, e.g. GENERATED ones.
+    fn column_readonly() -> Vec<bool> {
+        vec![false; Self::column_types().len()]
+    }
+
+    // Only fieldless types may have no columns, anything else is a broken impl.
+    fn is_fieldless() -> bool {
+        false
+    }
+
+    fn indexes() -> Vec<Index> {
+        Vec::new()
+    }
+
+    // STRICT tables reject values not matching the column type.
+    fn is_strict() -> bool {
+        false
+    }
+
+    // Tables created by someone else, e.g. a legacy application, which are
+    // read and written but never created or migrated.
+    fn is_existing_table() -> bool {
+        false
+    }
+
+    // Raw SQL expressions emitted verbatim as CHECK constraints of the table.
+    fn checks() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    // Row visibility predicate, with :name placeholders bound from the SecurityContext.
+    fn visible_when() -> Option<&'static str> {
+        None
+    }
+
+    // Int64 column of unix milliseconds after which a row is invisible,
+    // NULL meaning never.
+    fn expires_at() -> Option<&'static str> {
+        None
+    }
+
+    // ReadOnly objects can be read but never created, updated or deleted.
+    fn connection_type() -> ConnectionType {
+        ConnectionType::ReadWrite
+    }
+
     fn describe() -> Schema {
         Schema {
             table_name: Self::table_name(),
             field_names: Self::field_names(),
             column_names: Self::column_names(),
             column_types: Self::column_types(),
+            column_nullability: Self::column_nullability(),
+            column_readonly: Self::column_readonly(),
+            fieldless: Self::is_fieldless(),
+            indexes: Self::indexes(),
+            checks: Self::checks(),
+            strict: Self::is_strict(),
+            existing_table: Self::is_existing_table(),
+            explicit_table_name: Self::has_explicit_table_name(),
+            visible_when: Self::visible_when(),
+            expires_at: Self::expires_at(),
+            connection_type: Self::connection_type(),
             type_name: Self::type_name(),
         }
     }
 
+    // "TypeName { field: value, ... }", available even without Debug. A field
+    // stored under another column name is shown as "field (column)".
+    fn to_debug_string(&self) -> String {
+        let mut result = format!("{} {{", Self::type_name());
+        let field_names = Self::field_names();
+        let column_names = Self::column_names();
+        for (i, value) in self.as_row().iter().enumerate() {
+            result.push_str(if i == 0 { " " } else { ", " });
+            result.push_str(field_names[i]);
+            if column_names[i] != field_names[i] {
+                write!(result, " ({})", column_names[i]).unwrap();
+            }
+            write!(result, ": {}", value).unwrap();
+        }
+        result.push_str(if field_names.is_empty() { "}" } else { " }" });
+        result
+    }
+
+    #[cfg(feature = "serde-json")]
+    fn describe_json() -> serde_json::Value {
+        Self::describe().to_json()
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -39,6 +172,7 @@ pub trait Store: Any {
     fn as_any(&self) -> &dyn std::any::Any;
     fn as_mut_any(&mut self) -> &mut dyn std::any::Any;
     fn as_row(&self) -> Row;
+    fn load_row(&mut self, row: Row);
     fn describe(&self) -> Schema;
 }
 
@@ -53,6 +187,9 @@ impl<T: Object> Store for T {
     fn as_row(&self) -> Row {
         self.as_row()
     }
+    fn load_row(&mut self, row: Row) {
+        *self = Self::from_row(row);
+    }
     fn describe(&self) -> Schema {
         Self::describe()
     }
@@ -60,16 +197,175 @@ impl<T: Object> Store for T {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+// Equality and hashing of objects by their rows, for types without PartialEq
+// or Hash. Floats are compared by their bits: NaN equals itself (if it's the
+// same NaN) and 0.0 differs from -0.0. Values of different types differ even
+// if SQLite would compare them equal, e.g. Int64(1) and Bool(true), and so do
+// a String and Bytes of the same bytes.
+pub fn row_eq<T: Object>(lhs: &T, rhs: &T) -> bool {
+    let (lhs, rhs) = (lhs.as_row(), rhs.as_row());
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs.iter()).all(|(a, b)| value_eq(a, b))
+}
+
+// Consistent with row_eq. The hash is stable within a build, but not meant
+// to be stored: it may change with the Rust version.
+pub fn row_hash<T: Object>(object: &T) -> u64 {
+    hash_row(object.as_row().as_slice())
+}
+
+pub(crate) fn hash_row(row: &RowSlice) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for value in row.iter() {
+        hash_value(value, &mut hasher);
+    }
+    hasher.finish()
+}
+
+fn value_eq(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Float64(a), Value::Float64(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+fn hash_value<H: Hasher>(value: &Value, hasher: &mut H) {
+    std::mem::discriminant(value).hash(hasher);
+    match value {
+        Value::String(cow) => cow.hash(hasher),
+        Value::Bytes(cow) => cow.hash(hasher),
+        Value::Int64(n) => n.hash(hasher),
+        Value::Float64(n) => n.to_bits().hash(hasher),
+        Value::Bool(b) => b.hash(hasher),
+        Value::Null => (),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 // TODO: maybe we could build the whole schema in Object trait
+#[derive(Clone)]
 pub struct Schema {
     table_name: &'static str,
     field_names: Vec<&'static str>,
     column_names: Vec<&'static str>,
     column_types: Vec<DataType>,
+    column_nullability: Vec<bool>,
+    column_readonly: Vec<bool>,
+    fieldless: bool,
+    indexes: Vec<Index>,
+    checks: Vec<&'static str>,
+    strict: bool,
+    existing_table: bool,
+    explicit_table_name: bool,
+    visible_when: Option<&'static str>,
+    expires_at: Option<&'static str>,
+    connection_type: ConnectionType,
     type_name: &'static str,
 }
 
 impl Schema {
+    // Parses the CREATE TABLE statements yorm generates: the id column, columns
+    // of the built-in types and CHECK constraints, optionally STRICT. Custom
+    // types can't be told from their wire type and nullability isn't part of
+    // the DDL, so columns come back built-in and not nullable. The names are
+    // leaked to get 'static ones, which is meant for tools, not hot paths.
+    pub fn new_from_create_sql(sql: &str) -> Result<Schema> {
+        let invalid = |message: &str| Error::InvalidDdl(message.to_owned());
+        let sql = sql.trim().trim_end_matches(';').trim_end();
+        let rest = strip_prefix_ignore_case(sql, "CREATE TABLE")
+            .ok_or_else(|| invalid("expected CREATE TABLE"))?;
+        let rest = strip_prefix_ignore_case(rest.trim_start(), "IF NOT EXISTS").unwrap_or(rest);
+        let open = rest
+            .find('(')
+            .ok_or_else(|| invalid("expected a column list"))?;
+        let close = matching_paren(rest, open).ok_or_else(|| invalid("unbalanced parentheses"))?;
+        let table_name = leak(unquote_identifier(rest[..open].trim()));
+        if table_name.is_empty() {
+            return Err(invalid("expected a table name"));
+        }
+
+        let mut builder = SchemaBuilder::new(table_name);
+        match rest[close + 1..].trim() {
+            "" => (),
+            options if options.eq_ignore_ascii_case("STRICT") => builder = builder.strict(),
+            options => return Err(invalid(&format!("unsupported table options '{}'", options))),
+        }
+        let mut has_id = false;
+        for definition in split_top_level(&rest[open + 1..close]) {
+            let definition = definition.trim();
+            if let Some(check) = strip_prefix_ignore_case(definition, "CHECK") {
+                let check = check.trim();
+                if !check.starts_with('(') || matching_paren(check, 0) != Some(check.len() - 1) {
+                    return Err(invalid(&format!(
+                        "expected CHECK (...), got '{}'",
+                        definition
+                    )));
+                }
+                builder = builder.check(leak(check[1..check.len() - 1].to_owned()));
+                continue;
+            }
+            let mut tokens = definition.split_whitespace();
+            let name = unquote_identifier(tokens.next().unwrap_or(""));
+            let column_type = tokens.next().unwrap_or("").to_ascii_uppercase();
+            let constraints = tokens.collect::<Vec<_>>().join(" ").to_ascii_uppercase();
+            if name == "id" {
+                if column_type != "INTEGER" || !constraints.starts_with("PRIMARY KEY") {
+                    return Err(invalid("id must be the INTEGER PRIMARY KEY"));
+                }
+                has_id = true;
+                continue;
+            }
+            let data_type = match column_type.as_str() {
+                "TEXT" => DataType::String,
+                "BLOB" => DataType::Bytes,
+                "BIGINT" | "INT" | "INTEGER" => DataType::Int64,
+                "REAL" => DataType::Float64,
+                "TINYINT" => DataType::Bool,
+                _ => {
+                    return Err(invalid(&format!(
+                        "unsupported type '{}' of column '{}'",
+                        column_type, name
+                    )))
+                }
+            };
+            if !constraints.is_empty() && constraints != "NOT NULL" {
+                return Err(invalid(&format!(
+                    "unsupported constraint '{}' of column '{}'",
+                    constraints, name
+                )));
+            }
+            builder = builder.column(leak(name), data_type);
+        }
+        if !has_id {
+            return Err(invalid("no id column"));
+        }
+        Ok(builder.build())
+    }
+
+    // The schema of any table from the rows of its PRAGMA table_info: name,
+    // declared type, NOT NULL and whether the column is in the primary key.
+    // An INTEGER PRIMARY KEY named id is the object id; the other columns
+    // map with DataType::from_declared_type and are nullable unless NOT NULL.
+    // Checks, indexes and STRICT aren't part of table_info, so they are left
+    // out. The names are leaked like in new_from_create_sql.
+    pub fn new_from_table_info(
+        table_name: &str,
+        columns: &[(String, String, bool, bool)],
+    ) -> Schema {
+        let mut builder = SchemaBuilder::new(leak(table_name.to_owned()));
+        for (name, declared_type, not_null, primary_key) in columns {
+            if name == "id" && *primary_key && declared_type.eq_ignore_ascii_case("INTEGER") {
+                continue;
+            }
+            let data_type = DataType::from_declared_type(declared_type);
+            builder = match not_null {
+                true => builder.column(leak(name.clone()), data_type),
+                false => builder.nullable_column(leak(name.clone()), data_type),
+            };
+        }
+        builder.build()
+    }
+
     pub fn get_table_name(&self) -> &'static str {
         self.table_name
     }
@@ -86,9 +382,32 @@ impl Schema {
         self.column_names.join(separator)
     }
 
+    pub fn writable_column_list(&self, separator: &str) -> String {
+        self.writable_columns()
+            .map(|i| self.column_names[i])
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    pub fn writable_columns_count(&self) -> usize {
+        self.writable_columns().count()
+    }
+
+    // The values of the writable columns, in the order of writable_column_list.
+    pub fn writable_values<'r, 'v>(
+        &'r self,
+        row: &'r RowSlice<'v>,
+    ) -> impl Iterator<Item = &'r Value<'v>> + 'r {
+        self.writable_columns().map(move |i| &row[i])
+    }
+
+    fn writable_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.columns_count()).filter(move |&i| !self.column_readonly[i])
+    }
+
     pub fn prepare_update_column_list(&self) -> String {
         let mut result = String::new();
-        for col_name in &self.column_names {
+        for col_name in self.writable_columns().map(|i| self.column_names[i]) {
             result.push_str(col_name);
             result.push_str(" = ?,");
         }
@@ -115,6 +434,173 @@ impl Schema {
         self.field_names[n]
     }
 
+    pub fn is_nth_column_nullable(&self, n: usize) -> bool {
+        self.column_nullability[n]
+    }
+
+    pub fn is_nth_column_readonly(&self, n: usize) -> bool {
+        self.column_readonly[n]
+    }
+
+    // Checks the table and column names and the number of columns against
+    // MAX_IDENTIFIER_LEN and MAX_COLUMNS.
+    pub fn check_limits(&self) -> Result<()> {
+        check_identifier(self.table_name)?;
+        if self.columns_count() > MAX_COLUMNS {
+            return Err(Error::TooManyColumns(Box::new(TooManyColumnsError {
+                table_name: self.table_name,
+                columns: self.columns_count(),
+                limit: MAX_COLUMNS,
+            })));
+        }
+        for column_name in self.column_names.iter() {
+            check_identifier(column_name)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_row(&self, row: &RowSlice) -> Result<()> {
+        if row.len() < self.columns_count() {
+            return Err(Error::MissingColumn(Box::new(MissingColumnError {
+                type_name: self.type_name,
+                attr_name: self.field_names[row.len()],
+                table_name: self.table_name,
+                column_name: self.column_names[row.len()],
+            })));
+        }
+        for (i, value) in row.iter().enumerate().take(self.columns_count()) {
+            let expected_type = self.column_types[i];
+            let got_type = match value.data_type() {
+                // Durations are whole nanoseconds, which are never negative.
+                Some(_)
+                    if expected_type == DataType::Custom(&DURATION)
+                        && matches!(value, Value::Int64(nanos) if *nanos < 0) =>
+                {
+                    "negative Int64".to_owned()
+                }
+                Some(got_type) if got_type == expected_type.wire_type() => continue,
+                None if self.column_nullability[i] => continue,
+                Some(got_type) => format!("{:?}", got_type),
+                None => "Null".to_owned(),
+            };
+            return Err(Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                type_name: self.type_name,
+                attr_name: self.field_names[i],
+                table_name: self.table_name,
+                column_name: self.column_names[i],
+                expected_type,
+                got_type,
+                value: storage::render_offending_value(value),
+            })));
+        }
+        Ok(())
+    }
+
+    // check_row for a row of Object::as_row. A Duration too long for i64
+    // nanoseconds converts into NULL, so a NULL Duration is taken for one.
+    pub(crate) fn check_object_row(&self, row: &RowSlice) -> Result<()> {
+        for (i, value) in row.iter().enumerate().take(self.columns_count()) {
+            if self.column_types[i] == DataType::Custom(&DURATION) && *value == Value::Null {
+                return Err(Error::ValueOutOfRange(Box::new(ValueOutOfRangeError {
+                    type_name: self.type_name,
+                    attr_name: self.field_names[i],
+                    table_name: self.table_name,
+                    column_name: self.column_names[i],
+                    column_type: self.column_types[i],
+                })));
+            }
+        }
+        self.check_row(row)
+    }
+
+    pub fn is_fieldless(&self) -> bool {
+        self.fieldless
+    }
+
+    pub fn get_indexes(&self) -> &[Index] {
+        self.indexes.as_slice()
+    }
+
+    pub fn checks(&self) -> &[&'static str] {
+        self.checks.as_slice()
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn is_existing_table(&self) -> bool {
+        self.existing_table
+    }
+
+    // The oldest SQLite which runs what yorm generates for the schema:
+    // CREATE INDEX IF NOT EXISTS needs 3.3.0, partial indexes 3.8.0,
+    // readonly columns, which are meant for GENERATED ALWAYS AS, 3.31.0,
+    // RETURNING, which inserts into existing tables, 3.35.0 and STRICT tables
+    // 3.37.0.
+    pub fn sqlite_version_required(&self) -> (u32, u32, u32) {
+        let mut required = (3, 3, 0);
+        if self.indexes.iter().any(|index| index.predicate.is_some()) {
+            required = required.max((3, 8, 0));
+        }
+        if self.column_readonly.iter().any(|&readonly| readonly) {
+            required = required.max((3, 31, 0));
+        }
+        if self.existing_table {
+            required = required.max((3, 35, 0));
+        }
+        if self.strict {
+            required = required.max((3, 37, 0));
+        }
+        required
+    }
+
+    pub fn check_sqlite_version(&self, found: (u32, u32, u32)) -> Result<()> {
+        let required = self.sqlite_version_required();
+        if found < required {
+            return Err(Error::IncompatibleSqliteVersion(Box::new(
+                IncompatibleSqliteVersionError {
+                    table_name: self.table_name.to_owned(),
+                    required,
+                    found,
+                },
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn has_explicit_table_name(&self) -> bool {
+        self.explicit_table_name
+    }
+
+    // The schema with the table named by naming, unless its name is explicit.
+    pub fn with_table_naming(mut self, naming: TableNaming) -> Schema {
+        if !self.explicit_table_name {
+            self.table_name = naming.table_name(self.type_name);
+        }
+        self
+    }
+
+    // The type the column is declared with, which depends on STRICT.
+    pub fn get_nth_column_sql_type(&self, i: usize) -> &'static str {
+        match self.strict {
+            true => self.column_types[i].strict_type_name(),
+            false => self.column_types[i].into(),
+        }
+    }
+
+    pub fn get_visible_when(&self) -> Option<&'static str> {
+        self.visible_when
+    }
+
+    pub fn get_expires_at(&self) -> Option<&'static str> {
+        self.expires_at
+    }
+
+    pub fn get_connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
     pub fn column_fields_name(&self, separator: &str) -> String {
         self.column_names.join(separator)
     }
@@ -123,15 +609,457 @@ impl Schema {
         self.column_types.len()
     }
 
+    // Joined rather than trimmed, so that a table without columns is just the id.
     pub fn text_description(&self) -> String {
-        let mut result = "id INTEGER PRIMARY KEY AUTOINCREMENT,".to_owned();
-        for (col_name, col_type) in self.column_names.iter().zip(self.column_types.iter()) {
-            result.push_str(col_name);
-            result.push(' ');
-            result.push_str((*col_type).into());
-            result.push(',');
+        let mut definitions = vec!["id INTEGER PRIMARY KEY AUTOINCREMENT".to_owned()];
+        for (i, col_name) in self.column_names.iter().enumerate() {
+            definitions.push(format!("{} {}", col_name, self.get_nth_column_sql_type(i)));
+        }
+        for check in self.checks.iter() {
+            definitions.push(format!("CHECK ({})", check));
+        }
+        definitions.join(",")
+    }
+
+    // Creates the table or adds the missing columns; type changes and removed
+    // columns can't be applied automatically and are reported as SchemaMismatch.
+    pub fn migration_auto_apply(&self, conn: &rusqlite::Connection) -> Result<AutoMigrateResult> {
+        storage::auto_migrate(conn, self)
+    }
+
+    // Columns added, removed and changed in type since the `from` schema.
+    pub fn diff(&self, from: &Schema) -> SchemaDiff {
+        let find = |schema: &Schema, name: &str| {
+            (0..schema.columns_count())
+                .find(|&i| schema.column_names[i].eq_ignore_ascii_case(name))
+                .map(|i| schema.column_types[i])
+        };
+        let mut diff = SchemaDiff::default();
+        for (name, column_type) in self.column_names.iter().zip(self.column_types.iter()) {
+            match find(from, name) {
+                None => diff.added.push(name),
+                Some(from_type) if from_type != *column_type => diff.changed.push(name),
+                Some(_) => (),
+            }
+        }
+        for name in from.column_names.iter() {
+            if find(self, name).is_none() {
+                diff.removed.push(name);
+            }
+        }
+        diff
+    }
+
+    // Statements migrating the table from the `from` schema to this one, for
+    // review before running them (in a transaction). SQLite can't drop or
+    // retype a column in place, so the table is then rebuilt and the rows of
+    // the columns kept are copied over.
+    pub fn to_migration_sql(&self, from: &Schema) -> Vec<String> {
+        let diff = self.diff(from);
+        let table_name = self.table_name;
+        if diff.removed.is_empty() && diff.changed.is_empty() {
+            return diff
+                .added
+                .iter()
+                .map(|name| {
+                    let i = self.column_names.iter().position(|n| n == name).unwrap();
+                    format!(
+                        "ALTER TABLE {} ADD COLUMN {} {};",
+                        table_name,
+                        name,
+                        self.get_nth_column_sql_type(i)
+                    )
+                })
+                .collect();
+        }
+
+        let new_table_name = format!("{}_migration", table_name);
+        let mut kept_columns = vec!["id"];
+        kept_columns.extend(
+            self.column_names
+                .iter()
+                .filter(|name| !diff.added.contains(name)),
+        );
+        let kept_columns = kept_columns.join(", ");
+        let mut statements = vec![
+            format!("{};", storage::create_table_query(self, &new_table_name)),
+            format!(
+                "INSERT INTO {} ({}) SELECT {} FROM {};",
+                new_table_name, kept_columns, kept_columns, from.table_name
+            ),
+            format!("DROP TABLE {};", from.table_name),
+            format!("ALTER TABLE {} RENAME TO {};", new_table_name, table_name),
+        ];
+        for index in self.indexes.iter() {
+            statements.push(format!("{};", storage::create_index_query(self, index)));
         }
-        result.pop();
+        statements
+    }
+
+    // FNV-1a over the statements creating the table and its indexes, so that
+    // anything changing the DDL changes it, plus what the DDL doesn't show:
+    // nullability, readonly columns and whether the table is created at all.
+    // Stable across processes and compiler versions (unlike DefaultHasher).
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = FnvHasher::new();
+        hasher.write_str(&storage::create_table_query(self, self.table_name));
+        for index in self.indexes.iter() {
+            hasher.write_str(&storage::create_index_query(self, index));
+        }
+        for i in 0..self.columns_count() {
+            hasher.write(&[
+                self.column_nullability[i] as u8,
+                self.column_readonly[i] as u8,
+            ]);
+        }
+        hasher.write(&[self.existing_table as u8]);
+        hasher.finish()
+    }
+
+    // One row for the id and one per column, for generated documentation. The
+    // constraints are the ones the schema knows of, whether SQLite or yorm
+    // enforces them: NOT NULL or NULL, READONLY (generated or maintained by
+    // triggers), and every CHECK which mentions the column.
+    pub fn html_table(&self) -> String {
+        let mut result = "<table>\n<thead>\n<tr><th>Field</th><th>Column</th><th>Type</th>\
+                          <th>Constraints</th></tr>\n</thead>\n<tbody>\n\
+                          <tr><td></td><td>id</td><td>INTEGER</td>\
+                          <td>PRIMARY KEY AUTOINCREMENT</td></tr>\n"
+            .to_owned();
+        for i in 0..self.columns_count() {
+            let mut constraints = vec![match self.column_nullability[i] {
+                true => "NULL".to_owned(),
+                false => "NOT NULL".to_owned(),
+            }];
+            if self.column_readonly[i] {
+                constraints.push("READONLY".to_owned());
+            }
+            for check in self.checks.iter() {
+                let mut identifiers = check.split(|c: char| !c.is_alphanumeric() && c != '_');
+                if identifiers.any(|word| word.eq_ignore_ascii_case(self.column_names[i])) {
+                    constraints.push(format!("CHECK ({})", check));
+                }
+            }
+            writeln!(
+                result,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(self.field_names[i]),
+                escape_html(self.column_names[i]),
+                escape_html(self.get_nth_column_sql_type(i)),
+                escape_html(&constraints.join(", "))
+            )
+            .unwrap();
+        }
+        result.push_str("</tbody>\n</table>\n");
         result
     }
+
+    #[cfg(feature = "serde-json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        let columns = self
+            .column_names
+            .iter()
+            .zip(self.column_types.iter())
+            .zip(self.column_nullability.iter())
+            .map(|((col_name, col_type), nullable)| {
+                serde_json::json!({
+                    "name": col_name,
+                    "type": <&'static str>::from(*col_type),
+                    "not_null": !nullable,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "table": self.table_name,
+            "columns": columns,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TableNaming {
+    // OrderItem is stored in OrderItem.
+    #[default]
+    TypeName,
+    // OrderItem is stored in order_item.
+    SnakeCase,
+    // OrderItem is stored in order_items.
+    SnakeCasePlural,
+}
+
+impl TableNaming {
+    // Names are leaked to get 'static ones, once per distinct name.
+    pub fn table_name(self, type_name: &'static str) -> &'static str {
+        let name = match self {
+            TableNaming::TypeName => return type_name,
+            TableNaming::SnakeCase => to_snake_case(type_name),
+            TableNaming::SnakeCasePlural => pluralize(to_snake_case(type_name)),
+        };
+        static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+        let mut names = NAMES.lock().unwrap_or_else(|err| err.into_inner());
+        match names.get(name.as_str()) {
+            Some(&name) => name,
+            None => {
+                let name: &'static str = Box::leak(name.into_boxed_str());
+                names.insert(name);
+                name
+            }
+        }
+    }
+}
+
+// HTTPRequest2Log -> http_request2_log.
+fn to_snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut result = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                result.push('_');
+            }
+        }
+        result.extend(c.to_lowercase());
+    }
+    result
+}
+
+// Regular English plurals only: order -> orders, address -> addresses,
+// category -> categories.
+fn pluralize(mut name: String) -> String {
+    let ends_with_consonant_y =
+        name.ends_with('y') && !name[..name.len() - 1].ends_with(['a', 'e', 'i', 'o', 'u']);
+    if ends_with_consonant_y {
+        name.pop();
+        name.push_str("ies");
+    } else if ["s", "x", "z", "ch", "sh"]
+        .iter()
+        .any(|end| name.ends_with(end))
+    {
+        name.push_str("es");
+    } else {
+        name.push('s');
+    }
+    name
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Builds a Schema at runtime, for tables without a Rust struct (see DynTable).
+// Names are 'static like the ones generated by the derive macro.
+pub struct SchemaBuilder {
+    schema: Schema,
+}
+
+impl SchemaBuilder {
+    pub fn new(table_name: &'static str) -> Self {
+        Self {
+            schema: Schema {
+                table_name,
+                field_names: Vec::new(),
+                column_names: Vec::new(),
+                column_types: Vec::new(),
+                column_nullability: Vec::new(),
+                column_readonly: Vec::new(),
+                fieldless: false,
+                indexes: Vec::new(),
+                checks: Vec::new(),
+                strict: false,
+                existing_table: false,
+                explicit_table_name: true,
+                visible_when: None,
+                expires_at: None,
+                connection_type: ConnectionType::ReadWrite,
+                type_name: table_name,
+            },
+        }
+    }
+
+    pub fn column(self, name: &'static str, column_type: DataType) -> Self {
+        self.add_column(name, column_type, false)
+    }
+
+    pub fn nullable_column(self, name: &'static str, column_type: DataType) -> Self {
+        self.add_column(name, column_type, true)
+    }
+
+    pub fn index(mut self, index: Index) -> Self {
+        self.schema.indexes.push(index);
+        self
+    }
+
+    pub fn strict(mut self) -> Self {
+        self.schema.strict = true;
+        self
+    }
+
+    pub fn existing_table(mut self) -> Self {
+        self.schema.existing_table = true;
+        self
+    }
+
+    pub fn check(mut self, expression: &'static str) -> Self {
+        self.schema.checks.push(expression);
+        self
+    }
+
+    pub fn build(self) -> Schema {
+        self.schema
+    }
+
+    fn add_column(mut self, name: &'static str, column_type: DataType, nullable: bool) -> Self {
+        self.schema.field_names.push(name);
+        self.schema.column_names.push(name);
+        self.schema.column_types.push(column_type);
+        self.schema.column_nullability.push(nullable);
+        self.schema.column_readonly.push(false);
+        self
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub columns: Vec<&'static str>,
+    // Raw SQL emitted verbatim as the WHERE clause of a partial index.
+    pub predicate: Option<&'static str>,
+}
+
+impl Index {
+    pub fn name(&self, table_name: &str) -> String {
+        format!("{}_{}_idx", table_name, self.columns.join("_"))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoMigrateResult {
+    pub created: bool,
+    pub added_columns: Vec<String>,
+    pub unchanged: bool,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub added: Vec<&'static str>,
+    pub removed: Vec<&'static str>,
+    pub changed: Vec<&'static str>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    // Length-prefixed, so that ("ab", "c") and ("a", "bc") differ.
+    fn write_str(&mut self, str: &str) {
+        self.write(&(str.len() as u64).to_le_bytes());
+        self.write(str.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn escape_html(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn strip_prefix_ignore_case<'a>(str: &'a str, prefix: &str) -> Option<&'a str> {
+    match str.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => Some(&str[prefix.len()..]),
+        _ => None,
+    }
+}
+
+// Position of the parenthesis closing the one at `open`, skipping quoted text.
+fn matching_paren(str: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in str.char_indices().skip_while(|&(i, _)| i < open) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            (None, _) => (),
+        }
+    }
+    None
+}
+
+// Splits on the commas outside of parentheses and quoted text.
+fn split_top_level(str: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in str.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&str[start..i]);
+                start = i + 1;
+            }
+            (None, _) => (),
+        }
+    }
+    parts.push(&str[start..]);
+    parts
+}
+
+fn unquote_identifier(str: &str) -> String {
+    match (str.chars().next(), str.chars().last()) {
+        (Some('"'), Some('"')) | (Some('`'), Some('`')) if str.len() >= 2 => {
+            str[1..str.len() - 1].to_owned()
+        }
+        (Some('['), Some(']')) => str[1..str.len() - 1].to_owned(),
+        _ => str.to_owned(),
+    }
+}
+
+fn leak(str: String) -> &'static str {
+    Box::leak(str.into_boxed_str())
 }
@@ -0,0 +1,8 @@
+#![forbid(unsafe_code)]
+
+// Everything needed to define objects with the derive macro and work with them:
+// use orm::prelude::*;
+pub use crate::{
+    data::{DataType, Value},
+    Connection, Error, Object, ObjectId, Result, Transaction, Tx,
+};
@@ -1,54 +1,370 @@
 #![forbid(unsafe_code)]
 
-use crate::error::MissingColumnError;
+use crate::error::{
+    ConstraintError, IndexConflictError, MissingColumnError, ResultTooLargeError, RowWidthError,
+    SchemaMismatchError, TableNotFoundError,
+};
 use crate::{
     data::{DataType, Value},
     error::{Error, NotFoundError, Result, UnexpectedTypeError},
-    object::Schema,
+    object::{AutoMigrateResult, Index, Schema},
+    timing::{self, QueryStats},
     ObjectId,
 };
-use rusqlite::types::FromSql;
+use rusqlite::types::{FromSql, ValueRef};
+use rusqlite::ToSql;
+#[cfg(feature = "tracing")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+    ops::Deref,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub type Row<'a> = Vec<Value<'a>>;
 pub type RowSlice<'a> = [Value<'a>];
 
+pub fn approximate_row_size(row: &RowSlice) -> usize {
+    row.iter().map(Value::approximate_byte_size).sum()
+}
+
+pub fn render_params(row: &RowSlice, max_len: usize) -> String {
+    let mut result = String::new();
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            result.push_str(", ");
+        }
+        write!(result, "{:.*}", max_len, value).unwrap();
+    }
+    result
+}
+
+// For error messages: a blob by its length, anything else rendered like in
+// logs and cut at 64 characters.
+pub fn render_offending_value(value: &Value) -> String {
+    match value {
+        Value::Bytes(blob) => format!("<{} bytes>", blob.len()),
+        value => format!("{:.64}", value),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) trait StorageTransaction {
     fn table_exists(&self, table: &str) -> Result<bool>;
     fn create_table(&self, schema: &Schema) -> Result<()>;
+    // Keeps an existing index of the same definition, fails with
+    // IndexConflict on one of the same name but another definition.
+    fn create_index(&self, schema: &Schema, index: &Index) -> Result<()>;
+    // Fails with SchemaMismatch unless the table has exactly the schema's columns.
+    fn check_table(&self, schema: &Schema) -> Result<()>;
 
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
+    // Inserts with the given id rather than a new one.
+    fn insert_row_at(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
     fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
+    // assignments is the SET clause; its parameters come before the predicate's.
+    fn update_where(
+        &self,
+        schema: &Schema,
+        assignments: &str,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<u64>;
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
+    // Same as select_row, but the row must also satisfy an additional predicate.
+    fn select_row_where(
+        &self,
+        id: ObjectId,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<Row<'static>>;
+    // The row of root_id and of all its descendants through parent_column, by id.
+    fn select_subtree(
+        &self,
+        root_id: ObjectId,
+        schema: &Schema,
+        parent_column: &str,
+        predicate: Option<&str>,
+        params: &RowSlice,
+        max_rows: Option<u64>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    // Passes the matching rows to f one by one, in order_by order, until f fails.
+    fn select_each(
+        &self,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+        order_by: &str,
+        limit: Option<u64>,
+        f: &mut dyn FnMut(ObjectId, Row<'static>) -> Result<()>,
+    ) -> Result<()>;
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    // Returns the number of deleted rows.
+    fn delete_where(&self, schema: &Schema, predicate: &str, params: &RowSlice) -> Result<u64>;
+    // The same, but returns the ids of the rows deleted; needs SQLite 3.35 or
+    // newer for RETURNING.
+    fn delete_where_returning_ids(
+        &self,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<ObjectId>>;
+    // Runs arbitrary SQL, every value keeps the type SQLite returned it with.
+    fn query_rows(
+        &self,
+        query: &str,
+        params: &RowSlice,
+        max_rows: Option<u64>,
+    ) -> Result<Vec<Row<'static>>>;
+    // Runs an INSERT, UPDATE or DELETE ... RETURNING and returns its first row,
+    // or fails with NoRows; needs SQLite 3.35 or newer.
+    fn execute_returning(&self, query: &str, params: &RowSlice) -> Result<Row<'static>>;
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<()>;
+    fn rename_column(&self, table: &str, from: &str, to: &str) -> Result<()>;
+    // Updates the statistics the query planner chooses indexes by.
+    fn analyze(&self, table: &str) -> Result<()>;
+
+    // Named savepoints; release and rollback_to fail with UnknownSavepoint
+    // unless the name is open. rollback_to keeps the savepoint open.
+    fn savepoint(&self, name: &str) -> Result<()>;
+    fn release(&self, name: &str) -> Result<()>;
+    fn rollback_to(&self, name: &str) -> Result<()>;
+    // Opens a savepoint of a generated name (sp_0, sp_1, ...) unique in the
+    // transaction; commit and rollback of the result release it or roll back to it.
+    // The label only names its span.
+    fn nested(&self, label: &str) -> Result<Box<dyn StorageTransaction + '_>>;
 
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
+
+    // The yorm.transaction span of the transaction or savepoint; every
+    // statement it runs is a debug event within it.
+    #[cfg(feature = "tracing")]
+    fn span(&self) -> &tracing::Span;
+}
+
+// Rewrites every generated statement before it's prepared, see Connection::set_sql_rewriter.
+pub type SqlRewriter = dyn Fn(&str) -> String;
+
+// An open SQLite transaction or savepoint; everything but ending it goes
+// through the underlying connection.
+pub(crate) trait SqliteTransaction: Deref<Target = rusqlite::Connection> {
+    fn finish(&self, commit: bool) -> Result<()>;
+    fn sql_rewriter(&self) -> Option<&SqlRewriter>;
+    // Savepoints opened so far in the root transaction, for naming the next one.
+    fn savepoint_count(&self) -> &Cell<u64>;
+    // Set with Connection::enable_query_timing.
+    fn query_stats(&self) -> Option<&RefCell<QueryStats>>;
+    #[cfg(feature = "tracing")]
+    fn tracing_span(&self) -> &tracing::Span;
+
+    fn trace_statement(&self, _query: &str) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(parent: self.tracing_span(), sql = _query, "statement");
+    }
+
+    fn record_timing(&self, query: &str, elapsed: Duration) {
+        if let Some(stats) = self.query_stats() {
+            timing::record(stats, query, elapsed);
+        }
+    }
+
+    fn timed<R>(&self, query: &str, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record_timing(query, start.elapsed());
+        result
+    }
+
+    fn prepare_sql(&self, query: &str) -> rusqlite::Result<rusqlite::Statement<'_>> {
+        self.trace_statement(query);
+        match self.sql_rewriter() {
+            Some(rewrite) => self.deref().prepare(&rewrite(query)),
+            None => self.deref().prepare(query),
+        }
+    }
+
+    fn execute_sql<P: rusqlite::Params>(&self, query: &str, params: P) -> rusqlite::Result<usize> {
+        self.trace_statement(query);
+        match self.sql_rewriter() {
+            Some(rewrite) => self.deref().execute(&rewrite(query), params),
+            None => self.deref().execute(query, params),
+        }
+    }
 }
 
 // rusqlite::Transaction.deref() == rusqlite::Connection
-impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
+pub(crate) struct RootTransaction<'a> {
+    inner: rusqlite::Transaction<'a>,
+    sql_rewriter: Option<Rc<SqlRewriter>>,
+    savepoint_count: Cell<u64>,
+    query_stats: Option<Rc<RefCell<QueryStats>>>,
+    #[cfg(feature = "tracing")]
+    finished: Cell<bool>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<'a> RootTransaction<'a> {
+    pub(crate) fn new(
+        inner: rusqlite::Transaction<'a>,
+        sql_rewriter: Option<Rc<SqlRewriter>>,
+        query_stats: Option<Rc<RefCell<QueryStats>>>,
+    ) -> Self {
+        Self {
+            inner,
+            sql_rewriter,
+            savepoint_count: Cell::new(0),
+            query_stats,
+            #[cfg(feature = "tracing")]
+            finished: Cell::new(false),
+            #[cfg(feature = "tracing")]
+            span: transaction_span(None),
+        }
+    }
+}
+
+// A child of the current span; transactions are told apart in traces by a
+// number unique in the process.
+#[cfg(feature = "tracing")]
+fn transaction_span(savepoint: Option<&str>) -> tracing::Span {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    tracing::debug_span!(
+        "yorm.transaction",
+        id = NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        savepoint,
+        outcome = tracing::field::Empty,
+        inserted = tracing::field::Empty,
+        updated = tracing::field::Empty,
+        deleted = tracing::field::Empty,
+        duration_us = tracing::field::Empty,
+    )
+}
+
+impl<'a> Deref for RootTransaction<'a> {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        &self.inner
+    }
+}
+
+impl<'a> SqliteTransaction for RootTransaction<'a> {
+    fn finish(&self, commit: bool) -> Result<()> {
+        self.execute_sql(if commit { "COMMIT;" } else { "ROLLBACK;" }, [])?;
+        #[cfg(feature = "tracing")]
+        self.finished.set(true);
+        Ok(())
+    }
+
+    fn sql_rewriter(&self) -> Option<&SqlRewriter> {
+        self.sql_rewriter.as_deref()
+    }
+
+    fn savepoint_count(&self) -> &Cell<u64> {
+        &self.savepoint_count
+    }
+
+    fn query_stats(&self) -> Option<&RefCell<QueryStats>> {
+        self.query_stats.as_deref()
+    }
+
+    #[cfg(feature = "tracing")]
+    fn tracing_span(&self) -> &tracing::Span {
+        &self.span
+    }
+}
+
+// rusqlite::Transaction rolls back by itself, this only tells the trace.
+#[cfg(feature = "tracing")]
+impl<'a> Drop for RootTransaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished.get() {
+            self.span.record("outcome", "dropped");
+        }
+    }
+}
+
+// Rolled back when dropped unfinished, the same as rusqlite::Transaction.
+pub(crate) struct Savepoint<'a> {
+    conn: &'a rusqlite::Connection,
+    name: String,
+    finished: Cell<bool>,
+    sql_rewriter: Option<&'a SqlRewriter>,
+    savepoint_count: &'a Cell<u64>,
+    query_stats: Option<&'a RefCell<QueryStats>>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<'a> Deref for Savepoint<'a> {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        self.conn
+    }
+}
+
+impl<'a> SqliteTransaction for Savepoint<'a> {
+    fn finish(&self, commit: bool) -> Result<()> {
+        self.finished.set(true);
+        // ROLLBACK TO keeps the savepoint open, so it has to be released as well.
+        if !commit {
+            self.rollback_to(&self.name)?;
+        }
+        self.release(&self.name)
+    }
+
+    fn sql_rewriter(&self) -> Option<&SqlRewriter> {
+        self.sql_rewriter
+    }
+
+    fn savepoint_count(&self) -> &Cell<u64> {
+        self.savepoint_count
+    }
+
+    fn query_stats(&self) -> Option<&RefCell<QueryStats>> {
+        self.query_stats
+    }
+
+    #[cfg(feature = "tracing")]
+    fn tracing_span(&self) -> &tracing::Span {
+        &self.span
+    }
+}
+
+impl<'a> Drop for Savepoint<'a> {
+    fn drop(&mut self) {
+        if !self.finished.get() {
+            #[cfg(feature = "tracing")]
+            self.span.record("outcome", "dropped");
+            let _ = self.finish(false);
+        }
+    }
+}
+
+impl<T: SqliteTransaction> StorageTransaction for T {
     fn table_exists(&self, table_name: &str) -> Result<bool> {
         let query = format!(
             "SELECT name FROM sqlite_master WHERE type = 'table' AND name = \'{}\';",
             table_name
         );
-        let mut stmt = self.prepare(&query)?;
+        let mut stmt = self.prepare_sql(&query)?;
 
         let mut rows = stmt.query([])?;
         Ok(rows.next().map_or(false, |o| o.is_some()))
     }
 
     fn create_table(&self, schema: &Schema) -> Result<()> {
-        let query = format!(
-            "CREATE TABLE {} ({});",
-            schema.get_table_name(),
-            schema.text_description()
-        );
-        let mut stmt = self.prepare(&query)?;
+        let query = create_table_query(schema, schema.get_table_name());
+        let mut stmt = self.prepare_sql(&query)?;
         if let Err(err) = stmt.execute([]) {
             return match err {
                 rusqlite::Error::SqliteFailure(_, Some(str))
@@ -62,119 +378,736 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
         Ok(())
     }
 
+    fn create_index(&self, schema: &Schema, index: &Index) -> Result<()> {
+        let index_name = index.name(schema.get_table_name());
+        let mut stmt =
+            self.prepare_sql("SELECT sql FROM sqlite_master WHERE type = 'index' AND name = ?")?;
+        let mut rows = stmt.query([&index_name])?;
+        if let Some(row) = rows.next()? {
+            let existing = row.get::<_, String>(0)?;
+            if !same_definition(&existing, &index_definition(schema, index)) {
+                return Err(Error::IndexConflict(Box::new(IndexConflictError {
+                    index_name,
+                    table_name: schema.get_table_name(),
+                    existing,
+                })));
+            }
+        }
+        self.execute_sql(&create_index_query(schema, index), [])?;
+        Ok(())
+    }
+
+    fn check_table(&self, schema: &Schema) -> Result<()> {
+        let table_columns =
+            read_table_columns(self.prepare_sql(&table_info_query(schema.get_table_name()))?)?;
+        let mut mismatches = changed_columns(&table_columns, schema);
+        for i in 0..schema.columns_count() {
+            let col_name = schema.get_nth_column_name(i);
+            if !table_columns
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(col_name))
+            {
+                mismatches.push(format!("column '{}' is missing", col_name));
+            }
+        }
+        if !mismatches.is_empty() {
+            return Err(Error::SchemaMismatch(Box::new(SchemaMismatchError {
+                table_name: schema.get_table_name().to_owned(),
+                mismatches,
+            })));
+        }
+        Ok(())
+    }
+
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
-        let query = if schema.columns_count() == 0 {
+        let mut query = if schema.writable_columns_count() == 0 {
             format!("INSERT INTO {} (id) VALUES (NULL)", schema.get_table_name())
         } else {
             format!(
-                "INSERT INTO {} ({}) VALUES ({});",
+                "INSERT INTO {} ({}) VALUES ({})",
                 schema.get_table_name(),
-                schema.column_name_list(", "),
-                repeat_questions(schema.columns_count()),
+                schema.writable_column_list(", "),
+                repeat_questions(schema.writable_columns_count()),
             )
         };
+        // The id of a table yorm didn't create needn't be the rowid, so it's
+        // read back rather than taken from last_insert_rowid.
+        if schema.is_existing_table() {
+            query.push_str(" RETURNING id");
+        }
 
-        let stmt = self.prepare(&query);
+        let stmt = self.prepare_sql(&query);
         if let Err(err) = stmt {
             return match err {
                 rusqlite::Error::SqliteFailure(_, Some(str)) if has_missing_column_msg(&str) => {
                     Err(parse_missing_column(str, schema))
                 }
-                err => Err(err.into()),
+                err => Err(parse_missing_table(err, schema.get_table_name())),
             };
         }
-        let id = stmt
-            .unwrap()
-            .insert(rusqlite::params_from_iter(row.iter()))?;
+        let mut stmt = stmt.unwrap();
+        let params = rusqlite::params_from_iter(schema.writable_values(row));
+        let id = self
+            .timed(&query, || match schema.is_existing_table() {
+                true => stmt.query_row(params, |row| row.get(0)),
+                false => stmt.insert(params),
+            })
+            .map_err(|err| parse_check_failure(err, schema))?;
         Ok(ObjectId::new(id))
     }
 
+    fn insert_row_at(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        let query = if schema.writable_columns_count() == 0 {
+            format!("INSERT INTO {} (id) VALUES (?)", schema.get_table_name())
+        } else {
+            format!(
+                "INSERT INTO {} (id, {}) VALUES (?, {})",
+                schema.get_table_name(),
+                schema.writable_column_list(", "),
+                repeat_questions(schema.writable_columns_count()),
+            )
+        };
+
+        let stmt = self.prepare_sql(&query);
+        if let Err(err) = stmt {
+            return match err {
+                rusqlite::Error::SqliteFailure(_, Some(str)) if has_missing_column_msg(&str) => {
+                    Err(parse_missing_column(str, schema))
+                }
+                err => Err(parse_missing_table(err, schema.get_table_name())),
+            };
+        }
+        let mut stmt = stmt.unwrap();
+        let all_params = std::iter::once(&id as &dyn ToSql)
+            .chain(schema.writable_values(row).map(|value| value as &dyn ToSql));
+        self.timed(&query, || {
+            stmt.execute(rusqlite::params_from_iter(all_params))
+        })
+        .map_err(|err| parse_check_failure(err, schema))?;
+        Ok(())
+    }
+
     fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        if schema.writable_columns_count() == 0 {
+            return Ok(());
+        }
         let query = format!(
-            "UPDATE {} SET {} WHERE id = {}",
+            "UPDATE {} SET {} WHERE id = ?",
             schema.get_table_name(),
             schema.prepare_update_column_list(),
-            id
         );
-        let mut stmt = self.prepare(&query)?;
-        stmt.execute(rusqlite::params_from_iter(row.iter()))?;
+        let all_params = schema
+            .writable_values(row)
+            .map(|value| value as &dyn ToSql)
+            .chain(std::iter::once(&id as &dyn ToSql));
+        let mut stmt = self.prepare_sql(&query)?;
+        self.timed(&query, || {
+            stmt.execute(rusqlite::params_from_iter(all_params))
+        })
+        .map_err(|err| parse_check_failure(err, schema))?;
         Ok(())
     }
 
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
-        let query = if schema.columns_count() == 0 {
-            format!("SELECT * FROM {} WHERE id = ?", schema.get_table_name())
+        select_row_impl(self, id, schema, None, &[])
+    }
+
+    fn select_row_where(
+        &self,
+        id: ObjectId,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<Row<'static>> {
+        select_row_impl(self, id, schema, Some(predicate), params)
+    }
+
+    fn select_subtree(
+        &self,
+        root_id: ObjectId,
+        schema: &Schema,
+        parent_column: &str,
+        predicate: Option<&str>,
+        params: &RowSlice,
+        max_rows: Option<u64>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let table_name = schema.get_table_name();
+        let columns = if schema.columns_count() == 0 {
+            "id".to_owned()
         } else {
-            format!(
-                "SELECT {} FROM {} WHERE id = ?;",
-                schema.column_name_list(", "),
-                schema.get_table_name()
-            )
+            format!("{}, id", schema.column_name_list(", "))
         };
+        // UNION rather than UNION ALL, so that a cycle doesn't recurse forever.
+        let mut query = format!(
+            "WITH RECURSIVE subtree(id) AS (\
+                SELECT id FROM {table} WHERE id = ? \
+                UNION SELECT {table}.id FROM {table} JOIN subtree ON {table}.{parent} = subtree.id\
+            ) SELECT {columns} FROM {table} WHERE id IN subtree",
+            table = table_name,
+            parent = parent_column,
+            columns = columns,
+        );
+        if let Some(predicate) = predicate {
+            query.push_str(&format!(" AND ({})", predicate));
+        }
+        query.push_str(" ORDER BY id");
 
-        let stmt = self.prepare(&query);
+        let stmt = self.prepare_sql(&query);
         if let Err(err) = stmt {
             return match err {
                 rusqlite::Error::SqliteFailure(_, Some(str)) if has_missing_column_msg(&str) => {
                     Err(parse_missing_column(str, schema))
                 }
-                err => Err(err.into()),
+                err => Err(parse_missing_table(err, table_name)),
             };
         }
-
-        let result_row = stmt
-            .unwrap()
-            .query_row([id], |row| Ok(parse_sqlite_row(schema, row)));
-        match result_row {
-            Err(rusqlite::Error::QueryReturnedNoRows) => {
-                Err(Error::NotFound(Box::new(NotFoundError {
-                    object_id: id,
-                    type_name: schema.get_type_name(),
-                })))
+        let all_params =
+            std::iter::once(&root_id as &dyn ToSql).chain(params.iter().map(|p| p as &dyn ToSql));
+        let mut stmt = stmt.unwrap();
+        self.timed(&query, || {
+            let mut rows = stmt.query(rusqlite::params_from_iter(all_params))?;
+            let mut result = Vec::new();
+            while let Some(row) = rows.next()? {
+                check_row_limit(result.len(), max_rows, Some(table_name), None)?;
+                let id = ObjectId::new(row.get(schema.columns_count())?);
+                result.push((
+                    id,
+                    parse_sqlite_row(schema, row, schema.columns_count() + 1)?,
+                ));
             }
-            Ok(result) => result,
-            _ => panic!("Not implemented from select row"),
+            Ok(result)
+        })
+    }
+
+    fn select_each(
+        &self,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+        order_by: &str,
+        limit: Option<u64>,
+        f: &mut dyn FnMut(ObjectId, Row<'static>) -> Result<()>,
+    ) -> Result<()> {
+        let table_name = schema.get_table_name();
+        let columns = if schema.columns_count() == 0 {
+            "id".to_owned()
+        } else {
+            format!("{}, id", schema.column_name_list(", "))
+        };
+        let mut query = format!(
+            "SELECT {} FROM {} WHERE {} ORDER BY {}",
+            columns, table_name, predicate, order_by
+        );
+        // SQLite rejects a LIMIT over i64::MAX, which is no limit anyway.
+        if let Some(limit) = limit {
+            write!(query, " LIMIT {}", limit.min(i64::MAX as u64)).unwrap();
+        }
+
+        let stmt = self.prepare_sql(&query);
+        if let Err(err) = stmt {
+            return match err {
+                rusqlite::Error::SqliteFailure(_, Some(str)) if has_missing_column_msg(&str) => {
+                    Err(parse_missing_column(str, schema))
+                }
+                err => Err(parse_missing_table(err, table_name)),
+            };
         }
+        // Only the stepping is timed, not what f does with the rows.
+        let mut elapsed = Duration::ZERO;
+        let mut stmt = stmt.unwrap();
+        let start = Instant::now();
+        let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+        let mut next = rows.next();
+        elapsed += start.elapsed();
+        while let Some(row) = next? {
+            let id = ObjectId::new(row.get(schema.columns_count())?);
+            f(
+                id,
+                parse_sqlite_row(schema, row, schema.columns_count() + 1)?,
+            )?;
+            let start = Instant::now();
+            next = rows.next();
+            elapsed += start.elapsed();
+        }
+        self.record_timing(&query, elapsed);
+        Ok(())
     }
 
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
         let query = format!("DELETE FROM {} WHERE id = ?", schema.get_table_name());
-        self.execute(&query, [id])?;
+        self.timed(&query, || self.execute_sql(&query, [id]))?;
+        Ok(())
+    }
+
+    fn update_where(
+        &self,
+        schema: &Schema,
+        assignments: &str,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<u64> {
+        let query = format!(
+            "UPDATE {} SET {} WHERE {}",
+            schema.get_table_name(),
+            assignments,
+            predicate
+        );
+        let updated = self
+            .timed(&query, || {
+                self.execute_sql(&query, rusqlite::params_from_iter(params.iter()))
+            })
+            .map_err(|err| parse_check_failure(err, schema))?;
+        Ok(updated as u64)
+    }
+
+    fn delete_where(&self, schema: &Schema, predicate: &str, params: &RowSlice) -> Result<u64> {
+        let query = format!(
+            "DELETE FROM {} WHERE {}",
+            schema.get_table_name(),
+            predicate
+        );
+        let deleted = self.timed(&query, || {
+            self.execute_sql(&query, rusqlite::params_from_iter(params.iter()))
+        })?;
+        Ok(deleted as u64)
+    }
+
+    fn delete_where_returning_ids(
+        &self,
+        schema: &Schema,
+        predicate: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<ObjectId>> {
+        let query = format!(
+            "DELETE FROM {} WHERE {} RETURNING id",
+            schema.get_table_name(),
+            predicate
+        );
+        let ids = self.timed(&query, || {
+            let mut stmt = self.prepare_sql(&query)?;
+            let ids = stmt
+                .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                    row.get(0).map(ObjectId::new)
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>();
+            ids
+        })?;
+        Ok(ids)
+    }
+
+    fn query_rows(
+        &self,
+        query: &str,
+        params: &RowSlice,
+        max_rows: Option<u64>,
+    ) -> Result<Vec<Row<'static>>> {
+        // Written by the user, so not passed to the SQL rewriter.
+        self.trace_statement(query);
+        let mut stmt = self.deref().prepare(query)?;
+        let column_count = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            check_row_limit(result.len(), max_rows, None, Some(query))?;
+            result.push(read_untyped_row(row, column_count)?);
+        }
+        Ok(result)
+    }
+
+    fn execute_returning(&self, query: &str, params: &RowSlice) -> Result<Row<'static>> {
+        // Written by the user, so not passed to the SQL rewriter.
+        self.trace_statement(query);
+        let mut stmt = self.deref().prepare(query)?;
+        let column_count = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+        let first = match rows.next()? {
+            Some(row) => read_untyped_row(row, column_count)?,
+            None => return Err(Error::NoRows),
+        };
+        // The rest of the rows are stepped through so that the statement completes.
+        while rows.next()?.is_some() {}
+        Ok(first)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<()> {
+        let query = format!(
+            "ALTER TABLE {} RENAME TO {};",
+            quote_identifier(from),
+            quote_identifier(to)
+        );
+        self.execute_sql(&query, [])
+            .map_err(|err| parse_missing_table(err, from))?;
+        Ok(())
+    }
+
+    fn rename_column(&self, table: &str, from: &str, to: &str) -> Result<()> {
+        let query = format!(
+            "ALTER TABLE {} RENAME COLUMN {} TO {};",
+            quote_identifier(table),
+            quote_identifier(from),
+            quote_identifier(to)
+        );
+        self.execute_sql(&query, [])
+            .map_err(|err| parse_missing_table(err, table))?;
         Ok(())
     }
 
+    fn analyze(&self, table: &str) -> Result<()> {
+        self.execute_sql(&format!("ANALYZE {};", table), [])
+            .map_err(|err| parse_missing_table(err, table))?;
+        Ok(())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.execute_sql(&format!("SAVEPOINT {};", quote_identifier(name)), [])?;
+        Ok(())
+    }
+
+    fn release(&self, name: &str) -> Result<()> {
+        self.execute_sql(&format!("RELEASE {};", quote_identifier(name)), [])
+            .map_err(|err| parse_unknown_savepoint(err, name))?;
+        Ok(())
+    }
+
+    fn rollback_to(&self, name: &str) -> Result<()> {
+        self.execute_sql(&format!("ROLLBACK TO {};", quote_identifier(name)), [])
+            .map_err(|err| parse_unknown_savepoint(err, name))?;
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn nested(&self, label: &str) -> Result<Box<dyn StorageTransaction + '_>> {
+        let count = self.savepoint_count();
+        let name = format!("sp_{}", count.get());
+        count.set(count.get() + 1);
+        self.savepoint(&name)?;
+        Ok(Box::new(Savepoint {
+            conn: self.deref(),
+            finished: Cell::new(false),
+            sql_rewriter: self.sql_rewriter(),
+            savepoint_count: count,
+            query_stats: self.query_stats(),
+            #[cfg(feature = "tracing")]
+            span: self
+                .tracing_span()
+                .in_scope(|| transaction_span(Some(label))),
+            name,
+        }))
+    }
+
     fn commit(&self) -> Result<()> {
-        self.execute("COMMIT;", [])?;
+        self.finish(true)?;
+        #[cfg(feature = "tracing")]
+        self.tracing_span().record("outcome", "commit");
         Ok(())
     }
 
     fn rollback(&self) -> Result<()> {
-        self.execute("ROLLBACK;", [])?;
+        self.finish(false)?;
+        #[cfg(feature = "tracing")]
+        self.tracing_span().record("outcome", "rollback");
         Ok(())
     }
+
+    #[cfg(feature = "tracing")]
+    fn span(&self) -> &tracing::Span {
+        self.tracing_span()
+    }
 }
 
-fn parse_sqlite_row(schema: &Schema, row: &rusqlite::Row) -> Result<Row<'static>> {
-    let mut result = Vec::new();
-    for (i, col_type) in schema.get_types().iter().enumerate() {
-        let value = match col_type {
-            DataType::Bool => get_value_from_row::<bool>(row, i, DataType::Bool, schema)?.into(),
-            DataType::Float64 => {
-                get_value_from_row::<f64>(row, i, DataType::Float64, schema)?.into()
-            }
-            DataType::Int64 => get_value_from_row::<i64>(row, i, DataType::Int64, schema)?.into(),
-            DataType::Bytes => {
-                get_value_from_row::<Vec<u8>>(row, i, DataType::Bytes, schema)?.into()
+// Name and declared type of every column of the table, in table order.
+pub(crate) fn table_columns(
+    conn: &rusqlite::Connection,
+    table_name: &str,
+) -> Result<Vec<(String, String)>> {
+    read_table_columns(conn.prepare(&table_info_query(table_name))?)
+}
+
+fn table_info_query(table_name: &str) -> String {
+    format!("PRAGMA table_info({});", table_name)
+}
+
+fn read_table_columns(mut stmt: rusqlite::Statement) -> Result<Vec<(String, String)>> {
+    let columns = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(columns)
+}
+
+pub(crate) fn auto_migrate(
+    conn: &rusqlite::Connection,
+    schema: &Schema,
+) -> Result<AutoMigrateResult> {
+    let table_columns = table_columns(conn, schema.get_table_name())?;
+    if schema.is_existing_table() {
+        return check_existing_table(&table_columns, schema);
+    }
+    if table_columns.is_empty() {
+        conn.execute(&create_table_query(schema, schema.get_table_name()), [])?;
+        for index in schema.get_indexes() {
+            conn.execute(&create_index_query(schema, index), [])?;
+        }
+        return Ok(AutoMigrateResult {
+            created: true,
+            added_columns: Vec::new(),
+            unchanged: false,
+        });
+    }
+
+    let mismatches = changed_columns(&table_columns, schema);
+    if !mismatches.is_empty() {
+        return Err(Error::SchemaMismatch(Box::new(SchemaMismatchError {
+            table_name: schema.get_table_name().to_owned(),
+            mismatches,
+        })));
+    }
+
+    let mut added_columns = Vec::new();
+    for i in 0..schema.columns_count() {
+        let col_name = schema.get_nth_column_name(i);
+        if table_columns
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(col_name))
+        {
+            continue;
+        }
+        let query = format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            schema.get_table_name(),
+            col_name,
+            schema.get_nth_column_sql_type(i)
+        );
+        conn.execute(&query, [])?;
+        added_columns.push(col_name.to_owned());
+    }
+    Ok(AutoMigrateResult {
+        created: false,
+        unchanged: added_columns.is_empty(),
+        added_columns,
+    })
+}
+
+// Table columns which were removed from the schema or changed their type.
+// Only reports what the table lacks: its DDL is someone else's, so the
+// declared types aren't compared.
+fn check_existing_table(
+    table_columns: &[(String, String)],
+    schema: &Schema,
+) -> Result<AutoMigrateResult> {
+    if table_columns.is_empty() {
+        return Err(Error::TableNotFound(Box::new(TableNotFoundError {
+            table_name: schema.get_table_name().to_owned(),
+        })));
+    }
+    let mismatches = (0..schema.columns_count())
+        .map(|i| schema.get_nth_column_name(i))
+        .filter(|col_name| {
+            !table_columns
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(col_name))
+        })
+        .map(|col_name| format!("column '{}' is missing", col_name))
+        .collect::<Vec<_>>();
+    if !mismatches.is_empty() {
+        return Err(Error::SchemaMismatch(Box::new(SchemaMismatchError {
+            table_name: schema.get_table_name().to_owned(),
+            mismatches,
+        })));
+    }
+    Ok(AutoMigrateResult {
+        created: false,
+        added_columns: Vec::new(),
+        unchanged: true,
+    })
+}
+
+fn changed_columns(table_columns: &[(String, String)], schema: &Schema) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for (name, declared_type) in table_columns.iter() {
+        if name == "id" {
+            continue;
+        }
+        let column = (0..schema.columns_count())
+            .find(|&i| schema.get_nth_column_name(i).eq_ignore_ascii_case(name));
+        match column {
+            None => mismatches.push(format!("column '{}' was removed", name)),
+            Some(i) => {
+                let expected_type = schema.get_nth_column_sql_type(i);
+                if !expected_type.eq_ignore_ascii_case(declared_type) {
+                    mismatches.push(format!(
+                        "column '{}' changed type from {} to {}",
+                        name, declared_type, expected_type
+                    ));
+                }
             }
-            DataType::String => {
-                get_value_from_row::<String>(row, i, DataType::String, schema)?.into()
+        }
+    }
+    mismatches
+}
+
+pub(crate) fn create_table_query(schema: &Schema, table_name: &str) -> String {
+    let mut query = format!(
+        "CREATE TABLE {} ({})",
+        table_name,
+        schema.text_description()
+    );
+    if schema.is_strict() {
+        query.push_str(" STRICT");
+    }
+    query
+}
+
+pub(crate) fn create_index_query(schema: &Schema, index: &Index) -> String {
+    index_definition(schema, index).replacen("CREATE INDEX", "CREATE INDEX IF NOT EXISTS", 1)
+}
+
+// The statement as SQLite stores it in sqlite_master, which drops IF NOT EXISTS.
+fn index_definition(schema: &Schema, index: &Index) -> String {
+    let mut query = format!(
+        "CREATE INDEX {} ON {} ({})",
+        index.name(schema.get_table_name()),
+        schema.get_table_name(),
+        index.columns.join(", ")
+    );
+    if let Some(predicate) = index.predicate {
+        query.push_str(" WHERE ");
+        query.push_str(predicate);
+    }
+    query
+}
+
+// Ignores the whitespace and case, for indexes created by hand.
+fn same_definition(lhs: &str, rhs: &str) -> bool {
+    let normalize = |sql: &str| sql.split_whitespace().collect::<Vec<_>>().join(" ");
+    normalize(lhs).eq_ignore_ascii_case(&normalize(rhs))
+}
+
+fn select_row_impl<C: SqliteTransaction>(
+    conn: &C,
+    id: ObjectId,
+    schema: &Schema,
+    predicate: Option<&str>,
+    params: &RowSlice,
+) -> Result<Row<'static>> {
+    let mut query = if schema.columns_count() == 0 {
+        format!("SELECT id FROM {} WHERE id = ?", schema.get_table_name())
+    } else {
+        format!(
+            "SELECT {} FROM {} WHERE id = ?",
+            schema.column_name_list(", "),
+            schema.get_table_name()
+        )
+    };
+    if let Some(predicate) = predicate {
+        query.push_str(&format!(" AND ({})", predicate));
+    }
+
+    let stmt = conn.prepare_sql(&query);
+    if let Err(err) = stmt {
+        return match err {
+            rusqlite::Error::SqliteFailure(_, Some(str)) if has_missing_column_msg(&str) => {
+                Err(parse_missing_column(str, schema))
             }
+            err => Err(parse_missing_table(err, schema.get_table_name())),
+        };
+    }
+
+    let all_params =
+        std::iter::once(&id as &dyn ToSql).chain(params.iter().map(|p| p as &dyn ToSql));
+    let mut stmt = stmt.unwrap();
+    let result_row = conn.timed(&query, || {
+        stmt.query_row(rusqlite::params_from_iter(all_params), |row| {
+            // Without columns, the id alone is selected.
+            Ok(parse_sqlite_row(schema, row, schema.columns_count().max(1)))
+        })
+    });
+    match result_row {
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: id,
+                type_name: schema.get_type_name(),
+            })))
+        }
+        Ok(result) => result,
+        _ => panic!("Not implemented from select row"),
+    }
+}
+
+// Every value keeps the type SQLite returned it with.
+fn read_untyped_row(row: &rusqlite::Row, column_count: usize) -> Result<Row<'static>> {
+    let values = (0..column_count)
+        .map(|i| Ok(untyped_value(row.get_ref(i)?)))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(values)
+}
+
+fn untyped_value(value: ValueRef) -> Value<'static> {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(num) => num.into(),
+        ValueRef::Real(num) => num.into(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned().into(),
+        ValueRef::Blob(blob) => blob.to_vec().into(),
+    }
+}
+
+// width is the number of columns the statement was generated with, which a
+// rewritten statement or a drifted view may no longer return.
+fn parse_sqlite_row(schema: &Schema, row: &rusqlite::Row, width: usize) -> Result<Row<'static>> {
+    let got = row.as_ref().column_count();
+    if got != width {
+        return Err(Error::RowWidth(Box::new(RowWidthError {
+            table_name: schema.get_table_name(),
+            expected: width,
+            got,
+        })));
+    }
+    let mut result = Vec::new();
+    for (i, col_type) in schema.get_types().iter().enumerate() {
+        if schema.is_nth_column_nullable(i) && row.get_ref(i)? == ValueRef::Null {
+            result.push(Value::Null);
+            continue;
+        }
+        let col_type = *col_type;
+        let value = match col_type.wire_type() {
+            DataType::Bool => get_value_from_row::<bool>(row, i, col_type, schema)?.into(),
+            DataType::Float64 => get_value_from_row::<f64>(row, i, col_type, schema)?.into(),
+            DataType::Int64 => get_value_from_row::<i64>(row, i, col_type, schema)?.into(),
+            DataType::Bytes => get_value_from_row::<Vec<u8>>(row, i, col_type, schema)?.into(),
+            DataType::String => get_value_from_row::<String>(row, i, col_type, schema)?.into(),
+            DataType::Custom(_) => unreachable!(),
         };
         result.push(value)
     }
     Ok(result)
 }
 
+// Fails before reading one more row than max_rows, so that an oversized
+// result is never materialized.
+fn check_row_limit(
+    rows_read: usize,
+    max_rows: Option<u64>,
+    table: Option<&str>,
+    query: Option<&str>,
+) -> Result<()> {
+    match max_rows {
+        Some(limit) if rows_read as u64 >= limit => {
+            Err(Error::ResultTooLarge(Box::new(ResultTooLargeError {
+                table: table.map(str::to_owned),
+                query: query.map(str::to_owned),
+                limit,
+            })))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 fn repeat_questions(count: usize) -> String {
     assert_ne!(count, 0);
     let mut string = "?,".repeat(count);
@@ -194,6 +1127,39 @@ fn parse_missing_column(str: String, schema: &Schema) -> Error {
     }));
 }
 
+fn parse_missing_table(err: rusqlite::Error, table_name: &str) -> Error {
+    match err {
+        rusqlite::Error::SqliteFailure(_, Some(str)) if str.contains("no such table") => {
+            Error::TableNotFound(Box::new(TableNotFoundError {
+                table_name: table_name.to_owned(),
+            }))
+        }
+        err => err.into(),
+    }
+}
+
+fn parse_unknown_savepoint(err: rusqlite::Error, name: &str) -> Error {
+    match err {
+        rusqlite::Error::SqliteFailure(_, Some(str)) if str.contains("no such savepoint") => {
+            Error::UnknownSavepoint(name.to_owned())
+        }
+        err => err.into(),
+    }
+}
+
+fn parse_check_failure(err: rusqlite::Error, schema: &Schema) -> Error {
+    const PREFIX: &str = "CHECK constraint failed: ";
+    match err {
+        rusqlite::Error::SqliteFailure(_, Some(str)) if str.starts_with(PREFIX) => {
+            Error::Constraint(Box::new(ConstraintError {
+                table_name: schema.get_table_name(),
+                check: str[PREFIX.len()..].to_owned(),
+            }))
+        }
+        err => err.into(),
+    }
+}
+
 fn get_value_from_row<T: FromSql>(
     row: &rusqlite::Row,
     ind: usize,
@@ -210,6 +1176,7 @@ fn get_value_from_row<T: FromSql>(
                 column_name: schema.get_nth_column_name(ind),
                 expected_type,
                 got_type: c_type.to_string(),
+                value: render_offending_value(&untyped_value(row.get_ref(ind)?)),
             })))
         }
         Ok(row) => Ok(row),
@@ -0,0 +1,127 @@
+#![forbid(unsafe_code)]
+
+use crate::object::{Object, Schema};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[macro_export]
+macro_rules! assert_schema_matches {
+    ($type:ty, $sql:expr) => {
+        $crate::test_util::assert_schema_matches(&<$type as $crate::Object>::describe(), $sql)
+    };
+}
+
+pub fn assert_schema_matches(schema: &Schema, sql: &str) {
+    let mismatches = schema_mismatches(schema, sql);
+    if !mismatches.is_empty() {
+        panic!(
+            "schema of {} doesn't match SQL:\n  {}",
+            schema.get_type_name(),
+            mismatches.join("\n  ")
+        );
+    }
+}
+
+// Compares table name, column names and column types; the implicit id column is skipped.
+pub fn schema_mismatches(schema: &Schema, sql: &str) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let (table_name, sql_columns) = match parse_create_table(sql) {
+        Some(parsed) => parsed,
+        None => return vec![format!("cannot parse SQL: {}", sql)],
+    };
+
+    if !table_name.eq_ignore_ascii_case(schema.get_table_name()) {
+        mismatches.push(format!(
+            "table name: Rust has '{}', SQL has '{}'",
+            schema.get_table_name(),
+            table_name
+        ));
+    }
+
+    let rust_columns = (0..schema.columns_count())
+        .map(|i| {
+            let col_type: &'static str = schema.get_types()[i].into();
+            (
+                schema.get_nth_column_name(i).to_owned(),
+                col_type.to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for i in 0..rust_columns.len().max(sql_columns.len()) {
+        let describe = |column: Option<&(String, String)>| match column {
+            Some((name, col_type)) => format!("'{} {}'", name, col_type),
+            None => "nothing".to_owned(),
+        };
+        let (rust_column, sql_column) = (rust_columns.get(i), sql_columns.get(i));
+        let same = match (rust_column, sql_column) {
+            (Some((rust_name, rust_type)), Some((sql_name, sql_type))) => {
+                rust_name.eq_ignore_ascii_case(sql_name) && rust_type.eq_ignore_ascii_case(sql_type)
+            }
+            _ => false,
+        };
+        if !same {
+            mismatches.push(format!(
+                "column #{}: Rust has {}, SQL has {}",
+                i + 1,
+                describe(rust_column),
+                describe(sql_column)
+            ));
+        }
+    }
+    mismatches
+}
+
+fn parse_create_table(sql: &str) -> Option<(String, Vec<(String, String)>)> {
+    let (head, tail) = sql.split_once('(')?;
+    let body = &tail[..tail.rfind(')')?];
+
+    let table_name = unquote(head.split_whitespace().last()?);
+    let columns = body
+        .split(',')
+        .filter_map(|column| {
+            let mut words = column.split_whitespace();
+            let name = unquote(words.next()?);
+            let col_type = words.next().unwrap_or_default().to_owned();
+            Some((name, col_type))
+        })
+        .filter(|(name, _)| !name.eq_ignore_ascii_case("id"))
+        .collect();
+    Some((table_name, columns))
+}
+
+fn unquote(name: &str) -> String {
+    name.trim_matches(|c| c == '"' || c == '`' || c == '[' || c == ']')
+        .to_owned()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Compares the objects field by field, as stored: the id isn't part of the
+// row, so an object can be checked against the one it was created from.
+pub fn assert_object_eq<T: Object>(left: &T, right: &T) {
+    let mismatches = object_mismatches(left, right);
+    if !mismatches.is_empty() {
+        panic!(
+            "{} objects differ:\n  {}",
+            T::type_name(),
+            mismatches.join("\n  ")
+        );
+    }
+}
+
+pub fn object_mismatches<T: Object>(left: &T, right: &T) -> Vec<String> {
+    let field_names = T::field_names();
+    left.as_row()
+        .iter()
+        .zip(right.as_row().iter())
+        .enumerate()
+        .filter(|(_, (left_value, right_value))| left_value != right_value)
+        .map(|(i, (left_value, right_value))| {
+            format!(
+                "field '{}': left has {}, right has {}",
+                field_names[i], left_value, right_value
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,162 @@
+#![forbid(unsafe_code)]
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Upper bounds of the histogram buckets but the last, which has no bound.
+pub const HISTOGRAM_BOUNDS: [Duration; 5] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+#[derive(Clone, Debug)]
+pub struct QueryTiming {
+    // The statement shape: the generated SQL, where every value is a ? and an
+    // IN list of any length is a single ?.
+    pub shape: String,
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+    // Executions by duration: histogram[i] took less than HISTOGRAM_BOUNDS[i],
+    // and the last one counts the rest.
+    pub histogram: [u64; HISTOGRAM_BOUNDS.len() + 1],
+}
+
+impl QueryTiming {
+    fn new(shape: String) -> Self {
+        Self {
+            shape,
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            histogram: [0; HISTOGRAM_BOUNDS.len() + 1],
+        }
+    }
+
+    fn add(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        let bucket = HISTOGRAM_BOUNDS
+            .iter()
+            .position(|&bound| elapsed < bound)
+            .unwrap_or(HISTOGRAM_BOUNDS.len());
+        self.histogram[bucket] += 1;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub type SlowQueryCallback = dyn Fn(&QueryTiming);
+
+struct Entry {
+    timing: QueryTiming,
+    last_used: u64,
+    reported: bool,
+}
+
+// Timings of the generated statements of a connection, for at most
+// max_shapes shapes; the least recently run one makes room for a new one.
+pub(crate) struct QueryStats {
+    max_shapes: usize,
+    clock: u64,
+    entries: HashMap<String, Entry>,
+    slow_query: Option<(Duration, Rc<SlowQueryCallback>)>,
+}
+
+impl QueryStats {
+    pub(crate) fn new(max_shapes: usize) -> Self {
+        Self {
+            max_shapes: max_shapes.max(1),
+            clock: 0,
+            entries: HashMap::new(),
+            slow_query: None,
+        }
+    }
+
+    pub(crate) fn set_max_shapes(&mut self, max_shapes: usize) {
+        self.max_shapes = max_shapes.max(1);
+        while self.entries.len() > self.max_shapes {
+            self.evict();
+        }
+    }
+
+    pub(crate) fn set_slow_query_callback(
+        &mut self,
+        threshold: Duration,
+        callback: Rc<SlowQueryCallback>,
+    ) {
+        self.slow_query = Some((threshold, callback));
+    }
+
+    pub(crate) fn slow_queries(&self, threshold: Duration) -> Vec<QueryTiming> {
+        let mut result = self
+            .entries
+            .values()
+            .filter(|entry| entry.timing.max >= threshold)
+            .map(|entry| entry.timing.clone())
+            .collect::<Vec<_>>();
+        result.sort_by(|lhs, rhs| rhs.max.cmp(&lhs.max).then(lhs.shape.cmp(&rhs.shape)));
+        result
+    }
+
+    // The timing to report to the slow query callback, the first time its
+    // shape takes longer than the threshold.
+    fn add(
+        &mut self,
+        query: &str,
+        elapsed: Duration,
+    ) -> Option<(QueryTiming, Rc<SlowQueryCallback>)> {
+        self.clock += 1;
+        let shape = statement_shape(query);
+        if !self.entries.contains_key(&shape) && self.entries.len() >= self.max_shapes {
+            self.evict();
+        }
+        let entry = self.entries.entry(shape.clone()).or_insert_with(|| Entry {
+            timing: QueryTiming::new(shape),
+            last_used: 0,
+            reported: false,
+        });
+        entry.timing.add(elapsed);
+        entry.last_used = self.clock;
+        match &self.slow_query {
+            Some((threshold, callback)) if elapsed > *threshold && !entry.reported => {
+                entry.reported = true;
+                Some((entry.timing.clone(), callback.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(shape, _)| shape.clone());
+        if let Some(shape) = oldest {
+            self.entries.remove(&shape);
+        }
+    }
+}
+
+pub(crate) fn record(stats: &RefCell<QueryStats>, query: &str, elapsed: Duration) {
+    let slow = stats.borrow_mut().add(query, elapsed);
+    if let Some((timing, callback)) = slow {
+        callback(&timing);
+    }
+}
+
+// Values are bound as parameters, so only IN lists of different lengths
+// tell apart the statements of the same shape.
+fn statement_shape(query: &str) -> String {
+    let mut shape = query.trim_end_matches(';').to_owned();
+    while shape.contains("?, ?") {
+        shape = shape.replace("?, ?", "?");
+    }
+    shape
+}
@@ -2,18 +2,27 @@
 
 use crate::object::Store;
 use crate::{
-    data::ObjectId,
-    error::{Error, NotFoundError, Result},
-    object::{Object, Schema},
-    storage::StorageTransaction,
+    connection::{Clock, ConnectionType, SecurityContext, SystemClock},
+    cursor::{Cursor, Direction},
+    data::{DataType, FromValue, ObjectId, Value},
+    error::{
+        EnsureTableError, Error, IncompatibleSqliteVersionError, NestedConflictError,
+        NotFoundError, QueryColumnError, Result, TableNotFoundError,
+    },
+    filter::{Filter, MAX_PARAMS},
+    object::{self, Object, Schema, TableNaming},
+    storage::{self, Row, RowSlice, StorageTransaction},
 };
 use std::ops::Deref;
 use std::{
-    any::Any,
+    any::{Any, TypeId},
     cell::{Ref, RefCell, RefMut},
-    collections::HashMap,
-    marker::PhantomData,
-    rc::Rc,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    hash::{Hash, Hasher},
+    marker::{PhantomData, PhantomPinned},
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
 };
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -22,6 +31,30 @@ pub struct Transaction<'a> {
     inner: Box<dyn StorageTransaction + 'a>,
     cache: RefCell<HashMap<ObjectId, Rc<RefCell<dyn Store>>>>,
     states: RefCell<HashMap<ObjectId, Rc<RefCell<ObjectState>>>>,
+    security_context: Option<Rc<dyn SecurityContext>>,
+    clock: Rc<dyn Clock>,
+    connection_type: ConnectionType,
+    // Begun with BEGIN IMMEDIATE, so holding the write lock.
+    immediate: bool,
+    parent: Option<&'a Transaction<'a>>,
+    // Of a nested transaction: its savepoint, and the row hash and state of
+    // every object of the parent when it began, to tell conflicting writes.
+    savepoint: Option<String>,
+    parent_snapshot: HashMap<ObjectId, (u64, ObjectState)>,
+    strict_schema: bool,
+    max_rows_per_query: Option<u64>,
+    analyze_threshold: Option<u64>,
+    // Rows created, for analyze_threshold and the CommitSummary.
+    inserted: RefCell<Vec<(&'static str, ObjectId)>>,
+    started: Instant,
+    // Types whose table was checked against the schema, with strict_schema.
+    checked_types: RefCell<HashSet<TypeId>>,
+    table_naming: TableNaming,
+    assume_tables_exist: bool,
+    // Of the connection, which the schemas are checked against; None before
+    // it's set, which skips the check.
+    sqlite_version: Option<(u32, u32, u32)>,
+    cache_observer: Option<Rc<dyn Fn(CacheEvent) + 'a>>,
 }
 
 impl<'a> Transaction<'a> {
@@ -30,88 +63,1333 @@ impl<'a> Transaction<'a> {
             inner,
             cache: RefCell::new(HashMap::new()),
             states: RefCell::new(HashMap::new()),
+            security_context: None,
+            clock: Rc::new(SystemClock),
+            connection_type: ConnectionType::ReadWrite,
+            immediate: false,
+            parent: None,
+            savepoint: None,
+            parent_snapshot: HashMap::new(),
+            strict_schema: false,
+            max_rows_per_query: None,
+            analyze_threshold: None,
+            inserted: RefCell::new(Vec::new()),
+            started: Instant::now(),
+            checked_types: RefCell::new(HashSet::new()),
+            table_naming: TableNaming::TypeName,
+            assume_tables_exist: false,
+            sqlite_version: None,
+            cache_observer: None,
+        }
+    }
+
+    pub(crate) fn set_strict_schema(&mut self, strict_schema: bool) {
+        self.strict_schema = strict_schema;
+    }
+
+    // Queries returning more rows fail with ResultTooLarge, None is unlimited.
+    pub(crate) fn set_max_rows_per_query(&mut self, max_rows: Option<u64>) {
+        self.max_rows_per_query = max_rows;
+    }
+
+    // Tables with more than threshold rows created are analyzed on commit.
+    pub(crate) fn set_analyze_threshold(&mut self, threshold: Option<u64>) {
+        self.analyze_threshold = threshold;
+    }
+
+    pub(crate) fn set_sqlite_version(&mut self, version: (u32, u32, u32)) {
+        self.sqlite_version = Some(version);
+    }
+
+    pub(crate) fn set_table_naming(&mut self, table_naming: TableNaming) {
+        self.table_naming = table_naming;
+    }
+
+    // The schema of T, with its table named by the connection's TableNaming.
+    fn describe<T: Object>(&self) -> Schema {
+        <T as Object>::describe().with_table_naming(self.table_naming)
+    }
+
+    fn table_name<T: Object>(&self) -> &'static str {
+        match <T as Object>::has_explicit_table_name() {
+            true => <T as Object>::table_name(),
+            false => self.table_naming.table_name(<T as Object>::type_name()),
+        }
+    }
+
+    pub(crate) fn set_connection_type(&mut self, connection_type: ConnectionType) {
+        self.connection_type = connection_type;
+    }
+
+    pub(crate) fn set_immediate(&mut self, immediate: bool) {
+        self.immediate = immediate;
+    }
+
+    // Writes are refused before reaching the storage, both on a read-only
+    // connection and for objects declared read-only.
+    fn check_writable(&self, schema: &Schema) -> Result<()> {
+        if self.connection_type == ConnectionType::ReadOnly
+            || schema.get_connection_type() == ConnectionType::ReadOnly
+        {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // f is called on every cache lookup, insertion and eviction, including
+    // those of the nested transactions begun afterwards.
+    pub fn observe_cache<F: Fn(CacheEvent) + 'a>(&mut self, f: F) {
+        self.cache_observer = Some(Rc::new(f));
+    }
+
+    fn notify_cache(&self, event_type: CacheEventType, id: ObjectId, type_name: &'static str) {
+        if let Some(observer) = &self.cache_observer {
+            observer(CacheEvent {
+                event_type,
+                id,
+                type_name,
+            });
+        }
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn set_security_context(&mut self, context: Rc<dyn SecurityContext>) {
+        self.security_context = Some(context);
+    }
+
+    // Replaces :name placeholders of a visibility predicate with bound parameters.
+    fn bind_visibility(&self, predicate: &str) -> Result<(String, Row<'static>)> {
+        let values = match &self.security_context {
+            Some(context) => context.current_values(),
+            None => HashMap::new(),
+        };
+        let mut query = String::new();
+        let mut params = Vec::new();
+        let mut chars = predicate.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != ':' {
+                query.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            let value = match values.get(&name) {
+                Some(value) => value.clone(),
+                None => return Err(Error::MissingContextValue(name)),
+            };
+            query.push('?');
+            params.push(value);
+        }
+        Ok((query, params))
+    }
+
+    // What a row must satisfy to be loaded at all: the visibility predicate
+    // and not being expired. None if every row is visible.
+    fn row_predicate(&self, schema: &Schema) -> Result<Option<(String, Row<'static>)>> {
+        let mut predicates = Vec::new();
+        let mut params = Vec::new();
+        if let Some(visible_when) = schema.get_visible_when() {
+            let (predicate, visibility_params) = self.bind_visibility(visible_when)?;
+            predicates.push(format!("({})", predicate));
+            params.extend(visibility_params);
+        }
+        if let Some(column) = schema.get_expires_at() {
+            predicates.push(format!("({0} IS NULL OR {0} > ?)", column));
+            params.push(self.clock.now_millis().into());
+        }
+        if predicates.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((predicates.join(" AND "), params)))
+    }
+
+    // A performance knob for tight loops over tables known to exist: no
+    // longer checks for the table before each operation, so that a missing
+    // one fails with SQLite's own error, mostly TableNotFound, instead of
+    // being created. ensure_tables still creates the missing tables.
+    pub fn assume_tables_exist(&mut self, assume: bool) {
+        self.assume_tables_exist = assume;
+    }
+
+    // For statements on table which need a newer SQLite than its schema does.
+    fn require_sqlite_version(&self, table: &str, required: (u32, u32, u32)) -> Result<()> {
+        match self.sqlite_version {
+            Some(found) if found < required => Err(Error::IncompatibleSqliteVersion(Box::new(
+                IncompatibleSqliteVersionError {
+                    table_name: table.to_owned(),
+                    required,
+                    found,
+                },
+            ))),
+            _ => Ok(()),
         }
     }
 
     fn ensure_table(&self, schema: &Schema) -> Result<()> {
+        // Skips the lookup, not the version check: an old SQLite fails on
+        // the statements of an existing table all the same.
+        if self.assume_tables_exist {
+            return match self.sqlite_version {
+                Some(version) => schema.check_sqlite_version(version),
+                None => Ok(()),
+            };
+        }
+        self.create_missing_table(schema)
+    }
+
+    fn create_missing_table(&self, schema: &Schema) -> Result<()> {
+        schema.check_limits()?;
+        if let Some(version) = self.sqlite_version {
+            schema.check_sqlite_version(version)?;
+        }
         if !self.inner.table_exists(schema.get_table_name())? {
+            if schema.is_existing_table() {
+                return Err(Error::TableNotFound(Box::new(TableNotFoundError {
+                    table_name: schema.get_table_name().to_owned(),
+                })));
+            }
+            if self.connection_type == ConnectionType::ReadOnly {
+                return Err(Error::ReadOnly);
+            }
             self.inner.create_table(schema)?;
+            for index in schema.get_indexes() {
+                self.inner.create_index(schema, index)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Creates all missing tables up front instead of lazily on first use.
+    pub fn ensure_tables(&self, schemas: &[Schema]) -> Result<()> {
+        for schema in schemas {
+            let schema = &schema.clone().with_table_naming(self.table_naming);
+            self.create_missing_table(schema).map_err(|err| {
+                Error::EnsureTable(Box::new(EnsureTableError {
+                    table_name: schema.get_table_name(),
+                    source: err,
+                }))
+            })?;
         }
         Ok(())
     }
 
+    // Creates the missing indexes of the tables, which ensure_tables only does
+    // along with a new table, e.g. after an index is added to an existing type.
+    pub fn ensure_indexes(&self, schemas: &[Schema]) -> Result<()> {
+        for schema in schemas {
+            self.ensure_table(schema)?;
+            for index in schema.get_indexes() {
+                self.inner.create_index(schema, index)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Starts a child transaction on a SAVEPOINT of a generated name; name
+    // only tells the child apart in its span and in NestedConflict. The child
+    // has its own cache: it reads what this transaction has written so far,
+    // but not its uncommitted modifications. Committing the child updates the
+    // objects this transaction has already loaded; it fails with
+    // NestedConflict if one the child wrote was changed here after
+    // begin_nested, and the child is rolled back. Every loaded object is
+    // hashed to tell.
+    pub fn begin_nested(&self, name: &str) -> Result<Transaction<'_>> {
+        let mut child = Transaction::new(self.inner.nested(name)?);
+        child.savepoint = Some(name.to_owned());
+        // An object borrowed mutably right now counts as changed later.
+        let cache = self.cache.borrow();
+        for (id, state) in self.states.borrow().iter() {
+            if let Ok(object) = cache[id].try_borrow() {
+                let hash = object::hash_row(object.as_row().as_slice());
+                child
+                    .parent_snapshot
+                    .insert(*id, (hash, *state.deref().borrow()));
+            }
+        }
+        child.security_context = self.security_context.clone();
+        child.clock = self.clock.clone();
+        child.connection_type = self.connection_type;
+        child.immediate = self.immediate;
+        child.strict_schema = self.strict_schema;
+        child.max_rows_per_query = self.max_rows_per_query;
+        child.analyze_threshold = self.analyze_threshold;
+        child.table_naming = self.table_naming;
+        child.assume_tables_exist = self.assume_tables_exist;
+        child.sqlite_version = self.sqlite_version;
+        child.cache_observer = self.cache_observer.clone();
+        child.parent = Some(self);
+        Ok(child)
+    }
+
+    // Whether this transaction changed an object since child began which the
+    // child wrote as well: merging would silently lose one of the writes.
+    fn check_nested_conflicts(&self, child: &Transaction) -> Result<()> {
+        let cache = self.cache.borrow();
+        let states = self.states.borrow();
+        let child_cache = child.cache.borrow();
+        for (id, child_state) in child.states.borrow().iter() {
+            if !matches!(
+                *child_state.deref().borrow(),
+                ObjectState::Modified | ObjectState::Removed
+            ) {
+                continue;
+            }
+            let object = match cache.get(id) {
+                Some(object) => object,
+                None => continue,
+            };
+            let child_type_id = child_cache[id].deref().borrow().as_any().type_id();
+            let object = match object.try_borrow() {
+                Ok(object) if object.as_any().type_id() != child_type_id => continue,
+                Ok(object) => Some(object),
+                Err(_) => None,
+            };
+            let state = *states[id].deref().borrow();
+            let changed = match (&object, child.parent_snapshot.get(id)) {
+                (None, _) => true,
+                (Some(_), None) => state != ObjectState::Clean,
+                (Some(object), Some(&(hash, snapshot_state))) => {
+                    (state == ObjectState::Removed) != (snapshot_state == ObjectState::Removed)
+                        || object::hash_row(object.as_row().as_slice()) != hash
+                }
+            };
+            if changed {
+                let type_name = child_cache[id].deref().borrow().describe().get_type_name();
+                return Err(Error::NestedConflict(Box::new(NestedConflictError {
+                    savepoint: child.savepoint.clone().unwrap_or_default(),
+                    object_id: *id,
+                    type_name,
+                })));
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_nested(&self, child: &Transaction) {
+        let cache = self.cache.borrow();
+        let child_cache = child.cache.borrow();
+        for (id, state) in child.states.borrow().iter() {
+            let object = match cache.get(id) {
+                Some(object) => object,
+                None => continue,
+            };
+            let child_object = child_cache.get(id).unwrap().deref().borrow();
+            if object.deref().borrow().as_any().type_id() != child_object.as_any().type_id() {
+                continue;
+            }
+            match *state.deref().borrow() {
+                ObjectState::Modified => {
+                    object.deref().borrow_mut().load_row(child_object.as_row())
+                }
+                ObjectState::Removed => {
+                    *self.states.borrow().get(id).unwrap().borrow_mut() = ObjectState::Removed
+                }
+                ObjectState::Clean | ObjectState::RolledBack => (),
+            }
+        }
+    }
+
+    pub fn dyn_table(&self, schema: Schema) -> Result<DynTable<'_, 'a>> {
+        self.ensure_table(&schema)?;
+        Ok(DynTable {
+            transaction: self,
+            schema,
+        })
+    }
+
     pub fn create<T: Object>(&self, src_obj: T) -> Result<Tx<'_, T>> {
-        // Insert object into the underlying database.
-        let schema = <T as Object>::describe();
+        self.insert(src_obj, None)
+    }
+
+    // Get-or-create by id: the object of the id if there is one, otherwise
+    // T::default() created with that id, and whether it was created. A row
+    // hidden by visible_when or expires_at still holds the id, so creating
+    // over it fails, the same as over a removed object not yet committed.
+    pub fn load_or_create<T: Object + Default>(&self, id: ObjectId) -> Result<(Tx<'_, T>, bool)> {
+        match self.get::<T>(id) {
+            Ok(tx_object) => Ok((tx_object, false)),
+            Err(Error::NotFound(_)) if !self.cache.borrow().contains_key(&id) => {
+                Ok((self.insert(T::default(), Some(id))?, true))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // Get-or-create by a column: the first object, by id, whose unique_field
+    // (a field or column name) equals value, otherwise default created as it
+    // is, and whether it was created. A match removed in this transaction
+    // counts as none. Only a UNIQUE index keeps other transactions from
+    // creating the same value, this doesn't lock anything.
+    pub fn get_or_insert<T: Object>(
+        &self,
+        unique_field: &str,
+        value: impl Into<Value<'static>>,
+        default: T,
+    ) -> Result<(Tx<'_, T>, bool)> {
+        let schema = self.describe::<T>();
+        let column = (0..schema.columns_count())
+            .find(|&i| {
+                schema.get_nth_field_name(i) == unique_field
+                    || schema.get_nth_column_name(i) == unique_field
+            })
+            .map(|i| schema.get_nth_column_name(i))
+            .ok_or_else(|| Error::UnknownColumn(unique_field.to_owned()))?;
         self.ensure_table(&schema)?;
-        let id = self
-            .inner
-            .insert_row(&schema, src_obj.as_row().as_slice())?;
 
-        // Create Tx object and save it in the transaction cache.
-        let rc = Rc::new(RefCell::new(src_obj));
-        let state = Rc::new(RefCell::new(ObjectState::Clean));
-        self.cache
+        let (mut predicate, mut params) = Filter::eq(column, value).to_sql();
+        if let Some((row_predicate, row_params)) = self.row_predicate(&schema)? {
+            write!(predicate, " AND {}", row_predicate).unwrap();
+            params.extend(row_params);
+        }
+        let mut found = None;
+        self.inner.select_each(
+            &schema,
+            &predicate,
+            &params,
+            "id",
+            Some(1),
+            &mut |id, row| {
+                found = Some((id, row));
+                Ok(())
+            },
+        )?;
+        if let Some((id, row)) = found {
+            match self.get_cached::<T>(id) {
+                Ok(Some(tx_object)) => return Ok((tx_object, false)),
+                Ok(None) => {
+                    let object = <T as Object>::try_from_row(&schema, row)?;
+                    return Ok((self.cache_loaded(id, object), false));
+                }
+                Err(Error::NotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((self.insert(default, None)?, true))
+    }
+
+    fn insert<T: Object>(&self, src_obj: T, id: Option<ObjectId>) -> Result<Tx<'_, T>> {
+        // Insert object into the underlying database.
+        let schema = self.describe::<T>();
+        self.check_writable(&schema)?;
+        if schema.columns_count() == 0 && !schema.is_fieldless() {
+            return Err(Error::EmptySchema(schema.get_type_name()));
+        }
+        self.ensure_table(&schema)?;
+        if self.strict_schema && !self.checked_types.borrow().contains(&TypeId::of::<T>()) {
+            self.inner.check_table(&schema)?;
+            self.checked_types.borrow_mut().insert(TypeId::of::<T>());
+        }
+        let row = src_obj.as_row();
+        schema.check_object_row(row.as_slice())?;
+        let id = match id {
+            Some(id) => {
+                self.inner.insert_row_at(id, &schema, row.as_slice())?;
+                id
+            }
+            None => self.inner.insert_row(&schema, row.as_slice())?,
+        };
+        self.inserted
             .borrow_mut()
-            .insert(id, rc.clone() as Rc<RefCell<dyn Store>>);
-        self.states.borrow_mut().insert(id, state.clone());
-        Ok(Tx::new(rc, id, state))
+            .push((schema.get_table_name(), id));
+        Ok(self.cache_loaded(id, src_obj))
+    }
+
+    // SELECT ... FOR UPDATE as far as SQLite goes: it has no row locks, so the
+    // object is read under the write lock of the whole database, which the
+    // transaction holds from BEGIN IMMEDIATE to its end. Other connections
+    // can't write anything until then, not just this row. Fails with
+    // ImmediateRequired unless begun by Connection::new_immediate_transaction.
+    pub fn get_for_update<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
+        if !self.immediate {
+            return Err(Error::ImmediateRequired);
+        }
+        self.get(id)
     }
 
     pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
         // If current transaction already has such object loaded than return it.
-        let (rc, state_ref) = if self.cache.borrow().contains_key(&id) {
-            // Check if an object was removed already.
-            if *self.states.borrow().get(&id).unwrap().deref().borrow() == ObjectState::Removed {
-                return Err(Error::NotFound(Box::new(NotFoundError {
-                    object_id: id,
-                    type_name: <T as Object>::type_name(),
-                })));
+        if let Some(tx_object) = self.get_cached::<T>(id)? {
+            return Ok(tx_object);
+        }
+        // Get object from underlying database.
+        let schema = self.describe::<T>();
+        self.ensure_table(&schema)?;
+        let row = match self.row_predicate(&schema)? {
+            Some((predicate, params)) => {
+                self.inner
+                    .select_row_where(id, &schema, &predicate, params.as_slice())?
             }
-            let rc = self.cache.borrow().get(&id).unwrap().clone();
-            let state = self.states.borrow().get(&id).unwrap().clone();
-            (rc, state)
-        } else {
-            // Get object from underlying database.
-            let schema = <T as Object>::describe();
-            self.ensure_table(&schema)?;
-            let row = self.inner.select_row(id, &schema)?;
-            let src_obj = <T as Object>::from_row(row);
-
-            // Create Tx object and save it in the transaction cache.
-            let rc = Rc::new(RefCell::new(src_obj)) as Rc<RefCell<dyn Store>>;
-            let state = Rc::new(RefCell::new(ObjectState::Clean));
-            self.cache.borrow_mut().insert(id, rc.clone());
-            self.states.borrow_mut().insert(id, state.clone());
-            (rc, state)
+            None => self.inner.select_row(id, &schema)?,
         };
-        Ok(Tx::new(rc, id, state_ref))
+        Ok(self.cache_loaded(id, <T as Object>::try_from_row(&schema, row)?))
+    }
+
+    fn get_cached<T: Object>(&self, id: ObjectId) -> Result<Option<Tx<'_, T>>> {
+        if !self.cache.borrow().contains_key(&id) {
+            self.notify_cache(CacheEventType::Miss, id, <T as Object>::type_name());
+            return Ok(None);
+        }
+        self.notify_cache(CacheEventType::Hit, id, <T as Object>::type_name());
+        // Check if an object was removed already.
+        if *self.states.borrow().get(&id).unwrap().deref().borrow() == ObjectState::Removed {
+            return Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: id,
+                type_name: <T as Object>::type_name(),
+            })));
+        }
+        let rc = self.cache.borrow().get(&id).unwrap().clone();
+        let state = self.states.borrow().get(&id).unwrap().clone();
+        Ok(Some(Tx::new(rc, id, state, self.table_name::<T>())))
+    }
+
+    // Create Tx object and save it in the transaction cache.
+    fn cache_loaded<T: Object>(&self, id: ObjectId, src_obj: T) -> Tx<'_, T> {
+        let rc = Rc::new(RefCell::new(src_obj)) as Rc<RefCell<dyn Store>>;
+        let state = Rc::new(RefCell::new(ObjectState::Clean));
+        self.cache.borrow_mut().insert(id, rc.clone());
+        self.states.borrow_mut().insert(id, state.clone());
+        self.notify_cache(CacheEventType::Added, id, <T as Object>::type_name());
+        Tx::new(rc, id, state, self.table_name::<T>())
+    }
+
+    // Loads root_id and all of its descendants in one WITH RECURSIVE query,
+    // following parent_column (the name of the Int64 column holding the parent
+    // id). Objects are ordered by id; removed ones are skipped, cached ones are
+    // returned as they are.
+    pub fn load_subtree<T: Object>(
+        &self,
+        root_id: ObjectId,
+        parent_column: &str,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        let schema = self.describe::<T>();
+        let column =
+            (0..schema.columns_count()).find(|&i| schema.get_nth_column_name(i) == parent_column);
+        if column.map(|i| schema.get_types()[i].wire_type()) != Some(DataType::Int64) {
+            return Err(Error::InvalidParentColumn(parent_column.to_owned()));
+        }
+        self.ensure_table(&schema)?;
+        let rows = match self.row_predicate(&schema)? {
+            Some((predicate, params)) => self.inner.select_subtree(
+                root_id,
+                &schema,
+                parent_column,
+                Some(&predicate),
+                params.as_slice(),
+                self.max_rows_per_query,
+            )?,
+            None => self.inner.select_subtree(
+                root_id,
+                &schema,
+                parent_column,
+                None,
+                &[],
+                self.max_rows_per_query,
+            )?,
+        };
+        if rows.is_empty() {
+            return Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: root_id,
+                type_name: schema.get_type_name(),
+            })));
+        }
+
+        let mut result = Vec::new();
+        for (id, row) in rows {
+            match self.get_cached::<T>(id) {
+                Ok(Some(tx_object)) => result.push(tx_object),
+                Ok(None) => {
+                    let src_obj = <T as Object>::try_from_row(&schema, row)?;
+                    result.push(self.cache_loaded(id, src_obj));
+                }
+                Err(Error::NotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(result)
+    }
+
+    // Streams the objects matching filter to f in id order, without collecting
+    // them, and stops at the first error f returns. Objects are cached as by
+    // get and removed ones are skipped. max_rows_per_query doesn't apply.
+    // A filter with more than MAX_PARAMS parameters is run as several queries
+    // over chunks of its largest IN list, whose rows are collected to be
+    // passed on in id order. Unknown filter columns fail with UnknownColumn.
+    pub fn for_each<T: Object, F: FnMut(Tx<'_, T>) -> Result<()>>(
+        &self,
+        filter: impl Into<Filter>,
+        mut f: F,
+    ) -> Result<()> {
+        let filter = filter.into();
+        let schema = self.describe::<T>();
+        filter.check_columns(&schema)?;
+        self.ensure_table(&schema)?;
+        let row_predicate = self.row_predicate(&schema)?;
+        let row_params_count = row_predicate.as_ref().map_or(0, |(_, params)| params.len());
+        let mut chunks = filter.to_sql_chunks(MAX_PARAMS.saturating_sub(row_params_count));
+        for (predicate, params) in chunks.iter_mut() {
+            if let Some((row_predicate, row_params)) = &row_predicate {
+                *predicate = format!("({}) AND {}", predicate, row_predicate);
+                params.extend(row_params.iter().cloned());
+            }
+        }
+        let mut pass_on = |id: ObjectId, row: Row<'static>| {
+            let tx_object = match self.get_cached::<T>(id) {
+                Ok(Some(tx_object)) => tx_object,
+                Ok(None) => self.cache_loaded(id, <T as Object>::try_from_row(&schema, row)?),
+                Err(Error::NotFound(_)) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            f(tx_object)
+        };
+        if let [(predicate, params)] = chunks.as_slice() {
+            return self
+                .inner
+                .select_each(&schema, predicate, params, "id", None, &mut pass_on);
+        }
+        // Keyed by id, which orders the rows and drops the duplicates matched
+        // by two chunks when the IN list repeats a value.
+        let mut rows = BTreeMap::new();
+        for (predicate, params) in chunks.iter() {
+            self.inner
+                .select_each(&schema, predicate, params, "id", None, &mut |id, row| {
+                    rows.insert(id.into_i64(), row);
+                    Ok(())
+                })?;
+        }
+        for (id, row) in rows {
+            pass_on(ObjectId::new(id), row)?;
+        }
+        Ok(())
+    }
+
+    // Compare-and-swap on arbitrary columns: sets the columns of set in the
+    // row of id only if its columns of expected still hold the given values,
+    // compared with IS so that Null matches NULL. Returns whether the row was
+    // updated. Objects this transaction has already loaded aren't refreshed,
+    // and a modified one overwrites the update on commit.
+    pub fn update_if<T: Object>(
+        &self,
+        id: ObjectId,
+        expected: &[(&str, Value<'static>)],
+        set: &[(&str, Value<'static>)],
+    ) -> Result<bool> {
+        let schema = self.describe::<T>();
+        for (column, _) in expected.iter().chain(set.iter()) {
+            if !(0..schema.columns_count()).any(|i| schema.get_nth_column_name(i) == *column) {
+                return Err(Error::UnknownColumn(column.to_string()));
+            }
+        }
+        self.check_writable(&schema)?;
+        self.ensure_table(&schema)?;
+
+        let mut assignments = set
+            .iter()
+            .map(|(column, _)| format!("{} = ?", column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if assignments.is_empty() {
+            // Nothing to set, which still tells whether the row matches.
+            assignments.push_str("id = id");
+        }
+        let mut predicate = "id = ?".to_owned();
+        for (column, _) in expected {
+            write!(predicate, " AND {} IS ?", column).unwrap();
+        }
+        let mut params = set
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect::<Vec<_>>();
+        params.push(id.into_i64().into());
+        params.extend(expected.iter().map(|(_, value)| value.clone()));
+        if let Some((row_predicate, row_params)) = self.row_predicate(&schema)? {
+            write!(predicate, " AND {}", row_predicate).unwrap();
+            params.extend(row_params);
+        }
+        let updated = self
+            .inner
+            .update_where(&schema, &assignments, &predicate, &params)?;
+        Ok(updated > 0)
+    }
+
+    // Deletes the expired rows of T, returning how many were deleted. Objects
+    // this transaction has already loaded stay usable: expiry is only checked
+    // when a row is read from the storage.
+    pub fn purge_expired<T: Object>(&self) -> Result<u64> {
+        let schema = self.describe::<T>();
+        let column = match schema.get_expires_at() {
+            Some(column) => column,
+            None => return Ok(0),
+        };
+        self.check_writable(&schema)?;
+        self.ensure_table(&schema)?;
+        self.inner.delete_where(
+            &schema,
+            &format!("{} <= ?", column),
+            &[self.clock.now_millis().into()],
+        )
+    }
+
+    // Deletes the rows of ids with one DELETE per MAX_PARAMS of them,
+    // returning how many were deleted; rows hidden by visible_when or expired
+    // are left alone. The loaded objects of the rows actually deleted are
+    // marked Removed, the same as by Tx::delete. Needs SQLite 3.35.0 for
+    // DELETE ... RETURNING.
+    pub fn delete_many<T: Object>(&self, ids: &[ObjectId]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let schema = self.describe::<T>();
+        self.check_writable(&schema)?;
+        self.require_sqlite_version(schema.get_table_name(), (3, 35, 0))?;
+        self.ensure_table(&schema)?;
+        let row_predicate = self.row_predicate(&schema)?;
+        let row_params_count = row_predicate.as_ref().map_or(0, |(_, params)| params.len());
+        let filter = Filter::In(
+            "id".to_owned(),
+            ids.iter().map(|id| id.into_i64().into()).collect(),
+        );
+        let mut deleted = Vec::new();
+        for (mut predicate, mut params) in
+            filter.to_sql_chunks(MAX_PARAMS.saturating_sub(row_params_count))
+        {
+            if let Some((row_predicate, row_params)) = &row_predicate {
+                write!(predicate, " AND {}", row_predicate).unwrap();
+                params.extend(row_params.iter().cloned());
+            }
+            deleted.extend(
+                self.inner
+                    .delete_where_returning_ids(&schema, &predicate, &params)?,
+            );
+        }
+
+        let cache = self.cache.borrow();
+        let states = self.states.borrow();
+        for id in deleted.iter() {
+            let is_t = cache.get(id).is_some_and(|object| {
+                object.deref().borrow().as_any().type_id() == TypeId::of::<T>()
+            });
+            if is_t {
+                *states.get(id).unwrap().borrow_mut() = ObjectState::Removed;
+            }
+        }
+        Ok(deleted.len() as u64)
+    }
+
+    // Keyset pagination: at most limit objects ordered by the columns of order,
+    // then by id to break ties, starting after cursor. The returned cursor
+    // resumes after the last object, None once there are no more. Ordering
+    // columns must not hold NULL, or the rows with it may be skipped.
+    pub fn page<T: Object>(
+        &self,
+        order: &[(&str, Direction)],
+        cursor: Option<Cursor>,
+        limit: u64,
+    ) -> Result<(Vec<Tx<'_, T>>, Option<Cursor>)> {
+        let schema = self.describe::<T>();
+        let mut columns = Vec::new();
+        for (column, _) in order {
+            match (0..schema.columns_count()).find(|&i| schema.get_nth_column_name(i) == *column) {
+                Some(i) => columns.push(i),
+                None => return Err(Error::UnknownColumn(column.to_string())),
+            }
+        }
+        let mut keys = order.to_vec();
+        keys.push(("id", Direction::Asc));
+        self.ensure_table(&schema)?;
+
+        let filter = match &cursor {
+            Some(cursor) if cursor.values().len() != keys.len() => {
+                return Err(Error::InvalidCursor(
+                    "the cursor is of another ordering".to_owned(),
+                ))
+            }
+            Some(cursor) => keyset_filter(&keys, cursor.values()),
+            None => Filter::And(vec![]),
+        };
+        let (mut predicate, mut params) = filter.to_sql();
+        if let Some((row_predicate, row_params)) = self.row_predicate(&schema)? {
+            predicate = format!("({}) AND {}", predicate, row_predicate);
+            params.extend(row_params);
+        }
+        let order_by = keys
+            .iter()
+            .map(|(column, direction)| format!("{} {}", column, direction.sql_keyword()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // One row more than the page tells whether there is a next one.
+        let mut result = Vec::new();
+        let mut rows_read = 0;
+        let mut last_values = None;
+        self.inner.select_each(
+            &schema,
+            &predicate,
+            params.as_slice(),
+            &order_by,
+            Some(limit.saturating_add(1)),
+            &mut |id, row| {
+                rows_read += 1;
+                if rows_read > limit {
+                    return Ok(());
+                }
+                let mut values = columns.iter().map(|&i| row[i].clone()).collect::<Vec<_>>();
+                values.push(id.into_i64().into());
+                last_values = Some(values);
+                match self.get_cached::<T>(id) {
+                    Ok(Some(tx_object)) => result.push(tx_object),
+                    Ok(None) => result
+                        .push(self.cache_loaded(id, <T as Object>::try_from_row(&schema, row)?)),
+                    Err(Error::NotFound(_)) => (),
+                    Err(err) => return Err(err),
+                }
+                Ok(())
+            },
+        )?;
+        let next = match rows_read > limit {
+            true => last_values.map(Cursor::new),
+            false => None,
+        };
+        Ok((result, next))
+    }
+
+    // Overwrites the object with the incoming one and marks it modified.
+    // An object which doesn't exist is created instead, under the given id.
+    // One removed in this transaction fails with NotFound, like in
+    // load_or_create: the removal isn't undone.
+    pub fn merge<T: Object>(&self, id: ObjectId, incoming: T) -> Result<Tx<'_, T>> {
+        match self.get::<T>(id) {
+            Ok(tx_object) => {
+                *tx_object.borrow_mut() = incoming;
+                Ok(tx_object)
+            }
+            Err(Error::NotFound(_)) if !self.cache.borrow().contains_key(&id) => {
+                self.insert(incoming, Some(id))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn duplicate<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
+        self.duplicate_with(id, |_: &mut T| {})
+    }
+
+    pub fn duplicate_with<T: Object, F: FnOnce(&mut T)>(
+        &self,
+        id: ObjectId,
+        modify: F,
+    ) -> Result<Tx<'_, T>> {
+        // Copy through the row representation, so T doesn't have to be Clone.
+        // A cached object is copied with its uncommitted modifications.
+        let original = self.get::<T>(id)?;
+        let mut copy = <T as Object>::from_row(original.borrow().as_row());
+        modify(&mut copy);
+        self.create(copy)
+    }
+
+    // Evicts the Clean objects, which will be loaded again on the next get.
+    // Objects still referenced by a Tx are kept: changes made through such a
+    // handle after eviction would never be committed.
+    pub fn compact_cache(&self) -> usize {
+        let mut cache = self.cache.borrow_mut();
+        let mut states = self.states.borrow_mut();
+        let evicted = states
+            .iter()
+            .filter(|(id, state)| {
+                *state.borrow() == ObjectState::Clean
+                    && Rc::strong_count(state) == 1
+                    && Rc::strong_count(&cache[id]) == 1
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in evicted.iter() {
+            let object = cache.remove(id).unwrap();
+            states.remove(id);
+            let type_name = object.deref().borrow().describe().get_type_name();
+            self.notify_cache(CacheEventType::Evicted, *id, type_name);
+        }
+        evicted.len()
+    }
+
+    // Ids of the loaded objects in the given state, in ascending order, e.g.
+    // the Removed ones to confirm before committing.
+    pub fn ids_by_state(&self, state: ObjectState) -> Vec<ObjectId> {
+        let mut ids = self
+            .states
+            .borrow()
+            .iter()
+            .filter(|(_, object_state)| *object_state.borrow() == state)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.sort_by_key(ObjectId::into_i64);
+        ids
+    }
+
+    // Number of objects in the cache, removed ones included.
+    pub fn cache_len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    // Rough size of the cached rows in bytes, see Value::approximate_byte_size.
+    pub fn cache_memory_estimate(&self) -> usize {
+        self.cache
+            .borrow()
+            .values()
+            .map(|object| {
+                storage::approximate_row_size(object.deref().borrow().as_row().as_slice())
+            })
+            .sum()
+    }
+
+    // Average estimated row size of the cached objects of type T,
+    // None if there are no such objects in the cache.
+    pub fn avg_row_size_bytes<T: Object>(&self) -> Option<f64> {
+        let cache = self.cache.borrow();
+        let sizes = cache
+            .values()
+            .map(|object| object.deref().borrow())
+            .filter(|object| object.as_any().is::<T>())
+            .map(|object| storage::approximate_row_size(object.as_row().as_slice()))
+            .collect::<Vec<_>>();
+        if sizes.is_empty() {
+            return None;
+        }
+        Some(sizes.iter().sum::<usize>() as f64 / sizes.len() as f64)
+    }
+
+    // Raw INSERT, UPDATE or DELETE with a RETURNING clause (SQLite 3.35+),
+    // giving its first row as SQLite returned it, or NoRows. It bypasses the
+    // cache like the raw queries do, so objects already loaded keep their values.
+    pub fn execute_returning(&self, query: &str, params: &RowSlice) -> Result<Row<'static>> {
+        if self.connection_type == ConnectionType::ReadOnly {
+            return Err(Error::ReadOnly);
+        }
+        self.inner.execute_returning(query, params)
+    }
+
+    // Raw SQL projections read positionally into scalars and tuples. Modified
+    // objects are only written on commit, so queries see their previous values.
+    pub fn query_scalar1<A: FromValue>(&self, query: &str, params: &RowSlice) -> Result<Vec<A>> {
+        self.query_tuples(query, params, 1, |row| query_column(row, 0))
+    }
+
+    pub fn query_scalar2<A: FromValue, B: FromValue>(
+        &self,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<(A, B)>> {
+        self.query_tuples(query, params, 2, |row| {
+            Ok((query_column(row, 0)?, query_column(row, 1)?))
+        })
+    }
+
+    pub fn query_scalar3<A: FromValue, B: FromValue, C: FromValue>(
+        &self,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<(A, B, C)>> {
+        self.query_tuples(query, params, 3, |row| {
+            Ok((
+                query_column(row, 0)?,
+                query_column(row, 1)?,
+                query_column(row, 2)?,
+            ))
+        })
+    }
+
+    pub fn query_scalar4<A: FromValue, B: FromValue, C: FromValue, D: FromValue>(
+        &self,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<(A, B, C, D)>> {
+        self.query_tuples(query, params, 4, |row| {
+            Ok((
+                query_column(row, 0)?,
+                query_column(row, 1)?,
+                query_column(row, 2)?,
+                query_column(row, 3)?,
+            ))
+        })
+    }
+
+    fn query_tuples<T, F: Fn(&mut Row<'static>) -> Result<T>>(
+        &self,
+        query: &str,
+        params: &RowSlice,
+        arity: usize,
+        extract: F,
+    ) -> Result<Vec<T>> {
+        let mut rows = self
+            .inner
+            .query_rows(query, params, self.max_rows_per_query)?;
+        if let Some(row) = rows.first() {
+            if row.len() < arity {
+                return Err(Error::QueryColumn(Box::new(QueryColumnError {
+                    column: row.len(),
+                    expected: "a column",
+                    got: "nothing".to_owned(),
+                })));
+            }
+        }
+        rows.iter_mut().map(extract).collect()
+    }
+
+    // Statistics of an Int64 or Float64 column in one query. min, max, mean
+    // and sum are over the finite values; infinities are only counted, and
+    // NaN is stored by SQLite as NULL. sum is a float, so it can't overflow.
+    pub fn column_stats<T: Object>(&self, column: &str) -> Result<ColumnStats> {
+        let schema = self.describe::<T>();
+        let i = (0..schema.columns_count())
+            .find(|&i| schema.get_nth_column_name(i) == column)
+            .ok_or_else(|| Error::UnknownColumn(column.to_owned()))?;
+        if !matches!(
+            schema.get_types()[i].wire_type(),
+            DataType::Int64 | DataType::Float64
+        ) {
+            return Err(Error::NonNumericColumn(column.to_owned()));
+        }
+        self.ensure_table(&schema)?;
+        let (predicate, params) = self
+            .row_predicate(&schema)?
+            .unwrap_or_else(|| ("1".to_owned(), Vec::new()));
+        let query = format!(
+            "SELECT COUNT(value), COUNT(*) - COUNT(value), COUNT(value) - COUNT(finite), \
+                MIN(finite), MAX(finite), TOTAL(finite), COUNT(finite) \
+            FROM (SELECT {column} AS value, \
+                CASE WHEN {column} IN (9e999, -9e999) THEN NULL ELSE {column} END AS finite \
+                FROM {table} WHERE {predicate})",
+            column = column,
+            table = schema.get_table_name(),
+            predicate = predicate,
+        );
+        let mut row = self.inner.query_rows(&query, &params, None)?.remove(0);
+        let sum: f64 = query_column(&mut row, 5)?;
+        let finite_count: i64 = query_column(&mut row, 6)?;
+        Ok(ColumnStats {
+            count: query_column::<i64>(&mut row, 0)? as u64,
+            null_count: query_column::<i64>(&mut row, 1)? as u64,
+            non_finite_count: query_column::<i64>(&mut row, 2)? as u64,
+            min: query_column(&mut row, 3)?,
+            max: query_column(&mut row, 4)?,
+            mean: match finite_count {
+                0 => None,
+                count => Some(sum / count as f64),
+            },
+            sum,
+        })
+    }
+
+    // Inserts a row per CSV record, without loading the objects; they are
+    // visible to the queries of this transaction right away.
+    #[cfg(feature = "csv-import")]
+    pub fn import_csv<T: Object, R: std::io::Read>(
+        &self,
+        reader: R,
+        opts: crate::import::CsvImportOptions,
+    ) -> Result<crate::import::ImportResult> {
+        let schema = self.describe::<T>();
+        self.check_writable(&schema)?;
+        self.ensure_table(&schema)?;
+        crate::import::import_csv(self.inner.as_ref(), &schema, reader, opts)
+    }
+
+    // Inserts a row per element of a JSON array of objects keyed by column
+    // name, without loading the objects. A missing key reads as null.
+    #[cfg(feature = "serde-json")]
+    pub fn import_json<T: Object>(&self, json: &str) -> Result<crate::import::ImportResult> {
+        let schema = self.describe::<T>();
+        self.check_writable(&schema)?;
+        self.ensure_table(&schema)?;
+        crate::import::import_json(self.inner.as_ref(), &schema, json)
+    }
+
+    // Streams the rows of T in id order, without loading the objects, so
+    // objects modified in this transaction are written as they were before.
+    #[cfg(feature = "csv-import")]
+    pub fn export_csv<T: Object, W: std::io::Write>(
+        &self,
+        writer: W,
+        opts: crate::export::CsvExportOptions,
+    ) -> Result<u64> {
+        let schema = self.describe::<T>();
+        self.ensure_table(&schema)?;
+        let (predicate, params) = self
+            .row_predicate(&schema)?
+            .unwrap_or_else(|| ("1".to_owned(), Vec::new()));
+        crate::export::export_csv(
+            self.inner.as_ref(),
+            &schema,
+            &predicate,
+            &params,
+            writer,
+            opts,
+        )
+    }
+
+    pub fn rename_table(&self, from: &str, to: &str) -> Result<()> {
+        object::check_identifier(to)?;
+        self.inner.rename_table(from, to)
+    }
+
+    // ALTER TABLE ... RENAME COLUMN needs SQLite 3.25.0.
+    pub fn rename_column(&self, table: &str, from: &str, to: &str) -> Result<()> {
+        object::check_identifier(to)?;
+        self.require_sqlite_version(table, (3, 25, 0))?;
+        self.inner.rename_column(table, from, to)
+    }
+
+    pub fn analyze<T: Object>(&self) -> Result<()> {
+        self.inner.analyze(self.table_name::<T>())
+    }
+
+    // Analyzes the tables of all the objects in the cache.
+    pub fn analyze_all(&self) -> Result<()> {
+        let tables = self
+            .cache
+            .borrow()
+            .values()
+            .map(|object| {
+                object
+                    .deref()
+                    .borrow()
+                    .describe()
+                    .with_table_naming(self.table_naming)
+                    .get_table_name()
+            })
+            .collect::<HashSet<_>>();
+        for table in tables {
+            self.inner.analyze(table)?;
+        }
+        Ok(())
     }
 
     pub fn commit(self) -> Result<()> {
+        self.commit_summarized()?;
+        Ok(())
+    }
+
+    // Commits, returning what was written; see CommitSummary.
+    pub fn commit_summarized(self) -> Result<CommitSummary> {
+        if let Some(parent) = self.parent {
+            parent.check_nested_conflicts(&self)?;
+        }
+        for (id, state) in self.states.borrow().iter() {
+            if *state.deref().borrow() != ObjectState::Clean {
+                self.check_writable(
+                    &self
+                        .cache
+                        .borrow()
+                        .get(id)
+                        .unwrap()
+                        .deref()
+                        .borrow()
+                        .describe()
+                        .with_table_naming(self.table_naming),
+                )?;
+            }
+        }
+        let mut updated = Vec::new();
+        let mut deleted = Vec::new();
         for (id, state) in self.states.borrow().iter() {
             let cache = self.cache.borrow();
             match *state.deref().borrow() {
                 ObjectState::Modified => {
                     let object = cache.get(id).unwrap().deref().borrow();
                     let row = object.as_row();
-                    let schema = object.describe();
+                    let schema = object.describe().with_table_naming(self.table_naming);
+                    schema.check_object_row(row.as_slice())?;
                     self.inner.update_row(*id, &schema, row.as_slice())?;
+                    updated.push((schema.get_table_name(), *id));
                 }
                 ObjectState::Removed => {
                     let object = cache.get(id).unwrap().deref().borrow();
-                    let schema = object.describe();
+                    let schema = object.describe().with_table_naming(self.table_naming);
                     self.inner.delete_row(*id, &schema)?;
+                    deleted.push((schema.get_table_name(), *id));
+                }
+                ObjectState::Clean | ObjectState::RolledBack => (),
+            }
+        }
+        if let Some(threshold) = self.analyze_threshold {
+            let mut counts = HashMap::new();
+            for (table, _) in self.inserted.borrow().iter() {
+                *counts.entry(*table).or_insert(0) += 1;
+            }
+            for (table, inserted) in counts {
+                if inserted > threshold {
+                    self.inner.analyze(table)?;
                 }
-                ObjectState::Clean => (),
             }
         }
-        self.inner.commit()
+        self.inner.commit()?;
+        if let Some(parent) = self.parent {
+            parent.merge_nested(&self);
+        }
+        updated.sort_by_key(|&(table, id)| (table, id.into_i64()));
+        deleted.sort_by_key(|&(table, id)| (table, id.into_i64()));
+        let summary = CommitSummary {
+            inserted: self.inserted.take(),
+            updated,
+            deleted,
+            duration: self.started.elapsed(),
+        };
+        #[cfg(feature = "tracing")]
+        {
+            let span = self.inner.span();
+            span.record("inserted", summary.inserted.len());
+            span.record("updated", summary.updated.len());
+            span.record("deleted", summary.deleted.len());
+            span.record("duration_us", summary.duration.as_micros() as u64);
+        }
+        Ok(summary)
     }
 
+    // Handles which somehow outlive the transaction see its objects as
+    // RolledBack: still readable, but no longer writable.
     pub fn rollback(self) -> Result<()> {
+        for state in self.states.borrow().values() {
+            *state.borrow_mut() = ObjectState::RolledBack;
+        }
         self.inner.rollback()
     }
+
+    // The yorm.transaction span, with every statement of the transaction as
+    // a debug event; entering it puts the caller's own events there as well.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        self.inner.span()
+    }
+}
+
+// Rows after values in the keys ordering: greater in the first key, or equal
+// in it and greater in the second one, and so on.
+fn keyset_filter(keys: &[(&str, Direction)], values: &RowSlice<'static>) -> Filter {
+    Filter::Or(
+        (0..keys.len())
+            .map(|i| {
+                let mut terms = keys[..i]
+                    .iter()
+                    .zip(values)
+                    .map(|((column, _), value)| Filter::eq(column, value.clone()))
+                    .collect::<Vec<_>>();
+                let (column, direction) = keys[i];
+                terms.push(match direction {
+                    Direction::Asc => Filter::gt(column, values[i].clone()),
+                    Direction::Desc => Filter::lt(column, values[i].clone()),
+                });
+                Filter::And(terms)
+            })
+            .collect(),
+    )
+}
+
+fn query_column<A: FromValue>(row: &mut Row<'static>, column: usize) -> Result<A> {
+    let value = std::mem::replace(&mut row[column], Value::Null);
+    let data_type = value.data_type();
+    A::from_value(value).ok_or_else(|| {
+        Error::QueryColumn(Box::new(QueryColumnError {
+            column,
+            expected: A::TYPE_NAME,
+            got: data_type.map_or("Null".to_owned(), |data_type| format!("{:?}", data_type)),
+        }))
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Row-level access to a table described by a runtime Schema.
+// Unlike Tx, rows aren't cached: every call goes straight to the storage,
+// so a DynTable doesn't see typed objects modified in the same transaction
+// until they are written on commit, and such objects may overwrite rows
+// changed through a DynTable.
+pub struct DynTable<'t, 'a> {
+    transaction: &'t Transaction<'a>,
+    schema: Schema,
+}
+
+impl<'t, 'a> DynTable<'t, 'a> {
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn insert(&self, row: &RowSlice) -> Result<ObjectId> {
+        self.schema.check_row(row)?;
+        self.transaction.inner.insert_row(&self.schema, row)
+    }
+
+    pub fn get(&self, id: ObjectId) -> Result<Row<'static>> {
+        self.transaction.inner.select_row(id, &self.schema)
+    }
+
+    pub fn update(&self, id: ObjectId, row: &RowSlice) -> Result<()> {
+        self.schema.check_row(row)?;
+        self.transaction.inner.update_row(id, &self.schema, row)
+    }
+
+    pub fn delete(&self, id: ObjectId) -> Result<()> {
+        self.transaction.inner.delete_row(id, &self.schema)
+    }
+}
+
+#[macro_export]
+macro_rules! ensure_tables {
+    ($tx:expr, $($type:ty),+ $(,)?) => {
+        $tx.ensure_tables(&[$(<$type as $crate::Object>::describe()),+])
+    };
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// What a transaction wrote, by table name and id. Only the objects of the
+// transaction are listed: not the rows written by DynTable, the imports,
+// update_if or the deletes by predicate.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CommitSummary {
+    // Objects created, in order; also listed as deleted if removed afterwards.
+    pub inserted: Vec<(&'static str, ObjectId)>,
+    // Modified objects, sorted, which were written on commit.
+    pub updated: Vec<(&'static str, ObjectId)>,
+    // Removed objects, sorted.
+    pub deleted: Vec<(&'static str, ObjectId)>,
+    // From the beginning of the transaction to the end of the commit.
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ColumnStats {
+    // Values which aren't NULL, including the non-finite ones.
+    pub count: u64,
+    pub null_count: u64,
+    pub non_finite_count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub sum: f64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheEventType {
+    Hit,
+    Miss,
+    Evicted,
+    Added,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CacheEvent {
+    pub event_type: CacheEventType,
+    pub id: ObjectId,
+    pub type_name: &'static str,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -121,23 +1399,36 @@ pub enum ObjectState {
     Clean,
     Modified,
     Removed,
+    RolledBack,
 }
 
+// A handle to an object living in a transaction. It is neither Send nor
+// Unpin: like the Refs it hands out, it must not be held across .await
+// points in async code.
 #[derive(Clone)]
 pub struct Tx<'a, T> {
     state: Rc<RefCell<ObjectState>>,
     object: Rc<RefCell<dyn Store>>,
     id: ObjectId,
+    table_name: &'static str,
     lifetime: PhantomData<&'a T>,
+    _pinned: PhantomPinned,
 }
 
 impl<'a, T: Any> Tx<'a, T> {
-    fn new(object: Rc<RefCell<dyn Store>>, id: ObjectId, state: Rc<RefCell<ObjectState>>) -> Self {
+    fn new(
+        object: Rc<RefCell<dyn Store>>,
+        id: ObjectId,
+        state: Rc<RefCell<ObjectState>>,
+        table_name: &'static str,
+    ) -> Self {
         Self {
             state,
             object,
             id,
+            table_name,
             lifetime: PhantomData,
+            _pinned: PhantomPinned,
         }
     }
     pub fn id(&self) -> ObjectId {
@@ -148,6 +1439,38 @@ impl<'a, T: Any> Tx<'a, T> {
         *self.state.deref().borrow()
     }
 
+    pub fn with_row<R, F: FnOnce(&RowSlice) -> R>(&self, f: F) -> R {
+        with_row(&self.object, &self.state, f)
+    }
+
+    pub fn downgrade(&self) -> TxWeak<T> {
+        TxWeak {
+            state: Rc::downgrade(&self.state),
+            object: Rc::downgrade(&self.object),
+            id: self.id,
+            table_name: self.table_name,
+            object_type: PhantomData,
+        }
+    }
+
+    // Unbinds the handle from its transaction, for tests of handles which
+    // outlive it; normal code can't get such a handle.
+    #[cfg(test)]
+    pub(crate) fn detach(self) -> Tx<'static, T> {
+        Tx::new(self.object, self.id, self.state, self.table_name)
+    }
+
+    pub fn into_any(self) -> AnyTx<'a> {
+        AnyTx {
+            state: self.state,
+            object: self.object,
+            id: self.id,
+            table_name: self.table_name,
+            lifetime: PhantomData,
+            _pinned: PhantomPinned,
+        }
+    }
+
     pub fn borrow(&self) -> Ref<'_, T> {
         if *self.state.deref().borrow() == ObjectState::Removed {
             panic!("cannot borrow a removed object")
@@ -158,18 +1481,246 @@ impl<'a, T: Any> Tx<'a, T> {
 
     pub fn borrow_mut(&self) -> RefMut<'_, T> {
         // self.object.deref().borrow_mut()
-        if *self.state.deref().borrow() == ObjectState::Removed {
-            panic!("cannot borrow a removed object")
+        match *self.state.deref().borrow() {
+            ObjectState::Removed => panic!("cannot borrow a removed object"),
+            ObjectState::RolledBack => {
+                panic!("cannot mutably borrow an object of a rolled back transaction")
+            }
+            _ => (),
         }
         *self.state.borrow_mut() = ObjectState::Modified;
         let borrowed = self.object.deref().borrow_mut();
         RefMut::map(borrowed, |x| x.as_mut_any().downcast_mut::<T>().unwrap())
     }
 
+    // For objects already written some other way, e.g. with raw SQL,
+    // so that commit doesn't write them again.
+    pub fn mark_clean(&self) {
+        match *self.state.deref().borrow() {
+            ObjectState::Removed => panic!("cannot mark a removed object clean"),
+            ObjectState::RolledBack => {
+                panic!("cannot mark an object of a rolled back transaction clean")
+            }
+            _ => (),
+        }
+        *self.state.borrow_mut() = ObjectState::Clean;
+    }
+
     pub fn delete(self) {
         if self.object.try_borrow_mut().is_err() {
             panic!("cannot delete a borrowed object")
         }
+        if *self.state.deref().borrow() == ObjectState::RolledBack {
+            panic!("cannot delete an object of a rolled back transaction")
+        }
         *self.state.borrow_mut() = ObjectState::Removed;
     }
 }
+
+// Handles of the same row are equal, whether or not they came from the same
+// call; the object itself isn't compared, so modifying it through a handle
+// kept in a HashSet doesn't change its hash.
+impl<T> PartialEq for Tx<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Tx<'_, T> {}
+
+impl<T> Hash for Tx<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+// A weak Tx, which doesn't keep the object alive. Unlike Tx it isn't bound
+// to the lifetime of the transaction, so objects can hold one to refer to
+// each other without creating a reference cycle.
+pub struct TxWeak<T> {
+    state: Weak<RefCell<ObjectState>>,
+    object: Weak<RefCell<dyn Store>>,
+    id: ObjectId,
+    table_name: &'static str,
+    object_type: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for TxWeak<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            object: self.object.clone(),
+            id: self.id,
+            table_name: self.table_name,
+            object_type: PhantomData,
+        }
+    }
+}
+
+impl<T: Any> TxWeak<T> {
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+
+    // The handle is bound to tx, so it can't outlive the transaction, the
+    // same as the Tx it was downgraded from. None once the transaction has
+    // ended, if the object was removed, or if it isn't an object of tx.
+    pub fn upgrade<'t>(&self, tx: &'t Transaction<'_>) -> Option<Tx<'t, T>> {
+        let state = self.state.upgrade()?;
+        let object = self.object.upgrade()?;
+        if *state.deref().borrow() == ObjectState::Removed {
+            return None;
+        }
+        let cached = tx
+            .cache
+            .borrow()
+            .get(&self.id)
+            .is_some_and(|cached| Rc::ptr_eq(cached, &object));
+        if !cached {
+            return None;
+        }
+        Some(Tx::new(object, self.id, state, self.table_name))
+    }
+}
+
+// Passes the row of a cached object to f, without knowing its type.
+fn with_row<R, F: FnOnce(&RowSlice) -> R>(
+    object: &Rc<RefCell<dyn Store>>,
+    state: &Rc<RefCell<ObjectState>>,
+    f: F,
+) -> R {
+    if *state.deref().borrow() == ObjectState::Removed {
+        panic!("cannot borrow a removed object")
+    }
+    let object = object.deref().borrow();
+    f(object.as_row().as_slice())
+}
+
+// A Tx of an unknown type, for code which only needs the row of an object.
+// Like Tx, it must not be held across .await points.
+#[derive(Clone)]
+pub struct AnyTx<'a> {
+    state: Rc<RefCell<ObjectState>>,
+    object: Rc<RefCell<dyn Store>>,
+    id: ObjectId,
+    table_name: &'static str,
+    lifetime: PhantomData<&'a ()>,
+    _pinned: PhantomPinned,
+}
+
+impl<'a> AnyTx<'a> {
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+
+    pub fn state(&self) -> ObjectState {
+        *self.state.deref().borrow()
+    }
+
+    pub fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+
+    pub fn with_row<R, F: FnOnce(&RowSlice) -> R>(&self, f: F) -> R {
+        with_row(&self.object, &self.state, f)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Handles can't outlive their transaction outside of the crate, so what a
+// rolled back one does is only testable here, with Tx::detach.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Connection;
+
+    struct Note {
+        text: String,
+    }
+
+    impl Object for Note {
+        fn as_row(&self) -> Row {
+            vec![self.text.clone().into()]
+        }
+        fn from_row(mut row: Row) -> Self {
+            Self {
+                text: row.pop().unwrap().into(),
+            }
+        }
+        fn table_name() -> &'static str {
+            "Note"
+        }
+        fn type_name() -> &'static str {
+            "Note"
+        }
+        fn field_names() -> Vec<&'static str> {
+            vec!["text"]
+        }
+        fn column_names() -> Vec<&'static str> {
+            vec!["text"]
+        }
+        fn column_types() -> Vec<DataType> {
+            vec![DataType::String]
+        }
+    }
+
+    fn rolled_back_note(conn: &mut Connection) -> Tx<'static, Note> {
+        let tx = conn.new_transaction().unwrap();
+        let note = tx
+            .create(Note {
+                text: "draft".into(),
+            })
+            .unwrap()
+            .detach();
+        tx.rollback().unwrap();
+        note
+    }
+
+    #[test]
+    fn rolled_back_handles() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.new_transaction().unwrap();
+        let note = tx
+            .create(Note {
+                text: "draft".into(),
+            })
+            .unwrap()
+            .detach();
+        let id = note.id();
+        let weak = note.downgrade();
+        tx.rollback().unwrap();
+
+        assert_eq!(note.id(), id);
+        assert!(note.state() == ObjectState::RolledBack);
+        assert_eq!(note.borrow().text, "draft");
+        assert_eq!(note.with_row(|row| row.len()), 1);
+        let tx = conn.new_transaction().unwrap();
+        assert!(weak.upgrade(&tx).is_none());
+        assert!(note.downgrade().upgrade(&tx).is_none());
+        let any = note.into_any();
+        assert!(any.state() == ObjectState::RolledBack);
+        assert_eq!(any.with_row(|row| row.len()), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot mutably borrow an object of a rolled back transaction")]
+    fn rolled_back_borrow_mut_panics() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        rolled_back_note(&mut conn).borrow_mut().text.clear();
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot mark an object of a rolled back transaction clean")]
+    fn rolled_back_mark_clean_panics() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        rolled_back_note(&mut conn).mark_clean();
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot delete an object of a rolled back transaction")]
+    fn rolled_back_delete_panics() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        rolled_back_note(&mut conn).delete();
+    }
+}
@@ -0,0 +1,40 @@
+impl :: orm :: object :: Object for Checked
+{
+    fn as_row(& self) -> :: orm :: storage :: Row
+    {
+        vec!
+        [self.login.clone().into(), self.login_length.clone().into(),
+        self.age.clone().into(),]
+    } fn from_row(mut row : :: orm :: storage :: Row) -> Self
+    {
+        Self
+        {
+            age : row.pop().unwrap().into(), login_length :
+            row.pop().unwrap().into(), login : row.pop().unwrap().into(),
+        }
+    } fn table_name() -> & 'static str { stringify! (Checked) } fn type_name()
+    -> & 'static str { stringify! (Checked) } fn has_explicit_table_name() ->
+    bool { false } fn field_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (login), stringify! (login_length), stringify! (age),]
+    } fn column_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (login), stringify! (login_length), stringify! (age),]
+    } fn column_types() -> :: std :: vec :: Vec < :: orm :: data :: DataType >
+    {
+        vec!
+        [stringify! (String).into(), stringify! (i64).into(), stringify!
+        (i64).into(),]
+    } fn column_readonly() -> :: std :: vec :: Vec < bool >
+    { vec! [false, true, false,] } fn column_nullability() -> :: std :: vec ::
+    Vec < bool > { vec! [false, false, false,] } fn is_fieldless() -> bool
+    { false } fn indexes() -> :: std :: vec :: Vec < :: orm :: object :: Index
+    > { vec! [] } fn is_strict() -> bool { false } fn is_existing_table() ->
+    bool { false } fn checks() -> :: std :: vec :: Vec < & 'static str >
+    { vec! ["length(login) > 0", "age >= 0",] } fn visible_when() -> :: std ::
+    option :: Option < & 'static str > { None } fn expires_at() -> :: std ::
+    option :: Option < & 'static str > { None } fn connection_type() -> :: orm
+    :: ConnectionType { :: orm :: ConnectionType :: ReadWrite }
+}
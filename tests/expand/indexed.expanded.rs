@@ -0,0 +1,36 @@
+impl :: orm :: object :: Object for Indexed
+{
+    fn as_row(& self) -> :: orm :: storage :: Row
+    { vec! [self.login.clone().into(), self.active.clone().into(),] } fn
+    from_row(mut row : :: orm :: storage :: Row) -> Self
+    {
+        Self
+        {
+            active : row.pop().unwrap().into(), login :
+            row.pop().unwrap().into(),
+        }
+    } fn table_name() -> & 'static str { stringify! (Indexed) } fn type_name()
+    -> & 'static str { stringify! (Indexed) } fn has_explicit_table_name() ->
+    bool { false } fn field_names() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [stringify! (login), stringify! (active),] } fn column_names() ->
+    :: std :: vec :: Vec < & 'static str >
+    { vec! [stringify! (login), stringify! (active),] } fn column_types() ->
+    :: std :: vec :: Vec < :: orm :: data :: DataType >
+    { vec! [stringify! (String).into(), stringify! (bool).into(),] } fn
+    column_readonly() -> :: std :: vec :: Vec < bool >
+    { vec! [false, false,] } fn column_nullability() -> :: std :: vec :: Vec <
+    bool > { vec! [false, false,] } fn is_fieldless() -> bool { false } fn
+    indexes() -> :: std :: vec :: Vec < :: orm :: object :: Index >
+    {
+        vec!
+        [:: orm :: object :: Index
+        { columns : vec! ["login",], predicate : None, }, :: orm :: object ::
+        Index
+        { columns : vec! ["active",], predicate : Some("active = 1"), },]
+    } fn is_strict() -> bool { false } fn is_existing_table() -> bool
+    { false } fn checks() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn visible_when() -> :: std :: option :: Option < & 'static
+    str > { None } fn expires_at() -> :: std :: option :: Option < & 'static
+    str > { None } fn connection_type() -> :: orm :: ConnectionType
+    { :: orm :: ConnectionType :: ReadWrite }
+}
@@ -0,0 +1,40 @@
+impl :: orm :: object :: Object for Options
+{
+    fn as_row(& self) -> :: orm :: storage :: Row
+    {
+        vec!
+        [self.title.clone().into(), self.tenant_id.clone().into(),
+        self.expires.clone().into(),]
+    } fn from_row(mut row : :: orm :: storage :: Row) -> Self
+    {
+        Self
+        {
+            expires : row.pop().unwrap().into(), tenant_id :
+            row.pop().unwrap().into(), title : row.pop().unwrap().into(),
+        }
+    } fn table_name() -> & 'static str { stringify! (Options) } fn type_name()
+    -> & 'static str { stringify! (Options) } fn has_explicit_table_name() ->
+    bool { false } fn field_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (title), stringify! (tenant_id), stringify! (expires),]
+    } fn column_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (title), stringify! (tenant_id), stringify! (expires),]
+    } fn column_types() -> :: std :: vec :: Vec < :: orm :: data :: DataType >
+    {
+        vec!
+        [stringify! (String).into(), stringify! (i64).into(), stringify!
+        (i64).into(),]
+    } fn column_readonly() -> :: std :: vec :: Vec < bool >
+    { vec! [false, false, false,] } fn column_nullability() -> :: std :: vec
+    :: Vec < bool > { vec! [false, false, false,] } fn is_fieldless() -> bool
+    { false } fn indexes() -> :: std :: vec :: Vec < :: orm :: object :: Index
+    > { vec! [] } fn is_strict() -> bool { true } fn is_existing_table() ->
+    bool { false } fn checks() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn visible_when() -> :: std :: option :: Option < & 'static
+    str > { Some("tenant_id = :tenant") } fn expires_at() -> :: std :: option
+    :: Option < & 'static str > { Some("expires") } fn connection_type() -> ::
+    orm :: ConnectionType { :: orm :: ConnectionType :: ReadOnly }
+}
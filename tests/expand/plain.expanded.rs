@@ -0,0 +1,47 @@
+impl :: orm :: object :: Object for Plain
+{
+    fn as_row(& self) -> :: orm :: storage :: Row
+    {
+        vec!
+        [self.name.clone().into(), self.picture.clone().into(),
+        self.visits.clone().into(), self.balance.clone().into(),
+        self.is_admin.clone().into(),]
+    } fn from_row(mut row : :: orm :: storage :: Row) -> Self
+    {
+        Self
+        {
+            is_admin : row.pop().unwrap().into(), balance :
+            row.pop().unwrap().into(), visits : row.pop().unwrap().into(),
+            picture : row.pop().unwrap().into(), name :
+            row.pop().unwrap().into(),
+        }
+    } fn table_name() -> & 'static str { stringify! (Plain) } fn type_name()
+    -> & 'static str { stringify! (Plain) } fn has_explicit_table_name() ->
+    bool { false } fn field_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (name), stringify! (picture), stringify! (visits),
+        stringify! (balance), stringify! (is_admin),]
+    } fn column_names() -> :: std :: vec :: Vec < & 'static str >
+    {
+        vec!
+        [stringify! (name), stringify! (picture), stringify! (visits),
+        stringify! (balance), stringify! (is_admin),]
+    } fn column_types() -> :: std :: vec :: Vec < :: orm :: data :: DataType >
+    {
+        vec!
+        [stringify! (String).into(), stringify! (Vec < u8 >).into(),
+        stringify! (i64).into(), stringify! (f64).into(), stringify!
+        (bool).into(),]
+    } fn column_readonly() -> :: std :: vec :: Vec < bool >
+    { vec! [false, false, false, false, false,] } fn column_nullability() ->
+    :: std :: vec :: Vec < bool >
+    { vec! [false, false, false, false, false,] } fn is_fieldless() -> bool
+    { false } fn indexes() -> :: std :: vec :: Vec < :: orm :: object :: Index
+    > { vec! [] } fn is_strict() -> bool { false } fn is_existing_table() ->
+    bool { false } fn checks() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn visible_when() -> :: std :: option :: Option < & 'static
+    str > { None } fn expires_at() -> :: std :: option :: Option < & 'static
+    str > { None } fn connection_type() -> :: orm :: ConnectionType
+    { :: orm :: ConnectionType :: ReadWrite }
+}
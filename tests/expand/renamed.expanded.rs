@@ -0,0 +1,23 @@
+impl :: orm :: object :: Object for Renamed
+{
+    fn as_row(& self) -> :: orm :: storage :: Row
+    { vec! [self.is_tall.clone().into(),] } fn
+    from_row(mut row : :: orm :: storage :: Row) -> Self
+    { Self { is_tall : row.pop().unwrap().into(), } } fn table_name() -> &
+    'static str { stringify! (plain_renamed) } fn type_name() -> & 'static str
+    { stringify! (Renamed) } fn has_explicit_table_name() -> bool { true } fn
+    field_names() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [stringify! (is_tall),] } fn column_names() -> :: std :: vec :: Vec
+    < & 'static str > { vec! [stringify! (IsTall),] } fn column_types() -> ::
+    std :: vec :: Vec < :: orm :: data :: DataType >
+    { vec! [stringify! (bool).into(),] } fn column_readonly() -> :: std :: vec
+    :: Vec < bool > { vec! [false,] } fn column_nullability() -> :: std :: vec
+    :: Vec < bool > { vec! [false,] } fn is_fieldless() -> bool { false } fn
+    indexes() -> :: std :: vec :: Vec < :: orm :: object :: Index >
+    { vec! [] } fn is_strict() -> bool { false } fn is_existing_table() ->
+    bool { false } fn checks() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn visible_when() -> :: std :: option :: Option < & 'static
+    str > { None } fn expires_at() -> :: std :: option :: Option < & 'static
+    str > { None } fn connection_type() -> :: orm :: ConnectionType
+    { :: orm :: ConnectionType :: ReadWrite }
+}
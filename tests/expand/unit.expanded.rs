@@ -0,0 +1,19 @@
+impl :: orm :: object :: Object for Unit
+{
+    fn as_row(& self) -> :: orm :: storage :: Row { vec! [] } fn
+    from_row(mut row : :: orm :: storage :: Row) -> Self { Self {} } fn
+    table_name() -> & 'static str { stringify! (Unit) } fn type_name() -> &
+    'static str { stringify! (Unit) } fn has_explicit_table_name() -> bool
+    { false } fn field_names() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn column_names() -> :: std :: vec :: Vec < & 'static str >
+    { vec! [] } fn column_types() -> :: std :: vec :: Vec < :: orm :: data ::
+    DataType > { vec! [] } fn column_readonly() -> :: std :: vec :: Vec < bool
+    > { vec! [] } fn column_nullability() -> :: std :: vec :: Vec < bool >
+    { vec! [] } fn is_fieldless() -> bool { true } fn indexes() -> :: std ::
+    vec :: Vec < :: orm :: object :: Index > { vec! [] } fn is_strict() ->
+    bool { false } fn is_existing_table() -> bool { false } fn checks() -> ::
+    std :: vec :: Vec < & 'static str > { vec! [] } fn visible_when() -> ::
+    std :: option :: Option < & 'static str > { None } fn expires_at() -> ::
+    std :: option :: Option < & 'static str > { None } fn connection_type() ->
+    :: orm :: ConnectionType { :: orm :: ConnectionType :: ReadWrite }
+}
@@ -1,4 +1,7 @@
-use orm::{data::DataType, Connection, Object, ObjectId, ObjectState, Result, Tx};
+use orm::{
+    data::{DataType, Value},
+    Connection, ConnectionType, Object, ObjectId, ObjectState, Result, Tx,
+};
 
 use rusqlite::params;
 use tempfile::NamedTempFile;
@@ -393,6 +396,7 @@ fn unexpected_type() {
             assert_eq!(err.column_name, "is_admin");
             assert_eq!(err.expected_type, DataType::Bool);
             assert_eq!(err.got_type, "Text");
+            assert_eq!(err.value, "'true'");
         }
         res => panic!(
             "expecter Error::IncorrectType at get(), got {}",
@@ -805,6 +809,4367 @@ fn rollback() {
     assert_eq!(tx_user.borrow().balance, 220.);
 }
 //
+struct Profile {
+    login: String,
+    nickname: Option<String>,
+}
+
+impl Object for Profile {
+    fn as_row(&self) -> orm::storage::Row {
+        vec![
+            self.login.clone().into(),
+            match &self.nickname {
+                Some(nickname) => nickname.clone().into(),
+                None => Value::Null,
+            },
+        ]
+    }
+
+    fn from_row(mut row: orm::storage::Row) -> Self {
+        let nickname = match row.pop().unwrap() {
+            Value::Null => None,
+            value => Some(value.into()),
+        };
+        Self {
+            login: row.pop().unwrap().into(),
+            nickname,
+        }
+    }
+
+    fn table_name() -> &'static str {
+        "Profile"
+    }
+    fn type_name() -> &'static str {
+        "Profile"
+    }
+
+    fn field_names() -> Vec<&'static str> {
+        vec!["login", "nickname"]
+    }
+    fn column_names() -> Vec<&'static str> {
+        vec!["login", "nickname"]
+    }
+    fn column_types() -> Vec<DataType> {
+        vec![DataType::String, DataType::String]
+    }
+    fn column_nullability() -> Vec<bool> {
+        vec![false, true]
+    }
+}
+
+#[test]
+fn null_binding() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let anonymous_id = tx
+        .create(Profile {
+            login: "anonymous".into(),
+            nickname: None,
+        })
+        .unwrap()
+        .id();
+    let nicknamed = tx
+        .create(Profile {
+            login: "lizzie".into(),
+            nickname: Some("Liz".into()),
+        })
+        .unwrap();
+    let nicknamed_id = nicknamed.id();
+    nicknamed.borrow_mut().nickname = None;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let anonymous = tx.get::<Profile>(anonymous_id).unwrap();
+    assert_eq!(anonymous.borrow().login, "anonymous");
+    assert_eq!(anonymous.borrow().nickname, None);
+
+    let nicknamed = tx.get::<Profile>(nicknamed_id).unwrap();
+    assert_eq!(nicknamed.borrow().login, "lizzie");
+    assert_eq!(nicknamed.borrow().nickname, None);
+}
+//
+#[test]
+fn duplicate() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let user_id = tx
+        .create(User {
+            name: "Martha".into(),
+            picture: b"wq9u0dsf8vhasdv"[..].into(),
+            visits: 17,
+            balance: 150.,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx.get::<User>(user_id).unwrap();
+    tx_user.borrow_mut().visits = 18;
+
+    let tx_copy = tx.duplicate::<User>(user_id).unwrap();
+    assert_ne!(tx_copy.id(), user_id);
+    assert_eq!(*tx_copy.borrow(), *tx_user.borrow());
+
+    let tx_renamed = tx
+        .duplicate_with(user_id, |user: &mut User| user.name = "Marta".into())
+        .unwrap();
+    assert_eq!(tx_renamed.borrow().name, "Marta");
+    assert_eq!(tx_renamed.borrow().visits, 18);
+
+    let (copy_id, renamed_id) = (tx_copy.id(), tx_renamed.id());
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_copy = tx.get::<User>(copy_id).unwrap();
+    assert_eq!(tx_copy.borrow().name, "Martha");
+    assert_eq!(tx_copy.borrow().visits, 18);
+    let tx_renamed = tx.get::<User>(renamed_id).unwrap();
+    assert_eq!(tx_renamed.borrow().name, "Marta");
+    assert_eq!(tx.get::<User>(user_id).unwrap().borrow().visits, 18);
+
+    assert_not_found(tx.duplicate::<User>(12345.into()), 12345.into(), "User");
+}
+//
+#[cfg(feature = "serde-json")]
+#[test]
+fn describe_json() {
+    assert_eq!(
+        Order::describe_json(),
+        serde_json::json!({
+            "table": "order_table",
+            "columns": [{"name": "IsTall", "type": "TINYINT", "not_null": true}],
+        })
+    );
+    assert_eq!(
+        Profile::describe_json()["columns"][1],
+        serde_json::json!({"name": "nickname", "type": "TEXT", "not_null": false})
+    );
+}
+//
+#[test]
+fn rename_table_and_column() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE legacy_order (id INTEGER PRIMARY KEY AUTOINCREMENT, tall TINYINT)",
+            [],
+        )
+        .unwrap();
+    sqlite_conn
+        .execute("INSERT INTO legacy_order VALUES (?, ?)", params![1, true])
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    let mut orm_conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = orm_conn.new_transaction().unwrap();
+    tx.rename_table("legacy_order", "order_table").unwrap();
+    tx.rename_column("order_table", "tall", "IsTall").unwrap();
+    tx.commit().unwrap();
+
+    let tx = orm_conn.new_transaction().unwrap();
+    assert!(tx.get::<Order>(1.into()).unwrap().borrow().is_tall);
+
+    for res in [
+        tx.rename_table("legacy_order", "order_table"),
+        tx.rename_column("legacy_order", "tall", "IsTall"),
+    ] {
+        match res {
+            Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "legacy_order"),
+            res => panic!("expected Error::TableNotFound, got {}", fmt_res(&res)),
+        }
+    }
+
+    // Keywords are quoted, in both directions.
+    tx.rename_table("order_table", "order").unwrap();
+    tx.rename_column("order", "IsTall", "select").unwrap();
+    let tall = tx
+        .query_scalar1::<bool>("SELECT \"select\" FROM \"order\"", &[])
+        .unwrap();
+    assert_eq!(tall, [true]);
+    tx.rename_column("order", "select", "IsTall").unwrap();
+    tx.rename_table("order", "order_table").unwrap();
+    assert!(tx.get::<Order>(1.into()).unwrap().borrow().is_tall);
+}
+//
+#[test]
+fn try_from_row() {
+    let schema = User::describe();
+    let row = |is_admin: Value<'static>| {
+        vec![
+            String::from("Ann").into(),
+            b"zxcv"[..].to_vec().into(),
+            10.into(),
+            15.5.into(),
+            is_admin,
+        ]
+    };
+
+    let user = User::try_from_row(&schema, row(true.into())).unwrap();
+    assert_eq!(user.name, "Ann");
+    assert!(user.is_admin);
+
+    match User::try_from_row(&schema, row(1.into())) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, "User");
+            assert_eq!(err.attr_name, "is_admin");
+            assert_eq!(err.column_name, "is_admin");
+            assert_eq!(err.expected_type, DataType::Bool);
+            assert_eq!(err.got_type, "Int64");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+
+    match User::try_from_row(&schema, row(Value::Null)) {
+        Err(orm::Error::UnexpectedType(err)) => assert_eq!(err.got_type, "Null"),
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+
+    let mut short_row = row(true.into());
+    short_row.truncate(2);
+    match User::try_from_row(&schema, short_row) {
+        Err(orm::Error::MissingColumn(err)) => {
+            assert_eq!(err.attr_name, "visits");
+            assert_eq!(err.column_name, "visits");
+        }
+        res => panic!("expected Error::MissingColumn, got {}", fmt_res(&res)),
+    }
+}
+//
+#[cfg(feature = "test-util")]
+#[test]
+fn schema_matches_sql() {
+    orm::assert_schema_matches!(
+        Order,
+        "CREATE TABLE order_table (id INTEGER PRIMARY KEY AUTOINCREMENT, IsTall TINYINT)"
+    );
+    orm::assert_schema_matches!(
+        User,
+        "CREATE TABLE \"User\" (\
+            id INTEGER PRIMARY KEY AUTOINCREMENT,\
+            name TEXT,\
+            picture BLOB,\
+            visits BIGINT,\
+            balance REAL,\
+            is_admin TINYINT\
+        )"
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn schema_mismatches_sql() {
+    let mismatches = orm::test_util::schema_mismatches(
+        &Order::describe(),
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY AUTOINCREMENT, IsTall TEXT, size BIGINT)",
+    );
+    assert_eq!(
+        mismatches,
+        [
+            "table name: Rust has 'order_table', SQL has 'orders'",
+            "column #1: Rust has 'IsTall TINYINT', SQL has 'IsTall TEXT'",
+            "column #2: Rust has nothing, SQL has 'size BIGINT'",
+        ]
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+#[should_panic(expected = "schema of Order doesn't match SQL")]
+fn schema_mismatch_panics() {
+    orm::assert_schema_matches!(Order, "CREATE TABLE order_table (id INTEGER PRIMARY KEY)");
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn object_round_trip_eq() {
+    use orm::test_util::{assert_object_eq, object_mismatches};
+
+    let user = || User {
+        name: "Alice".into(),
+        picture: vec![1, 2, 3],
+        visits: 7,
+        balance: 1.5,
+        is_admin: false,
+    };
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(user()).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let loaded = tx.get::<User>(id).unwrap();
+    assert_object_eq(&*loaded.borrow(), &user());
+
+    let other = User {
+        visits: 8,
+        is_admin: true,
+        ..user()
+    };
+    assert_eq!(
+        object_mismatches(&*loaded.borrow(), &other),
+        [
+            "field 'visits': left has 7, right has 8",
+            "field 'is_admin': left has FALSE, right has TRUE",
+        ]
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+#[should_panic(
+    expected = "Order objects differ:\n  field 'is_tall': left has TRUE, right has FALSE"
+)]
+fn object_mismatch_panics() {
+    orm::test_util::assert_object_eq(&Order { is_tall: true }, &Order { is_tall: false });
+}
+//
+#[test]
+fn schema_fingerprint() {
+    #[derive(Object)]
+    #[table_name("order_table")]
+    struct SameOrder {
+        #[column_name("IsTall")]
+        _is_tall: bool,
+    }
+    #[derive(Object)]
+    #[table_name("order_table")]
+    struct RenamedColumnOrder {
+        #[column_name("tall")]
+        _is_tall: bool,
+    }
+    #[derive(Object)]
+    #[table_name("order_table")]
+    struct RetypedOrder {
+        #[column_name("IsTall")]
+        _is_tall: i64,
+    }
+
+    let fingerprint = Order::describe().fingerprint();
+    assert_eq!(fingerprint, Order::describe().fingerprint());
+    assert_eq!(fingerprint, SameOrder::describe().fingerprint());
+    assert_ne!(fingerprint, RenamedColumnOrder::describe().fingerprint());
+    assert_ne!(fingerprint, RetypedOrder::describe().fingerprint());
+    assert_ne!(fingerprint, User::describe().fingerprint());
+
+    #[derive(Object)]
+    #[table_name("order_table")]
+    struct ReadonlyOrder {
+        #[column_name("IsTall")]
+        #[readonly]
+        _is_tall: bool,
+    }
+    assert_ne!(fingerprint, ReadonlyOrder::describe().fingerprint());
+
+    // Every attribute changing the DDL changes the fingerprint.
+    use orm::object::{Index, SchemaBuilder};
+    let builder = || SchemaBuilder::new("fingerprinted").column("value", DataType::Int64);
+    let index = |predicate| Index {
+        columns: vec!["value"],
+        predicate,
+    };
+    let fingerprints = [
+        builder().build(),
+        builder().nullable_column("other", DataType::Int64).build(),
+        builder().column("other", DataType::Int64).build(),
+        builder().check("value > 0").build(),
+        builder().check("value > 1").build(),
+        builder().strict().build(),
+        builder().existing_table().build(),
+        builder().index(index(None)).build(),
+        builder().index(index(Some("value > 0"))).build(),
+        builder().index(index(Some("value > 1"))).build(),
+    ]
+    .map(|schema| schema.fingerprint());
+    for (i, lhs) in fingerprints.iter().enumerate() {
+        for rhs in fingerprints[i + 1..].iter() {
+            assert_ne!(lhs, rhs);
+        }
+    }
+    assert_eq!(fingerprints[0], builder().build().fingerprint());
+}
+//
+#[test]
+fn migration_auto_apply() {
+    let sqlite_conn = rusqlite::Connection::open_in_memory().unwrap();
+    let schema = User::describe();
+
+    let res = schema.migration_auto_apply(&sqlite_conn).unwrap();
+    assert!(res.created);
+    assert!(!res.unchanged);
+
+    let res = schema.migration_auto_apply(&sqlite_conn).unwrap();
+    assert!(!res.created);
+    assert!(res.unchanged);
+    assert!(res.added_columns.is_empty());
+
+    sqlite_conn.execute("DROP TABLE User", []).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE User (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, visits BIGINT)",
+            [],
+        )
+        .unwrap();
+    let res = schema.migration_auto_apply(&sqlite_conn).unwrap();
+    assert!(!res.created);
+    assert!(!res.unchanged);
+    assert_eq!(res.added_columns, ["picture", "balance", "is_admin"]);
+    sqlite_conn
+        .prepare("SELECT name, picture, visits, balance, is_admin FROM User")
+        .unwrap();
+
+    for (ddl, expected) in [
+        (
+            "CREATE TABLE User (id INTEGER PRIMARY KEY AUTOINCREMENT, name BLOB)",
+            "column 'name' changed type from BLOB to TEXT",
+        ),
+        (
+            "CREATE TABLE User (id INTEGER PRIMARY KEY AUTOINCREMENT, nickname TEXT)",
+            "column 'nickname' was removed",
+        ),
+    ] {
+        sqlite_conn.execute("DROP TABLE User", []).unwrap();
+        sqlite_conn.execute(ddl, []).unwrap();
+        match schema.migration_auto_apply(&sqlite_conn) {
+            Err(orm::Error::SchemaMismatch(err)) => {
+                assert_eq!(err.table_name, "User");
+                assert_eq!(err.mismatches, [expected]);
+            }
+            res => panic!("expected Error::SchemaMismatch, got {}", fmt_res(&res)),
+        }
+    }
+}
+//
+#[test]
+fn partial_index() {
+    #[derive(Object)]
+    #[index(login)]
+    #[index(active, where = "is_active = 1")]
+    struct Account {
+        login: String,
+        #[column_name("is_active")]
+        active: bool,
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut orm_conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = orm_conn.new_transaction().unwrap();
+    let account = tx
+        .create(Account {
+            login: "root".into(),
+            active: true,
+        })
+        .unwrap();
+    assert!(account.borrow().active);
+    assert_eq!(account.borrow().login, "root");
+    tx.commit().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let mut stmt = sqlite_conn
+        .prepare("SELECT sql FROM sqlite_master WHERE type = 'index' ORDER BY name")
+        .unwrap();
+    let indexes = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        indexes,
+        [
+            "CREATE INDEX Account_is_active_idx ON Account (is_active) WHERE is_active = 1",
+            "CREATE INDEX Account_login_idx ON Account (login)",
+        ]
+    );
+}
+//
+#[test]
+fn render_values() {
+    assert_eq!(Value::from(String::from("abc")).to_string(), "'abc'");
+    assert_eq!(Value::from(String::from("it's")).to_string(), "'it''s'");
+    assert_eq!(
+        format!("{:.3}", Value::from(String::from("привет"))),
+        "'при'... (6 chars)"
+    );
+    assert_eq!(Value::from(vec![0x0a, 0x0b]).to_string(), "X'0A0B'");
+    assert_eq!(Value::from(-5).to_string(), "-5");
+    assert_eq!(Value::from(2.5).to_string(), "2.5");
+    assert_eq!(Value::from(true).to_string(), "TRUE");
+    assert_eq!(Value::Null.to_string(), "NULL");
+
+    let blob = Value::from(vec![0xab; 1 << 20]);
+    assert_eq!(format!("{:.4}", blob), "X'ABABABAB'... (1048576 bytes)");
+
+    let row: orm::storage::Row = vec![
+        String::from("Liz").into(),
+        vec![1, 2, 3, 4, 5].into(),
+        Value::Null,
+        7.into(),
+    ];
+    assert_eq!(
+        orm::storage::render_params(&row, 2),
+        "'Li'... (3 chars), X'0102'... (5 bytes), NULL, 7"
+    );
+}
+//
+#[test]
+fn ensure_tables() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn_one = Connection::open_sqlite_file(&path).unwrap();
+    let tx_one = conn_one.new_transaction().unwrap();
+    orm::ensure_tables!(tx_one, User, Order).unwrap();
+    orm::ensure_tables!(tx_one, Order).unwrap();
+
+    let mut conn_two = Connection::open_sqlite_file(&path).unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    match orm::ensure_tables!(tx_two, User, Order) {
+        Err(orm::Error::EnsureTable(err)) => {
+            assert_eq!(err.table_name, "User");
+            assert!(matches!(err.source, orm::Error::LockConflict));
+        }
+        res => panic!("expected Error::EnsureTable, got {}", fmt_res(&res)),
+    }
+    drop(tx_two);
+
+    tx_one.commit().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let tables: i64 = sqlite_conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' \
+            AND name IN ('User', 'order_table')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(tables, 2);
+}
+//
+#[test]
+fn sqlite_version_and_features() {
+    let conn = Connection::open_in_memory().unwrap();
+
+    let (major, minor, patch) = conn.sqlite_version();
+    let version: String = rusqlite::Connection::open_in_memory()
+        .unwrap()
+        .query_row("SELECT sqlite_version()", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(format!("{}.{}.{}", major, minor, patch), version);
+
+    for option in conn.compile_options().unwrap() {
+        let name = option.split('=').next().unwrap();
+        assert!(conn.has_feature(name).unwrap());
+        assert!(conn.has_feature(&option).unwrap());
+    }
+    assert!(!conn.has_feature("ENABLE_TIME_TRAVEL").unwrap());
+}
+//
+#[test]
+fn visible_when() {
+    #[derive(Object)]
+    #[yorm(visible_when = "tenant_id = :current_tenant")]
+    struct Document {
+        tenant_id: i64,
+        title: String,
+    }
+
+    struct Tenant(i64);
+
+    impl orm::SecurityContext for Tenant {
+        fn current_values(&self) -> std::collections::HashMap<String, Value<'static>> {
+            [("current_tenant".to_owned(), self.0.into())].into()
+        }
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let first_id = tx
+        .create(Document {
+            tenant_id: 1,
+            title: "Report".into(),
+        })
+        .unwrap()
+        .id();
+    let second_id = tx
+        .create(Document {
+            tenant_id: 2,
+            title: "Memo".into(),
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Document>(first_id),
+        Err(orm::Error::MissingContextValue(name)) if name == "current_tenant"
+    ));
+    drop(tx);
+
+    let mut conn = Connection::open_sqlite_file(&path)
+        .unwrap()
+        .with_security_context(Tenant(1));
+    let tx = conn.new_transaction().unwrap();
+    let document = tx.get::<Document>(first_id).unwrap();
+    assert_eq!(document.borrow().title, "Report");
+    assert_eq!(document.borrow().tenant_id, 1);
+    match tx.get::<Document>(second_id) {
+        Err(orm::Error::NotFound(err)) => assert_eq!(err.object_id, second_id),
+        res => panic!("expected Error::NotFound, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn avg_row_size_bytes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.avg_row_size_bytes::<User>(), None);
+
+    let user = User {
+        name: "Nick".into(),
+        picture: vec![0; 100],
+        visits: 1,
+        balance: 1.,
+        is_admin: false,
+    };
+    tx.create(user.clone()).unwrap();
+    let small = tx.avg_row_size_bytes::<User>().unwrap();
+    assert!(small > 104.);
+    assert_eq!(tx.avg_row_size_bytes::<Order>(), None);
+
+    tx.create(User {
+        picture: vec![0; 300],
+        ..user
+    })
+    .unwrap();
+    assert_eq!(tx.avg_row_size_bytes::<User>().unwrap(), small + 100.);
+}
+//
+#[test]
+fn merge() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let user = User {
+        name: "Olga".into(),
+        picture: b"v0a9s8dvh"[..].into(),
+        visits: 5,
+        balance: 10.,
+        is_admin: false,
+    };
+    let tx = conn.new_transaction().unwrap();
+    let user_id = tx.create(user.clone()).unwrap().id();
+    tx.commit().unwrap();
+
+    let incoming = User {
+        visits: 6,
+        balance: 12.5,
+        ..user.clone()
+    };
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx.get::<User>(user_id).unwrap();
+    let tx_merged = tx.merge(user_id, incoming.clone()).unwrap();
+    assert_eq!(tx_merged.id(), user_id);
+    assert!(matches!(tx_merged.state(), ObjectState::Modified));
+    assert_eq!(*tx_user.borrow(), incoming);
+
+    let created_id = ObjectId::from(user_id.into_i64() + 100);
+    let tx_created = tx.merge(created_id, user.clone()).unwrap();
+    assert_eq!(tx_created.id(), created_id);
+    assert!(matches!(tx_created.state(), ObjectState::Clean));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(*tx.get::<User>(user_id).unwrap().borrow(), incoming);
+    assert_eq!(*tx.get::<User>(created_id).unwrap().borrow(), user);
+
+    // The removal stands.
+    tx.get::<User>(created_id).unwrap().delete();
+    match tx.merge(created_id, user.clone()) {
+        Err(orm::Error::NotFound(err)) => assert_eq!(err.object_id, created_id),
+        res => panic!("expected Error::NotFound, got {}", fmt_res(&res)),
+    }
+    tx.commit().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<User>(created_id),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+//
+#[test]
+fn object_id_base62() {
+    for (n, encoded) in [
+        (0, "0"),
+        (61, "z"),
+        (62, "10"),
+        (3843, "zz"),
+        (i64::MAX, "AzL8n0Y58m7"),
+    ] {
+        let id = ObjectId::from(n);
+        assert_eq!(id.to_base62(), encoded);
+        assert_eq!(ObjectId::from_base62(encoded), Ok(id));
+    }
+
+    assert_eq!(ObjectId::from(-62).to_base62(), "-10");
+    assert_eq!(ObjectId::from_base62("-10"), Err(orm::ParseError::Negative));
+    assert_eq!(ObjectId::from_base62("-0"), Err(orm::ParseError::Negative));
+    assert_eq!(ObjectId::from_base62(""), Err(orm::ParseError::Empty));
+    assert_eq!(
+        ObjectId::from_base62("a_b"),
+        Err(orm::ParseError::InvalidCharacter('_'))
+    );
+    assert_eq!(
+        ObjectId::from_base62("AzL8n0Y58m8"),
+        Err(orm::ParseError::Overflow)
+    );
+}
+//
+#[test]
+fn dyn_table() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let schema = orm::object::SchemaBuilder::new("form_answers")
+        .column("question", DataType::String)
+        .nullable_column("score", DataType::Int64)
+        .build();
+
+    let tx = conn.new_transaction().unwrap();
+    let table = tx.dyn_table(schema.clone()).unwrap();
+    let id = table
+        .insert(&[String::from("How are you?").into(), Value::Null])
+        .unwrap();
+    let row = table.get(id).unwrap();
+    assert!(matches!(&row[0], Value::String(question) if question == "How are you?"));
+    assert!(matches!(row[1], Value::Null));
+
+    table
+        .update(id, &[String::from("How are you?").into(), 5.into()])
+        .unwrap();
+    assert!(matches!(table.get(id).unwrap()[1], Value::Int64(5)));
+
+    match table.insert(&[5.into(), 5.into()]) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, "form_answers");
+            assert_eq!(err.column_name, "question");
+            assert_eq!(err.expected_type, DataType::String);
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let table = tx.dyn_table(schema).unwrap();
+    assert!(matches!(table.get(id).unwrap()[1], Value::Int64(5)));
+    table.delete(id).unwrap();
+    assert!(matches!(table.get(id), Err(orm::Error::NotFound(_))));
+}
+//
+#[test]
+fn read_only_connection() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Ivy".into(),
+            picture: vec![],
+            visits: 3,
+            balance: 1.5,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let mut conn = Connection::open_read_only(&path).unwrap();
+    assert_eq!(conn.connection_type(), ConnectionType::ReadOnly);
+    let tx = conn.new_transaction().unwrap();
+    let user = tx.get::<User>(id).unwrap();
+    assert_eq!(user.borrow().name, "Ivy");
+    assert!(matches!(
+        tx.create(User {
+            name: "Jack".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        }),
+        Err(orm::Error::ReadOnly)
+    ));
+    assert!(matches!(
+        tx.get::<Order>(1000.into()),
+        Err(orm::Error::ReadOnly)
+    ));
+    for dml in [
+        "UPDATE User SET visits = 0 RETURNING visits",
+        "DELETE FROM User RETURNING visits",
+    ] {
+        assert!(matches!(
+            tx.query_scalar1::<i64>(dml, &[]),
+            Err(orm::Error::ReadOnly)
+        ));
+    }
+    assert!(matches!(
+        tx.rename_table("User", "Person"),
+        Err(orm::Error::ReadOnly)
+    ));
+    user.borrow_mut().visits += 1;
+    assert!(matches!(tx.commit(), Err(orm::Error::ReadOnly)));
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap();
+    tx.commit().unwrap();
+}
+//
+#[test]
+fn read_only_object() {
+    #[derive(Object)]
+    #[yorm(connection_type = "ReadOnly")]
+    struct Country {
+        code: String,
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute_batch(
+            "CREATE TABLE Country (id INTEGER PRIMARY KEY AUTOINCREMENT, code TEXT);
+            INSERT INTO Country (code) VALUES ('NL');",
+        )
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.create(Country { code: "BE".into() }),
+        Err(orm::Error::ReadOnly)
+    ));
+    let country = tx.get::<Country>(1.into()).unwrap();
+    assert_eq!(country.borrow().code, "NL");
+    country.delete();
+    assert!(matches!(tx.commit(), Err(orm::Error::ReadOnly)));
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Country>(1.into()).unwrap().borrow().code, "NL");
+}
+//
+#[test]
+fn begin_nested() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let user = tx
+        .create(User {
+            name: "Kate".into(),
+            picture: vec![],
+            visits: 1,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().borrow_mut().visits = 2;
+    child.commit().unwrap();
+    assert_eq!(user.borrow().visits, 2);
+    assert!(matches!(user.state(), ObjectState::Clean));
+
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().borrow_mut().visits = 3;
+    child.commit().unwrap();
+
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().borrow_mut().visits = 4;
+    child.rollback().unwrap();
+
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().delete();
+    drop(child);
+
+    let child = tx.begin_nested("visits").unwrap();
+    assert_eq!(child.get::<User>(user.id()).unwrap().borrow().visits, 3);
+    drop(child);
+    assert_eq!(user.borrow().visits, 3);
+
+    // Written here after the child began as well: the child is rolled back
+    // rather than one of the writes lost.
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().borrow_mut().visits = 5;
+    user.borrow_mut().visits = 6;
+    match child.commit() {
+        Err(orm::Error::NestedConflict(err)) => {
+            assert_eq!(err.savepoint, "visits");
+            assert_eq!(err.object_id, user.id());
+            assert_eq!(err.type_name, "User");
+        }
+        res => panic!("expected Error::NestedConflict, got {}", fmt_res(&res)),
+    }
+    assert_eq!(user.borrow().visits, 6);
+    let child = tx.begin_nested("visits").unwrap();
+    assert_eq!(child.get::<User>(user.id()).unwrap().borrow().visits, 3);
+    drop(child);
+
+    // Changed before the child began, or only read by the child: no conflict.
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap();
+    user.borrow_mut().visits = 7;
+    child.commit().unwrap();
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().borrow_mut().visits = 8;
+    child.commit().unwrap();
+    assert_eq!(user.borrow().visits, 8);
+
+    let child = tx.begin_nested("visits").unwrap();
+    child.get::<User>(user.id()).unwrap().delete();
+    child.commit().unwrap();
+    assert!(matches!(user.state(), ObjectState::Removed));
+    let id = user.id();
+    assert_not_found(tx.get::<User>(id), id, "User");
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_not_found(tx.get::<User>(id), id, "User");
+}
+//
+#[test]
+fn empty_schema_of_struct_with_fields() {
+    #[allow(dead_code)]
+    struct Broken {
+        value: i64,
+    }
+
+    impl Object for Broken {
+        fn as_row(&self) -> orm::storage::Row {
+            vec![]
+        }
+        fn from_row(_row: orm::storage::Row) -> Self {
+            Self { value: 0 }
+        }
+        fn table_name() -> &'static str {
+            "Broken"
+        }
+        fn type_name() -> &'static str {
+            "Broken"
+        }
+        fn field_names() -> Vec<&'static str> {
+            vec![]
+        }
+        fn column_names() -> Vec<&'static str> {
+            vec![]
+        }
+        fn column_types() -> Vec<DataType> {
+            vec![]
+        }
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    match tx.create(Broken { value: 5 }) {
+        Err(orm::Error::EmptySchema(type_name)) => assert_eq!(type_name, "Broken"),
+        res => panic!("expected Error::EmptySchema, got {}", fmt_res(&res)),
+    }
+}
+//
+mod prelude_only {
+    use orm::prelude::*;
+
+    #[derive(Object)]
+    #[index(title)]
+    struct Note {
+        title: String,
+        pinned: bool,
+    }
+
+    #[test]
+    fn derive_with_prelude() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx: Transaction = conn.new_transaction().unwrap();
+        let note: Tx<Note> = tx
+            .create(Note {
+                title: "Groceries".into(),
+                pinned: true,
+            })
+            .unwrap();
+        let id: ObjectId = note.id();
+        let result: Result<Tx<Note>> = tx.get(id);
+        assert!(result.unwrap().borrow().pinned);
+        assert_eq!(Note::column_types(), vec![DataType::String, DataType::Bool]);
+        assert!(matches!(
+            tx.get::<Note>(ObjectId::new(id.into_i64() + 1)),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(Value::from(true).data_type(), Some(DataType::Bool));
+    }
+}
+//
+#[test]
+fn approximate_byte_size() {
+    let int = Value::from(7i64).approximate_byte_size();
+    assert!(int >= 8);
+    assert_eq!(Value::from(1.5).approximate_byte_size(), int);
+    assert_eq!(Value::Null.approximate_byte_size(), int);
+    assert_eq!(
+        Value::from("abcd".to_owned()).approximate_byte_size(),
+        int + 4
+    );
+    assert_eq!(
+        Value::from(b"xyz".to_vec()).approximate_byte_size(),
+        int + 3
+    );
+
+    let row = vec![
+        Value::from("abcd".to_owned()),
+        Value::from(7i64),
+        Value::Null,
+    ];
+    assert_eq!(orm::storage::approximate_row_size(&row), 3 * int + 4);
+}
+//
+#[test]
+fn query_scalar() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for (name, visits, is_admin) in [("Ann", 3, false), ("Bob", 8, true), ("Cid", 13, false)] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits,
+            balance: visits as f64 / 2.0,
+            is_admin,
+        })
+        .unwrap();
+    }
+
+    let count = tx
+        .query_scalar1::<i64>("SELECT COUNT(*) FROM User", &[])
+        .unwrap();
+    assert_eq!(count, vec![3]);
+
+    let visits = tx
+        .query_scalar2::<String, i64>(
+            "SELECT name, visits FROM User WHERE visits > ? ORDER BY name",
+            &[5i64.into()],
+        )
+        .unwrap();
+    assert_eq!(visits, vec![("Bob".into(), 8), ("Cid".into(), 13)]);
+
+    let admins = tx
+        .query_scalar3::<String, bool, f64>(
+            "SELECT name, is_admin, balance FROM User ORDER BY id LIMIT 2",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        admins,
+        vec![("Ann".into(), false, 1.5), ("Bob".into(), true, 4.0)]
+    );
+
+    let stats = tx
+        .query_scalar4::<i64, i64, f64, Option<String>>(
+            "SELECT MIN(visits), MAX(visits), AVG(visits), NULL FROM User",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(stats, vec![(3, 13, 8.0, None)]);
+
+    match tx.query_scalar1::<String>("SELECT visits FROM User", &[]) {
+        Err(orm::Error::QueryColumn(err)) => {
+            assert_eq!(err.column, 0);
+            assert_eq!(err.expected, "String");
+            assert_eq!(err.got, "Int64");
+        }
+        res => panic!("expected Error::QueryColumn, got {:?}", res.map(|_| ())),
+    }
+    assert!(matches!(
+        tx.query_scalar2::<String, i64>("SELECT name FROM User", &[]),
+        Err(orm::Error::QueryColumn(_))
+    ));
+}
+//
+#[test]
+fn strict_schema() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE User (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, \
+            picture BLOB, visits TEXT, balance REAL)",
+            [],
+        )
+        .unwrap();
+
+    let user = || User {
+        name: "Lena".into(),
+        picture: vec![],
+        visits: 1,
+        balance: 0.0,
+        is_admin: false,
+    };
+
+    let mut conn = Connection::open_sqlite_file(&path)
+        .unwrap()
+        .with_strict_schema();
+    let tx = conn.new_transaction().unwrap();
+    match tx.create(user()) {
+        Err(orm::Error::SchemaMismatch(err)) => {
+            assert_eq!(err.table_name, "User");
+            assert_eq!(
+                err.mismatches,
+                vec![
+                    "column 'visits' changed type from TEXT to BIGINT",
+                    "column 'is_admin' is missing",
+                ]
+            );
+        }
+        res => panic!("expected Error::SchemaMismatch, got {}", fmt_res(&res)),
+    }
+    tx.commit().unwrap();
+    let count: i64 = sqlite_conn
+        .query_row("SELECT COUNT(*) FROM User", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 0);
+
+    sqlite_conn
+        .execute_batch(
+            "DROP TABLE User;
+            CREATE TABLE User (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, \
+            picture BLOB, visits BIGINT, balance REAL, is_admin TINYINT);",
+        )
+        .unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(user()).unwrap();
+    tx.create(user()).unwrap();
+    tx.commit().unwrap();
+}
+//
+#[test]
+fn mark_clean() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx
+        .create(User {
+            name: "Mona".into(),
+            picture: vec![],
+            visits: 1,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    tx.query_scalar1::<i64>("UPDATE User SET visits = 2 RETURNING visits", &[])
+        .unwrap();
+    tx_user.borrow_mut().visits = 2;
+    assert!(matches!(tx_user.state(), ObjectState::Modified));
+    tx_user.mark_clean();
+    assert!(matches!(tx_user.state(), ObjectState::Clean));
+    tx_user.borrow_mut().name = "Nina".into();
+    tx_user.mark_clean();
+    let id = tx_user.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx.get::<User>(id).unwrap();
+    assert_eq!(tx_user.borrow().visits, 2);
+    assert_eq!(tx_user.borrow().name, "Mona");
+}
+//
+#[test]
+#[should_panic(expected = "cannot mark a removed object clean")]
+fn mark_clean_deleted() {
+    let mut conn = Connection::open_in_memory().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tx_user = tx
+        .create(User {
+            name: "Olga".into(),
+            picture: vec![],
+            visits: 1,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    let tx_user_2 = tx.get::<User>(tx_user.id()).unwrap();
+
+    tx_user.delete();
+    tx_user_2.mark_clean();
+}
+//
+#[test]
+fn column_name_with_reordered_fields() {
+    #[derive(Object)]
+    #[table_name("person")]
+    struct Person {
+        #[column_name("full_name")]
+        name: String,
+        #[column_name("nick")]
+        nickname: String,
+        age: i64,
+    }
+
+    // Same table, fields in a different order.
+    #[derive(Object)]
+    #[table_name("person")]
+    struct PersonReordered {
+        age: i64,
+        #[column_name("nick")]
+        nickname: String,
+        #[column_name("full_name")]
+        name: String,
+    }
+
+    #[derive(Object)]
+    #[table_name("person")]
+    struct PersonWrongType {
+        #[column_name("full_name")]
+        name: i64,
+    }
+
+    // The generated description and row must agree field by field.
+    assert_eq!(Person::field_names(), vec!["name", "nickname", "age"]);
+    assert_eq!(Person::column_names(), vec!["full_name", "nick", "age"]);
+    assert_eq!(
+        PersonReordered::column_names(),
+        vec!["age", "nick", "full_name"]
+    );
+    let person = Person {
+        name: "Peter Parker".into(),
+        nickname: "Spidey".into(),
+        age: 17,
+    };
+    assert_eq!(
+        orm::storage::render_params(&person.as_row(), 20),
+        "'Peter Parker', 'Spidey', 17"
+    );
+    let reordered = PersonReordered::from_row(vec![
+        42i64.into(),
+        "Logan".to_owned().into(),
+        "James Howlett".to_owned().into(),
+    ]);
+    assert_eq!(reordered.age, 42);
+    assert_eq!(reordered.nickname, "Logan");
+    assert_eq!(reordered.name, "James Howlett");
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let first_id = tx.create(person).unwrap().id();
+    let second_id = tx.create(reordered).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let first = tx.get::<PersonReordered>(first_id).unwrap();
+    assert_eq!(first.borrow().name, "Peter Parker");
+    assert_eq!(first.borrow().nickname, "Spidey");
+    assert_eq!(first.borrow().age, 17);
+    let second = tx.get::<Person>(second_id).unwrap();
+    assert_eq!(second.borrow().name, "James Howlett");
+    assert_eq!(second.borrow().nickname, "Logan");
+    assert_eq!(second.borrow().age, 42);
+    drop(tx);
+
+    // A column read as another type is reported, not converted.
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<PersonWrongType>(first_id) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "name");
+            assert_eq!(err.column_name, "full_name");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn to_migration_sql() {
+    use orm::object::{Index, SchemaBuilder};
+
+    let v1 = SchemaBuilder::new("account")
+        .column("login", DataType::String)
+        .column("age", DataType::Int64)
+        .column("legacy", DataType::Bytes)
+        .build();
+    let v2 = SchemaBuilder::new("account")
+        .column("login", DataType::String)
+        .column("age", DataType::Int64)
+        .column("legacy", DataType::Bytes)
+        .column("email", DataType::String)
+        .build();
+    let v3 = SchemaBuilder::new("account")
+        .column("login", DataType::String)
+        .column("age", DataType::String)
+        .column("email", DataType::String)
+        .index(Index {
+            columns: vec!["login"],
+            predicate: None,
+        })
+        .build();
+
+    let diff = v3.diff(&v2);
+    assert_eq!(diff.added, Vec::<&str>::new());
+    assert_eq!(diff.removed, vec!["legacy"]);
+    assert_eq!(diff.changed, vec!["age"]);
+    assert!(v2.to_migration_sql(&v2).is_empty());
+    assert_eq!(
+        v2.to_migration_sql(&v1),
+        vec!["ALTER TABLE account ADD COLUMN email TEXT;"]
+    );
+    assert_eq!(
+        v3.to_migration_sql(&v2),
+        vec![
+            "CREATE TABLE account_migration \
+            (id INTEGER PRIMARY KEY AUTOINCREMENT,login TEXT,age TEXT,email TEXT);",
+            "INSERT INTO account_migration (id, login, age, email) \
+            SELECT id, login, age, email FROM account;",
+            "DROP TABLE account;",
+            "ALTER TABLE account_migration RENAME TO account;",
+            "CREATE INDEX IF NOT EXISTS account_login_idx ON account (login);",
+        ]
+    );
+
+    let sqlite_conn = rusqlite::Connection::open_in_memory().unwrap();
+    sqlite_conn
+        .execute_batch(&format!(
+            "CREATE TABLE account ({});
+            INSERT INTO account (login, age, legacy) VALUES ('root', 30, X'00');",
+            v1.text_description()
+        ))
+        .unwrap();
+    for statement in v2
+        .to_migration_sql(&v1)
+        .into_iter()
+        .chain(v3.to_migration_sql(&v2))
+    {
+        sqlite_conn.execute(&statement, []).unwrap();
+    }
+    let row: (i64, String, String, Option<String>) = sqlite_conn
+        .query_row("SELECT id, login, age, email FROM account", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap();
+    assert_eq!(row, (1, "root".into(), "30".into(), None));
+}
+//
+#[test]
+fn any_tx_with_row() {
+    fn dump(objects: &[orm::AnyTx]) -> Vec<String> {
+        objects
+            .iter()
+            .map(|object| {
+                let row = object.with_row(|row| orm::storage::render_params(row, 10));
+                format!("{} {}: {}", object.table_name(), object.id(), row)
+            })
+            .collect()
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let user = tx
+        .create(User {
+            name: "Paul".into(),
+            picture: vec![1, 2],
+            visits: 3,
+            balance: 0.5,
+            is_admin: true,
+        })
+        .unwrap();
+    let order = tx.create(Order { is_tall: false }).unwrap();
+    assert_eq!(user.with_row(|row| row.len()), 5);
+
+    let objects = vec![user.clone().into_any(), order.into_any()];
+    assert_eq!(
+        dump(&objects),
+        vec![
+            "User 1: 'Paul', X'0102', 3, 0.5, TRUE",
+            "order_table 1: FALSE",
+        ]
+    );
+    user.borrow_mut().visits += 1;
+    assert!(matches!(objects[0].state(), ObjectState::Modified));
+    assert_eq!(dump(&objects)[0], "User 1: 'Paul', X'0102', 4, 0.5, TRUE");
+}
+//
+#[test]
+fn load_subtree() {
+    #[derive(Object)]
+    struct Category {
+        title: String,
+        parent_id: i64,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let create = |title: &str, parent_id: i64| {
+        tx.create(Category {
+            title: title.into(),
+            parent_id,
+        })
+        .unwrap()
+        .id()
+        .into_i64()
+    };
+    let root = create("Root", 0);
+    let books = create("Books", root);
+    let music = create("Music", root);
+    let novels = create("Novels", books);
+    create("Other root", 0);
+    let jazz = create("Jazz", music);
+    create("Poetry", books);
+
+    tx.get::<Category>(jazz.into()).unwrap().borrow_mut().title = "Jazz & Blues".into();
+    tx.get::<Category>(novels.into()).unwrap().delete();
+
+    let subtree = tx
+        .load_subtree::<Category>(music.into(), "parent_id")
+        .unwrap();
+    let titles = subtree
+        .iter()
+        .map(|category| category.borrow().title.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, vec!["Music", "Jazz & Blues"]);
+
+    let subtree = tx
+        .load_subtree::<Category>(root.into(), "parent_id")
+        .unwrap();
+    let titles = subtree
+        .iter()
+        .map(|category| category.borrow().title.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        titles,
+        vec!["Root", "Books", "Music", "Jazz & Blues", "Poetry"]
+    );
+    subtree[1].borrow_mut().title = "Printed books".into();
+    assert_eq!(
+        tx.get::<Category>(books.into()).unwrap().borrow().title,
+        "Printed books"
+    );
+
+    assert!(matches!(
+        tx.load_subtree::<Category>(root.into(), "title"),
+        Err(orm::Error::InvalidParentColumn(column)) if column == "title"
+    ));
+    assert!(matches!(
+        tx.load_subtree::<Category>(root.into(), "parent"),
+        Err(orm::Error::InvalidParentColumn(_))
+    ));
+    assert!(matches!(
+        tx.load_subtree::<Category>(100.into(), "parent_id"),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+//
+#[test]
+fn tx_weak() {
+    #[derive(Object)]
+    struct Employee {
+        name: String,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let alice = tx
+        .create(Employee {
+            name: "Alice".into(),
+        })
+        .unwrap();
+    let weak = alice.downgrade();
+    drop(alice);
+
+    assert_eq!(weak.upgrade(&tx).unwrap().borrow().name, "Alice");
+    weak.upgrade(&tx).unwrap().borrow_mut().name = "Alicia".into();
+    let alice = tx.get::<Employee>(weak.id()).unwrap();
+    assert_eq!(alice.borrow().name, "Alicia");
+    assert!(matches!(alice.state(), ObjectState::Modified));
+
+    let bob = tx.create(Employee { name: "Bob".into() }).unwrap();
+    let removed = bob.downgrade();
+    bob.delete();
+    assert!(removed.upgrade(&tx).is_none());
+
+    drop(alice);
+    tx.commit().unwrap();
+
+    // Only the transaction the object lives in upgrades it.
+    let tx = conn.new_transaction().unwrap();
+    assert!(weak.upgrade(&tx).is_none());
+    tx.get::<Employee>(weak.id()).unwrap();
+    assert!(weak.upgrade(&tx).is_none());
+}
+//
+#[test]
+fn sql_rewriter() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let statements = Rc::new(RefCell::new(Vec::new()));
+    let mut conn = Connection::open_in_memory().unwrap();
+    let log = statements.clone();
+    conn.set_sql_rewriter(Box::new(move |query| {
+        log.borrow_mut().push(query.to_owned());
+        query.replace(
+            "FROM User WHERE",
+            "FROM User INDEXED BY user_name_idx WHERE",
+        )
+    }));
+
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Quinn".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+    assert!(statements.borrow()[0].starts_with("SELECT name FROM sqlite_master"));
+    assert!(statements.borrow()[1].starts_with("CREATE TABLE User"));
+    assert!(statements.borrow()[2].starts_with("INSERT INTO User"));
+    assert_eq!(statements.borrow().last().unwrap(), "COMMIT;");
+
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<User>(id) {
+        Err(orm::Error::Storage(err)) => assert!(err.to_string().contains("user_name_idx")),
+        res => panic!("expected Error::Storage, got {}", fmt_res(&res)),
+    }
+    drop(tx);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.query_scalar1::<i64>("CREATE INDEX user_name_idx ON User (name)", &[])
+        .unwrap();
+    assert_eq!(tx.get::<User>(id).unwrap().borrow().name, "Quinn");
+}
+//
+#[test]
+fn compact_cache() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let mut ids = Vec::new();
+    for visits in 0..5 {
+        let tx_user = tx
+            .create(User {
+                name: "Rita".into(),
+                picture: vec![],
+                visits,
+                balance: 0.0,
+                is_admin: false,
+            })
+            .unwrap();
+        ids.push(tx_user.id());
+    }
+    let held = tx.get::<User>(ids[0]).unwrap();
+    tx.get::<User>(ids[1]).unwrap().borrow_mut().visits = 10;
+    tx.get::<User>(ids[2]).unwrap().delete();
+
+    assert_eq!(tx.compact_cache(), 2);
+    assert_eq!(tx.compact_cache(), 0);
+
+    held.borrow_mut().visits = 20;
+    assert_eq!(tx.get::<User>(ids[1]).unwrap().borrow().visits, 10);
+    assert_not_found(tx.get::<User>(ids[2]), ids[2], "User");
+    assert_eq!(tx.get::<User>(ids[3]).unwrap().borrow().visits, 3);
+    drop(held);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<User>(ids[0]).unwrap().borrow().visits, 20);
+    assert_eq!(tx.get::<User>(ids[1]).unwrap().borrow().visits, 10);
+}
+//
+#[test]
+fn filter_to_sql() {
+    use orm::Filter;
+
+    let filter = Filter::ge("visits", 5i64)
+        .and(
+            Filter::is_in("name", ["Ann".to_owned(), "Bob".to_owned()])
+                .or(!Filter::is_null("IsTall")),
+        )
+        .and(!Filter::eq("odd\"column", true));
+    let (sql, params) = filter.to_sql();
+    assert_eq!(
+        sql,
+        "(\"visits\" >= ? AND (\"name\" IN (?, ?) OR NOT (\"IsTall\" IS NULL)) \
+        AND NOT (\"odd\"\"column\" = ?))"
+    );
+    assert_eq!(
+        orm::storage::render_params(&params, 10),
+        "5, 'Ann', 'Bob', TRUE"
+    );
+    assert_eq!(filter.params_count(), 4);
+    assert_eq!(Filter::And(vec![]).to_sql().0, "1");
+    assert_eq!(Filter::is_in("id", Vec::<i64>::new()).to_sql().0, "0");
+
+    let filter = Filter::is_in("visits", 0..5i64).and(Filter::ne("name", "Eve".to_owned()));
+    assert_eq!(filter.to_sql_chunks(10).len(), 1);
+    let chunks = filter.to_sql_chunks(3);
+    let rendered = chunks
+        .iter()
+        .map(|(sql, params)| format!("{} [{}]", sql, orm::storage::render_params(params, 10)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            "(\"visits\" IN (?, ?) AND \"name\" <> ?) [0, 1, 'Eve']",
+            "(\"visits\" IN (?, ?) AND \"name\" <> ?) [2, 3, 'Eve']",
+            "(\"visits\" IN (?) AND \"name\" <> ?) [4, 'Eve']",
+        ]
+    );
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for (name, visits) in [("Ann", 1), ("Eve", 2), ("Bob", 4), ("Cid", 7)] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    let mut names = Vec::new();
+    for (sql, params) in chunks {
+        let query = format!("SELECT name FROM User WHERE {} ORDER BY id", sql);
+        names.extend(tx.query_scalar1::<String>(&query, &params).unwrap());
+    }
+    assert_eq!(names, vec!["Ann", "Bob"]);
+}
+//
+#[test]
+fn to_debug_string() {
+    #[derive(Object)]
+    struct Void;
+
+    let user = User {
+        name: "Sam 'O".into(),
+        picture: vec![0xab],
+        visits: 2,
+        balance: 1.25,
+        is_admin: false,
+    };
+    assert_eq!(
+        user.to_debug_string(),
+        "User { name: 'Sam ''O', picture: X'AB', visits: 2, balance: 1.25, is_admin: FALSE }"
+    );
+    assert_eq!(
+        Order { is_tall: true }.to_debug_string(),
+        "Order { is_tall (IsTall): TRUE }"
+    );
+    assert_eq!(Void.to_debug_string(), "Void {}");
+}
+//
+#[test]
+fn value_into_rusqlite_value() {
+    use rusqlite::types::Value as SqlValue;
+
+    let text = "Tom".to_owned();
+    let borrowed = Value::String(std::borrow::Cow::Borrowed(text.as_str()));
+    assert_eq!(SqlValue::from(borrowed), SqlValue::Text("Tom".into()));
+    assert_eq!(
+        SqlValue::from(Value::from(vec![1u8, 2])),
+        SqlValue::Blob(vec![1, 2])
+    );
+    assert_eq!(SqlValue::from(Value::from(-3i64)), SqlValue::Integer(-3));
+    assert_eq!(SqlValue::from(Value::from(0.5)), SqlValue::Real(0.5));
+    assert_eq!(SqlValue::from(Value::from(true)), SqlValue::Integer(1));
+    assert_eq!(SqlValue::from(Value::Null), SqlValue::Null);
+
+    let sqlite_conn = rusqlite::Connection::open_in_memory().unwrap();
+    let values: Vec<SqlValue> = vec![Value::from(2i64).into(), Value::from(3i64).into()];
+    let sum: i64 = sqlite_conn
+        .query_row("SELECT ? + ?", rusqlite::params_from_iter(values), |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(sum, 5);
+}
+//
+#[test]
+fn readonly_column() {
+    #[derive(Object)]
+    struct Rectangle {
+        width: i64,
+        height: i64,
+        #[readonly]
+        area: i64,
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE Rectangle (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+            width BIGINT, height BIGINT, area BIGINT GENERATED ALWAYS AS (width * height))",
+            [],
+        )
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    assert_eq!(Rectangle::column_readonly(), vec![false, false, true]);
+    let schema = Rectangle::describe();
+    assert_eq!(schema.writable_column_list(", "), "width, height");
+    assert_eq!(schema.prepare_update_column_list(), "width = ?,height = ?");
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Rectangle {
+            width: 2,
+            height: 3,
+            area: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let rectangle = tx.get::<Rectangle>(id).unwrap();
+    assert_eq!(rectangle.borrow().area, 6);
+    rectangle.borrow_mut().width = 5;
+    rectangle.borrow_mut().area = 1;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let rectangle = tx.get::<Rectangle>(id).unwrap();
+    assert_eq!(rectangle.borrow().width, 5);
+    assert_eq!(rectangle.borrow().area, 15);
+}
+//
+#[test]
+fn max_rows_per_query() {
+    let mut conn = Connection::open_in_memory()
+        .unwrap()
+        .with_max_rows_per_query(2);
+    let tx = conn.new_transaction().unwrap();
+    for name in ["Ann", "Bob", "Cid"] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+
+    let res = tx.query_scalar1::<String>("SELECT name FROM User", &[]);
+    match res {
+        Err(orm::Error::ResultTooLarge(err)) => {
+            assert_eq!(err.limit, 2);
+            assert_eq!(err.table, None);
+            assert_eq!(err.query.as_deref(), Some("SELECT name FROM User"));
+            assert_eq!(
+                err.to_string(),
+                "more than 2 rows in the result of query 'SELECT name FROM User': \
+                 read them with Transaction::page or stream them with Transaction::for_each instead"
+            );
+        }
+        _ => panic!("expected ResultTooLarge, got {}", fmt_res(&res)),
+    }
+
+    let mut names = vec![];
+    let (page, next) = tx.page::<User>(&[], None, 2).unwrap();
+    names.extend(page.iter().map(|user| user.borrow().name.clone()));
+    let (page, _) = tx.page::<User>(&[], next, 2).unwrap();
+    names.extend(page.iter().map(|user| user.borrow().name.clone()));
+    assert_eq!(names, vec!["Ann", "Bob", "Cid"]);
+    let mut count = 0;
+    tx.for_each::<User, _>(orm::Filter::And(vec![]), |_| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(count, 3);
+}
+//
+#[test]
+fn observe_cache() {
+    use orm::{CacheEvent, CacheEventType};
+    use std::{cell::RefCell, rc::Rc};
+
+    let events = Rc::new(RefCell::new(Vec::<CacheEvent>::new()));
+    let mut conn = Connection::open_in_memory().unwrap();
+    let mut tx = conn.new_transaction().unwrap();
+    let observed = events.clone();
+    tx.observe_cache(move |event| observed.borrow_mut().push(event));
+
+    let id = tx
+        .create(User {
+            name: "Ann".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.get::<User>(id).unwrap();
+    assert_eq!(tx.compact_cache(), 1);
+    tx.get::<User>(id).unwrap();
+
+    let event_types = events
+        .borrow()
+        .iter()
+        .map(|event| {
+            assert_eq!(event.id, id);
+            assert_eq!(event.type_name, "User");
+            event.event_type
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        event_types,
+        vec![
+            CacheEventType::Added,
+            CacheEventType::Hit,
+            CacheEventType::Evicted,
+            CacheEventType::Miss,
+            CacheEventType::Added,
+        ]
+    );
+}
+//
+#[test]
+fn check_constraints() {
+    #[derive(Object)]
+    #[check("length(login) > 0")]
+    struct Account {
+        login: String,
+        #[check("age >= 0")]
+        age: i64,
+    }
+
+    let schema = <Account as Object>::describe();
+    assert_eq!(schema.checks(), ["length(login) > 0", "age >= 0"]);
+    assert!(schema
+        .text_description()
+        .ends_with("CHECK (length(login) > 0),CHECK (age >= 0)"));
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let res = tx.create(Account {
+        login: "ann".into(),
+        age: -1,
+    });
+    match res {
+        Err(orm::Error::Constraint(err)) => {
+            assert_eq!(err.table_name, "Account");
+            assert_eq!(err.check, "age >= 0");
+        }
+        _ => panic!("expected Error::Constraint, got {}", fmt_res(&res)),
+    }
+
+    let account = tx
+        .create(Account {
+            login: "ann".into(),
+            age: 30,
+        })
+        .unwrap();
+    account.borrow_mut().login = String::new();
+    match tx.commit() {
+        Err(orm::Error::Constraint(err)) => assert_eq!(err.check, "length(login) > 0"),
+        res => panic!("expected Error::Constraint, got {:?}", res.err()),
+    }
+}
+//
+#[test]
+fn for_each() {
+    use orm::Filter;
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let mut ids = vec![];
+    for (name, visits) in [("Ann", 1), ("Bob", 2), ("Cid", 3), ("Dan", 4)] {
+        let user = tx
+            .create(User {
+                name: name.into(),
+                picture: vec![],
+                visits,
+                balance: 0.0,
+                is_admin: false,
+            })
+            .unwrap();
+        ids.push(user.id());
+    }
+    tx.get::<User>(ids[3]).unwrap().delete();
+
+    let mut names = vec![];
+    tx.for_each::<User, _>(&Filter::ge("visits", 2i64), |user| {
+        names.push(user.borrow().name.clone());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(names, vec!["Bob", "Cid"]);
+
+    // Dan is removed, so the first callback fails and stops the iteration.
+    let mut visited = 0;
+    let res = tx.for_each::<User, _>(Filter::And(vec![]), |_| {
+        visited += 1;
+        tx.get::<User>(ids[3]).map(|_| ())
+    });
+    assert!(matches!(res, Err(orm::Error::NotFound(_))));
+    assert_eq!(visited, 1);
+
+    // A misspelt column is an error, not a filter matching nothing.
+    let res = tx.for_each::<User, _>(Filter::ge("vists", 2i64), |_| {
+        panic!("no object should be passed on")
+    });
+    match res {
+        Err(orm::Error::UnknownColumn(column)) => assert_eq!(column, "vists"),
+        res => panic!("expected Error::UnknownColumn, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn explicit_column_order() {
+    #[derive(Object)]
+    struct Legacy {
+        #[yorm(column_order = 2)]
+        b: String,
+        c: i64,
+        #[yorm(column_order = 1)]
+        a: bool,
+    }
+
+    let schema = <Legacy as Object>::describe();
+    assert_eq!(schema.column_name_list(", "), "a, b, c");
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Legacy {
+            b: "bee".into(),
+            c: 3,
+            a: true,
+        })
+        .unwrap()
+        .id();
+    let columns = tx
+        .query_scalar1::<String>("SELECT name FROM pragma_table_info('Legacy')", &[])
+        .unwrap();
+    assert_eq!(columns, vec!["id", "a", "b", "c"]);
+    let row = tx
+        .query_scalar3::<bool, String, i64>("SELECT a, b, c FROM Legacy", &[])
+        .unwrap();
+    assert_eq!(row, vec![(true, "bee".to_owned(), 3)]);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let legacy = tx.get::<Legacy>(id).unwrap();
+    assert_eq!(legacy.borrow().b, "bee");
+    assert_eq!(legacy.borrow().c, 3);
+    assert!(legacy.borrow().a);
+}
+//
+#[test]
+fn expires_at() {
+    use orm::Clock;
+    use std::{cell::Cell, rc::Rc};
+
+    #[derive(Object)]
+    #[yorm(expires_at = "expires_at")]
+    struct Session {
+        token: String,
+        expires_at: i64,
+    }
+
+    struct ManualClock(Rc<Cell<i64>>);
+
+    impl Clock for ManualClock {
+        fn now_millis(&self) -> i64 {
+            self.0.get()
+        }
+    }
+
+    let now = Rc::new(Cell::new(1000));
+    let mut conn = Connection::open_in_memory()
+        .unwrap()
+        .with_clock(ManualClock(now.clone()));
+    let tx = conn.new_transaction().unwrap();
+    let short = tx
+        .create(Session {
+            token: "short".into(),
+            expires_at: 2000,
+        })
+        .unwrap()
+        .id();
+    let long = tx
+        .create(Session {
+            token: "long".into(),
+            expires_at: 5000,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    now.set(3000);
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Session>(short),
+        Err(orm::Error::NotFound(_))
+    ));
+    assert_eq!(tx.get::<Session>(long).unwrap().borrow().token, "long");
+    tx.commit().unwrap();
+
+    // Once loaded, an object stays usable until the end of its transaction.
+    let tx = conn.new_transaction().unwrap();
+    let session = tx.get::<Session>(long).unwrap();
+    now.set(6000);
+    assert_eq!(tx.get::<Session>(long).unwrap().borrow().token, "long");
+    assert_eq!(tx.purge_expired::<Session>().unwrap(), 2);
+    assert_eq!(session.borrow().token, "long");
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let count = tx
+        .query_scalar1::<i64>("SELECT COUNT(*) FROM Session", &[])
+        .unwrap();
+    assert_eq!(count, vec![0]);
+}
+//
+#[test]
+fn analyze() {
+    fn stat_count(tx: &orm::Transaction, table: &str) -> i64 {
+        tx.query_scalar1::<i64>(
+            "SELECT COUNT(*) FROM sqlite_stat1 WHERE tbl = ?",
+            &[table.to_owned().into()],
+        )
+        .unwrap()[0]
+    }
+
+    let mut conn = Connection::open_in_memory()
+        .unwrap()
+        .with_analyze_threshold(2);
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Order { is_tall: true }).unwrap();
+    for name in ["Ann", "Bob", "Cid"] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    // Only User got more rows than the threshold.
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(stat_count(&tx, "User"), 1);
+    assert_eq!(stat_count(&tx, "order_table"), 0);
+
+    let order = tx
+        .query_scalar1::<i64>("SELECT id FROM order_table", &[])
+        .unwrap()[0];
+    tx.get::<Order>(order.into()).unwrap();
+    tx.analyze_all().unwrap();
+    assert_eq!(stat_count(&tx, "order_table"), 1);
+
+    match tx.analyze::<Profile>() {
+        Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "Profile"),
+        res => panic!("expected Error::TableNotFound, got {:?}", res.err()),
+    }
+}
+//
+#[test]
+fn page_with_cursor() {
+    use orm::{Cursor, Direction};
+    use std::collections::HashSet;
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for i in 0..1000 {
+        tx.create(User {
+            name: format!("user{}", i % 13),
+            picture: vec![],
+            visits: i % 10,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let order = [("visits", Direction::Desc), ("name", Direction::Asc)];
+    let mut seen = HashSet::new();
+    let mut last_key: Option<(i64, String)> = None;
+    let mut cursor: Option<String> = None;
+    loop {
+        // A fresh transaction per request, with the cursor passed as a string.
+        let tx = conn.new_transaction().unwrap();
+        let cursor_value = cursor.as_deref().map(|str| Cursor::decode(str).unwrap());
+        let (users, next) = tx.page::<User>(&order, cursor_value, 7).unwrap();
+        assert!(users.len() == 7 || next.is_none());
+        for user in users.iter() {
+            assert!(seen.insert(user.id()));
+            let key = (user.borrow().visits, user.borrow().name.clone());
+            if let Some(last_key) = &last_key {
+                assert!(last_key.0 > key.0 || last_key.0 == key.0 && last_key.1 <= key.1);
+            }
+            last_key = Some(key);
+        }
+        match next {
+            Some(next) => cursor = Some(next.encode()),
+            None => break,
+        }
+    }
+    assert_eq!(seen.len(), 1000);
+
+    for bad in ["not base64!", "_w"] {
+        assert!(matches!(
+            Cursor::decode(bad),
+            Err(orm::Error::InvalidCursor(_))
+        ));
+    }
+    let tx = conn.new_transaction().unwrap();
+    let (_, next) = tx
+        .page::<User>(&[("visits", Direction::Asc)], None, 7)
+        .unwrap();
+    let res = tx.page::<User>(&order, next, 7);
+    assert!(matches!(res, Err(orm::Error::InvalidCursor(_))));
+    let res = tx.page::<User>(&[("nope", Direction::Asc)], None, 7);
+    assert!(matches!(res, Err(orm::Error::UnknownColumn(_))));
+    // Everything in one page.
+    let (users, next) = tx.page::<User>(&[], None, u64::MAX).unwrap();
+    assert_eq!(users.len(), 1000);
+    assert!(next.is_none());
+}
+//
+#[test]
+fn strict_table() {
+    #[derive(Object)]
+    #[strict]
+    struct Measurement {
+        sensor: String,
+        value: f64,
+        count: i64,
+        valid: bool,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Measurement {
+            sensor: "t1".into(),
+            value: 21.5,
+            count: 3,
+            valid: true,
+        })
+        .unwrap()
+        .id();
+    let sql = tx
+        .query_scalar1::<String>(
+            "SELECT sql FROM sqlite_master WHERE name = 'Measurement'",
+            &[],
+        )
+        .unwrap();
+    assert!(sql[0].ends_with(" STRICT"));
+    let types = tx
+        .query_scalar1::<String>("SELECT type FROM pragma_table_info('Measurement')", &[])
+        .unwrap();
+    assert_eq!(types, vec!["INTEGER", "TEXT", "REAL", "INT", "INT"]);
+
+    let res = tx.query_scalar1::<i64>(
+        "INSERT INTO Measurement (sensor, value, count, valid) \
+        VALUES ('t2', 1.0, 'many', 1) RETURNING id",
+        &[],
+    );
+    assert!(matches!(res, Err(orm::Error::Storage(_))));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let measurement = tx.get::<Measurement>(id).unwrap();
+    assert_eq!(measurement.borrow().value, 21.5);
+    assert!(measurement.borrow().valid);
+}
+//
+#[test]
+fn column_stats() {
+    #[derive(Object)]
+    struct Reading {
+        label: String,
+        value: f64,
+        hits: i64,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let stats = tx.column_stats::<Reading>("value").unwrap();
+    assert_eq!(
+        (stats.count, stats.min, stats.mean, stats.sum),
+        (0, None, None, 0.0)
+    );
+
+    for (value, hits) in [(1.5, i64::MAX), (-2.5, i64::MAX)] {
+        tx.create(Reading {
+            label: "ok".into(),
+            value,
+            hits,
+        })
+        .unwrap();
+    }
+    tx.query_scalar1::<i64>(
+        "INSERT INTO Reading (label, value, hits) \
+        VALUES ('null', NULL, NULL), ('inf', 9e999, NULL), ('-inf', -9e999, 0) RETURNING id",
+        &[],
+    )
+    .unwrap();
+
+    let stats = tx.column_stats::<Reading>("value").unwrap();
+    assert_eq!(stats.count, 4);
+    assert_eq!(stats.null_count, 1);
+    assert_eq!(stats.non_finite_count, 2);
+    assert_eq!(stats.min, Some(-2.5));
+    assert_eq!(stats.max, Some(1.5));
+    assert_eq!(stats.sum, -1.0);
+    assert_eq!(stats.mean, Some(-0.5));
+
+    // The sum of the integers would overflow i64.
+    let stats = tx.column_stats::<Reading>("hits").unwrap();
+    assert_eq!(
+        (stats.count, stats.null_count, stats.non_finite_count),
+        (3, 2, 0)
+    );
+    assert_eq!(stats.min, Some(0.0));
+    assert_eq!(stats.max, Some(i64::MAX as f64));
+    assert_eq!(stats.sum, 2.0 * i64::MAX as f64);
+    assert_eq!(stats.mean, Some(2.0 * i64::MAX as f64 / 3.0));
+
+    assert!(matches!(
+        tx.column_stats::<Reading>("label"),
+        Err(orm::Error::NonNumericColumn(_))
+    ));
+    assert!(matches!(
+        tx.column_stats::<Reading>("missing"),
+        Err(orm::Error::UnknownColumn(_))
+    ));
+}
+//
+#[cfg(feature = "csv-import")]
+#[test]
+fn import_csv() {
+    use orm::import::{CsvImportOptions, OnError};
+
+    let csv = "\
+is_admin;name;visits;balance;picture
+true;Ann;3;1.5;a
+false;Bob;many;2.5;b
+false;Cid;7;0;c
+";
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let opts = CsvImportOptions {
+        delimiter: b';',
+        on_error: OnError::Collect,
+        ..CsvImportOptions::default()
+    };
+    let result = tx.import_csv::<User, _>(csv.as_bytes(), opts).unwrap();
+    assert_eq!((result.imported, result.skipped), (2, 1));
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.errors[0].0, 3);
+    assert!(matches!(result.errors[0].1, orm::Error::Csv(_)));
+
+    let users = tx
+        .query_scalar3::<String, i64, bool>(
+            "SELECT name, visits, is_admin FROM User ORDER BY id",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        users,
+        vec![("Ann".to_owned(), 3, true), ("Cid".to_owned(), 7, false)]
+    );
+
+    let opts = CsvImportOptions {
+        delimiter: b';',
+        skip_rows: 2,
+        ..CsvImportOptions::default()
+    };
+    let result = tx.import_csv::<User, _>(csv.as_bytes(), opts).unwrap();
+    assert_eq!((result.imported, result.skipped), (1, 0));
+    let opts = CsvImportOptions {
+        delimiter: b';',
+        ..CsvImportOptions::default()
+    };
+    let res = tx.import_csv::<User, _>(csv.as_bytes(), opts);
+    assert!(matches!(res, Err(orm::Error::Csv(_))));
+
+    let res = tx.import_csv::<User, _>("name\nDan\n".as_bytes(), CsvImportOptions::default());
+    match res {
+        Err(orm::Error::Csv(err)) => assert_eq!(err.message, "no column for field 'picture'"),
+        res => panic!("expected Error::Csv, got {:?}", res.err()),
+    }
+}
+//
+#[cfg(feature = "csv-import")]
+#[test]
+fn export_csv() {
+    use orm::{export::CsvExportOptions, import::CsvImportOptions};
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let mut ids = vec![];
+    for (name, visits) in [("Ann", 3), ("Bob, Jr.", 8)] {
+        let user = tx
+            .create(User {
+                name: name.into(),
+                picture: vec![b'p'],
+                visits,
+                balance: 1.5,
+                is_admin: visits > 5,
+            })
+            .unwrap();
+        ids.push(user.id());
+    }
+
+    let mut csv = vec![];
+    let written = tx
+        .export_csv::<User, _>(&mut csv, CsvExportOptions::default())
+        .unwrap();
+    assert_eq!(written, 2);
+    assert_eq!(
+        String::from_utf8(csv.clone()).unwrap(),
+        "name,picture,visits,balance,is_admin\n\
+        Ann,p,3,1.5,false\n\
+        \"Bob, Jr.\",p,8,1.5,true\n"
+    );
+
+    // What is exported can be imported again.
+    let result = tx
+        .import_csv::<User, _>(csv.as_slice(), CsvImportOptions::default())
+        .unwrap();
+    assert_eq!(result.imported, 2);
+
+    let mut csv = vec![];
+    let opts = CsvExportOptions {
+        delimiter: b'\t',
+        include_header: false,
+        include_id_column: true,
+    };
+    assert_eq!(tx.export_csv::<User, _>(&mut csv, opts).unwrap(), 4);
+    let first_line = String::from_utf8(csv)
+        .unwrap()
+        .lines()
+        .next()
+        .unwrap()
+        .to_owned();
+    assert_eq!(first_line, format!("{}\tAnn\tp\t3\t1.5\tfalse", ids[0]));
+}
+//
+#[test]
+fn custom_data_type() {
+    use orm::data::CustomType;
+
+    // Stored as "major.minor.patch" text.
+    #[derive(Clone, PartialEq, Debug)]
+    struct Version(i64, i64, i64);
+
+    impl<'a> From<Version> for Value<'a> {
+        fn from(version: Version) -> Self {
+            format!("{}.{}.{}", version.0, version.1, version.2).into()
+        }
+    }
+
+    impl<'a> From<Value<'a>> for Version {
+        fn from(value: Value<'a>) -> Self {
+            let parts = String::from(value)
+                .split('.')
+                .map(|part| part.parse().unwrap())
+                .collect::<Vec<i64>>();
+            Version(parts[0], parts[1], parts[2])
+        }
+    }
+
+    static VERSION: CustomType = CustomType {
+        name: "Version",
+        sql_type: "VERSION_TEXT",
+        wire_type: DataType::String,
+    };
+
+    #[derive(Object)]
+    struct Release {
+        name: String,
+        #[yorm(with = "VERSION")]
+        version: Version,
+    }
+
+    let schema = <Release as Object>::describe();
+    assert_eq!(schema.get_types()[1], DataType::Custom(&VERSION));
+    assert!(schema.text_description().ends_with("version VERSION_TEXT"));
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Release {
+            name: "first".into(),
+            version: Version(1, 20, 3),
+        })
+        .unwrap()
+        .id();
+    tx.query_scalar1::<i64>(
+        "INSERT INTO Release (name, version) VALUES ('broken', X'07') RETURNING id",
+        &[],
+    )
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Release>(id).unwrap().borrow().version,
+        Version(1, 20, 3)
+    );
+    let broken = tx
+        .query_scalar1::<i64>("SELECT id FROM Release WHERE name = 'broken'", &[])
+        .unwrap()[0];
+    match tx.get::<Release>(broken.into()) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(format!("{:?}", err.expected_type), "Version")
+        }
+        res => panic!("expected Error::UnexpectedType, got {:?}", res.err()),
+    }
+}
+//
+#[test]
+fn cache_len() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!((tx.cache_len(), tx.cache_memory_estimate()), (0, 0));
+
+    let user = tx
+        .create(User {
+            name: "Ann".into(),
+            picture: vec![0; 1000],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    tx.create(User {
+        name: "Bob".into(),
+        picture: vec![],
+        visits: 0,
+        balance: 0.0,
+        is_admin: false,
+    })
+    .unwrap();
+    assert_eq!(tx.cache_len(), 2);
+    let estimate = tx.cache_memory_estimate();
+    assert!(estimate > 1000);
+
+    user.borrow_mut().picture.clear();
+    assert!(tx.cache_memory_estimate() < estimate - 900);
+    user.delete();
+    assert_eq!(tx.cache_len(), 2);
+}
+//
+#[cfg(feature = "serde-json")]
+#[test]
+fn import_json() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let result = tx
+        .import_json::<User>(
+            r#"[
+                {"name": "Ann", "picture": [1, 2], "visits": 3, "balance": 1.5, "is_admin": true},
+                {"name": "Bob", "picture": "png", "visits": 8, "balance": 2, "is_admin": false}
+            ]"#,
+        )
+        .unwrap();
+    assert_eq!(result.imported, 2);
+    assert_eq!(result.ids.len(), 2);
+    let ann = tx.get::<User>(result.ids[0]).unwrap();
+    assert_eq!(ann.borrow().picture, vec![1, 2]);
+    assert!(ann.borrow().is_admin);
+    let bob = tx.get::<User>(result.ids[1]).unwrap();
+    assert_eq!(bob.borrow().picture, b"png".to_vec());
+    assert_eq!(bob.borrow().balance, 2.0);
+
+    for json in [
+        "{}",
+        "[1]",
+        r#"[{"name": "Cid", "picture": [], "visits": "many", "balance": 0, "is_admin": false}]"#,
+        r#"[{"name": "Dan"}]"#,
+    ] {
+        assert!(matches!(
+            tx.import_json::<User>(json),
+            Err(orm::Error::Json(_))
+        ));
+    }
+
+    let profiles = tx.import_json::<Profile>(r#"[{"login": "ann"}]"#).unwrap();
+    let nickname = tx
+        .query_scalar1::<Option<String>>(
+            "SELECT nickname FROM Profile WHERE id = ?",
+            &[profiles.ids[0].into_i64().into()],
+        )
+        .unwrap();
+    assert_eq!(nickname, vec![None]);
+}
+//
+#[test]
+fn update_binds_id() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let statements = Rc::new(RefCell::new(Vec::<String>::new()));
+    let recorded = statements.clone();
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.set_sql_rewriter(Box::new(move |query| {
+        recorded.borrow_mut().push(query.to_owned());
+        query.to_owned()
+    }));
+
+    let tx = conn.new_transaction().unwrap();
+    let mut ids = vec![];
+    for name in ["Ann", "Bob"] {
+        let user = tx
+            .create(User {
+                name: name.into(),
+                picture: vec![],
+                visits: 0,
+                balance: 0.0,
+                is_admin: false,
+            })
+            .unwrap();
+        ids.push(user.id());
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for id in ids.iter() {
+        tx.get::<User>(*id).unwrap().borrow_mut().visits = 5;
+    }
+    statements.borrow_mut().clear();
+    tx.commit().unwrap();
+
+    let updates = statements
+        .borrow()
+        .iter()
+        .filter(|query| query.starts_with("UPDATE"))
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(updates.len(), 2);
+    assert_eq!(updates[0], updates[1]);
+    assert!(updates[0].ends_with("WHERE id = ?"));
+
+    let tx = conn.new_transaction().unwrap();
+    for id in ids {
+        assert_eq!(tx.get::<User>(id).unwrap().borrow().visits, 5);
+    }
+}
+//
+#[test]
+fn schema_html_table() {
+    use orm::object::SchemaBuilder;
+
+    let schema = SchemaBuilder::new("note")
+        .column("title", DataType::String)
+        .nullable_column("a<b", DataType::Int64)
+        .build();
+    assert_eq!(
+        schema.html_table(),
+        "<table>\n\
+         <thead>\n\
+         <tr><th>Field</th><th>Column</th><th>Type</th><th>Constraints</th></tr>\n\
+         </thead>\n\
+         <tbody>\n\
+         <tr><td></td><td>id</td><td>INTEGER</td><td>PRIMARY KEY AUTOINCREMENT</td></tr>\n\
+         <tr><td>title</td><td>title</td><td>TEXT</td><td>NOT NULL</td></tr>\n\
+         <tr><td>a&lt;b</td><td>a&lt;b</td><td>BIGINT</td><td>NULL</td></tr>\n\
+         </tbody>\n\
+         </table>\n"
+    );
+
+    #[derive(Object)]
+    #[check("length(login) > 0")]
+    struct Account {
+        login: String,
+        #[check("age >= 0")]
+        age: i64,
+        #[readonly]
+        #[null_as_default]
+        login_length: i64,
+    }
+
+    let html = Account::describe().html_table();
+    assert!(html.contains(
+        "<tr><td>login</td><td>login</td><td>TEXT</td>\
+         <td>NOT NULL, CHECK (length(login) &gt; 0)</td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr><td>age</td><td>age</td><td>BIGINT</td><td>NOT NULL, CHECK (age &gt;= 0)</td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr><td>login_length</td><td>login_length</td><td>BIGINT</td><td>NULL, READONLY</td></tr>"
+    ));
+}
+//
+#[test]
+fn unit_struct_lifecycle() {
+    #[derive(Object)]
+    struct Marker;
+
+    assert_eq!(
+        Marker::describe().text_description(),
+        "id INTEGER PRIMARY KEY AUTOINCREMENT"
+    );
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let kept = tx.create(Marker).unwrap();
+    let deleted = tx.create(Marker).unwrap();
+    let (kept_id, deleted_id) = (kept.id(), deleted.id());
+    drop(kept.borrow_mut());
+    assert!(matches!(kept.state(), ObjectState::Modified));
+    deleted.delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let kept = tx.get::<Marker>(kept_id).unwrap();
+    drop(kept.borrow_mut());
+    assert!(matches!(
+        tx.get::<Marker>(deleted_id),
+        Err(orm::Error::NotFound(_))
+    ));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Marker>(kept_id).unwrap().delete();
+    tx.commit().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Marker>(kept_id),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+//
+#[test]
+fn ensure_indexes_conflict() {
+    #[derive(Object)]
+    #[table_name("Account")]
+    #[index(login)]
+    struct AccountV1 {
+        login: String,
+        active: bool,
+    }
+
+    #[derive(Object)]
+    #[table_name("Account")]
+    #[index(login, where = "active = 1")]
+    struct AccountV2 {
+        login: String,
+        active: bool,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(AccountV1 {
+        login: "root".into(),
+        active: true,
+    })
+    .unwrap();
+    tx.ensure_indexes(&[AccountV1::describe()]).unwrap();
+
+    match tx.ensure_indexes(&[AccountV2::describe()]) {
+        Err(orm::Error::IndexConflict(err)) => {
+            assert_eq!(err.index_name, "Account_login_idx");
+            assert_eq!(
+                err.existing,
+                "CREATE INDEX Account_login_idx ON Account (login)"
+            );
+        }
+        res => panic!("expected IndexConflict, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn nested_savepoint_names() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let statements = Rc::new(RefCell::new(Vec::<String>::new()));
+    let recorded = statements.clone();
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.set_sql_rewriter(Box::new(move |query| {
+        if query.contains("SAVEPOINT") || query.contains("RELEASE") || query.contains("ROLLBACK") {
+            recorded.borrow_mut().push(query.to_owned());
+        }
+        query.to_owned()
+    }));
+
+    let tx = conn.new_transaction().unwrap();
+    let child = tx.begin_nested("import").unwrap();
+    let id = child
+        .create(User {
+            name: "Ivy".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    // The names are only labels, the savepoints are numbered instead.
+    let grandchild = child.begin_nested("import rows").unwrap();
+    grandchild.get::<User>(id).unwrap().delete();
+    grandchild.rollback().unwrap();
+    child.commit().unwrap();
+    let child = tx.begin_nested("order").unwrap();
+    child.commit().unwrap();
+    assert_eq!(tx.get::<User>(id).unwrap().borrow().name, "Ivy");
+    tx.commit().unwrap();
+
+    assert_eq!(
+        *statements.borrow(),
+        [
+            "SAVEPOINT \"sp_0\";",
+            "SAVEPOINT \"sp_1\";",
+            "ROLLBACK TO \"sp_1\";",
+            "RELEASE \"sp_1\";",
+            "RELEASE \"sp_0\";",
+            "SAVEPOINT \"sp_2\";",
+            "RELEASE \"sp_2\";",
+        ]
+    );
+}
+//
+#[test]
+fn derive_expansion_snapshots() {
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    struct Plain {
+        name: String,
+        picture: Vec<u8>,
+        visits: i64,
+        balance: f64,
+        is_admin: bool,
+    }
+
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    #[table_name("plain_renamed")]
+    struct Renamed {
+        #[column_name("IsTall")]
+        is_tall: bool,
+    }
+
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    struct Unit;
+
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    #[check("length(login) > 0")]
+    struct Checked {
+        login: String,
+        #[readonly]
+        login_length: i64,
+        #[check("age >= 0")]
+        age: i64,
+    }
+
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    #[index(login)]
+    #[index(active, where = "active = 1")]
+    struct Indexed {
+        login: String,
+        active: bool,
+    }
+
+    #[derive(Object)]
+    #[yorm(debug_expand)]
+    #[strict]
+    #[yorm(visible_when = "tenant_id = :tenant", expires_at = "expires")]
+    #[yorm(connection_type = "ReadOnly")]
+    struct Options {
+        #[yorm(column_order = 1)]
+        tenant_id: i64,
+        expires: i64,
+        #[yorm(column_order = 0)]
+        title: String,
+    }
+
+    // How TokenStream's Display spaces and wraps tokens changes between
+    // compiler versions, so whitespace doesn't count.
+    fn without_whitespace(code: &str) -> String {
+        code.split_whitespace().collect()
+    }
+
+    fn assert_expansion(name: &str, expansion: &str) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/expand")
+            .join(format!("{}.expanded.rs", name));
+        if std::env::var_os("YORM_BLESS").is_some() {
+            std::fs::write(&path, format!("{}\n", expansion)).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert_eq!(
+            without_whitespace(expansion),
+            without_whitespace(&expected),
+            "the expansion of {} changed, rerun with YORM_BLESS=1 to update {}",
+            name,
+            path.display()
+        );
+    }
+
+    assert_expansion("plain", Plain::YORM_EXPANSION);
+    assert_expansion("renamed", Renamed::YORM_EXPANSION);
+    assert_expansion("unit", Unit::YORM_EXPANSION);
+    assert_expansion("checked", Checked::YORM_EXPANSION);
+    assert_expansion("indexed", Indexed::YORM_EXPANSION);
+    assert_expansion("options", Options::YORM_EXPANSION);
+}
+//
+#[test]
+fn schema_from_create_sql() {
+    #[derive(Object)]
+    #[check("length(login) > 0")]
+    struct Member {
+        login: String,
+        picture: Vec<u8>,
+        #[check("age >= 0")]
+        age: i64,
+        rating: f64,
+        #[column_name("IsActive")]
+        active: bool,
+    }
+
+    let derived = Member::describe();
+    let sql = format!("CREATE TABLE Member ({});", derived.text_description());
+    let parsed = orm::object::Schema::new_from_create_sql(&sql).unwrap();
+    assert_eq!(parsed.get_table_name(), "Member");
+    assert_eq!(parsed.fingerprint(), derived.fingerprint());
+    assert_eq!(parsed.checks(), derived.checks());
+    assert_eq!(parsed.text_description(), derived.text_description());
+    assert!(!parsed.is_strict());
+
+    let parsed = orm::object::Schema::new_from_create_sql(
+        "create table if not exists \"note\" (id INTEGER PRIMARY KEY, \
+         title TEXT NOT NULL, views INT, CHECK (title <> '(,)')) STRICT",
+    )
+    .unwrap();
+    assert_eq!(parsed.get_table_name(), "note");
+    assert!(parsed.is_strict());
+    assert_eq!(parsed.get_types(), [DataType::String, DataType::Int64]);
+    assert_eq!(parsed.checks(), ["title <> '(,)'"]);
+
+    for sql in [
+        "CREATE INDEX user_idx ON user (name)",
+        "CREATE TABLE t (name TEXT)",
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(10))",
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT UNIQUE)",
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT",
+        "CREATE TABLE t (id INTEGER PRIMARY KEY) WITHOUT ROWID",
+    ] {
+        assert!(
+            matches!(
+                orm::object::Schema::new_from_create_sql(sql),
+                Err(orm::Error::InvalidDdl(_))
+            ),
+            "{}",
+            sql
+        );
+    }
+}
+//
+#[test]
+fn ids_by_state() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let users = ["Ann", "Bob", "Cid", "Dee"]
+        .iter()
+        .map(|name| {
+            tx.create(User {
+                name: (*name).into(),
+                picture: vec![],
+                visits: 0,
+                balance: 0.0,
+                is_admin: false,
+            })
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let ids = users.iter().map(|user| user.id()).collect::<Vec<_>>();
+    assert_eq!(tx.ids_by_state(ObjectState::Clean), ids);
+    assert!(tx.ids_by_state(ObjectState::Removed).is_empty());
+
+    users[2].borrow_mut().visits = 1;
+    for user in users.into_iter().step_by(3) {
+        user.delete();
+    }
+    assert_eq!(tx.ids_by_state(ObjectState::Clean), [ids[1]]);
+    assert_eq!(tx.ids_by_state(ObjectState::Modified), [ids[2]]);
+    assert_eq!(tx.ids_by_state(ObjectState::Removed), [ids[0], ids[3]]);
+}
+//
+#[test]
+fn table_name_from_const() {
+    mod names {
+        pub const ACCOUNTS: &str = "accounts";
+    }
+
+    #[derive(Object)]
+    #[yorm(table = names::ACCOUNTS)]
+    struct Account {
+        login: String,
+    }
+
+    #[derive(Object)]
+    #[yorm(table = "account_archive")]
+    struct ArchivedAccount {
+        login: String,
+    }
+
+    assert_eq!(Account::table_name(), names::ACCOUNTS);
+    assert_eq!(ArchivedAccount::table_name(), "account_archive");
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Account {
+        login: "root".into(),
+    })
+    .unwrap();
+    let query = format!("SELECT login FROM {}", names::ACCOUNTS);
+    assert_eq!(tx.query_scalar1::<String>(&query, &[]).unwrap(), ["root"]);
+}
+//
+#[test]
+fn row_width_mismatch() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.set_sql_rewriter(Box::new(|query| {
+        query.replace("is_admin FROM User", "is_admin, 1 FROM User")
+    }));
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Uma".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<User>(id) {
+        Err(orm::Error::RowWidth(err)) => {
+            assert_eq!((err.table_name, err.expected, err.got), ("User", 5, 6));
+        }
+        res => panic!("expected RowWidth, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn get_for_update() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Vic".into(),
+            picture: vec![],
+            visits: 0,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    assert!(matches!(
+        tx.get_for_update::<User>(id),
+        Err(orm::Error::ImmediateRequired)
+    ));
+    tx.commit().unwrap();
+
+    let other = rusqlite::Connection::open(&path).unwrap();
+    other.busy_timeout(std::time::Duration::ZERO).unwrap();
+
+    let tx = conn.new_immediate_transaction().unwrap();
+    tx.get_for_update::<User>(id).unwrap().borrow_mut().visits = 1;
+    // The whole database is locked, not just the row.
+    assert!(other.execute_batch("BEGIN IMMEDIATE").is_err());
+    let child = tx.begin_nested("lock").unwrap();
+    child.get_for_update::<User>(id).unwrap();
+    drop(child);
+    tx.commit().unwrap();
+
+    other.execute_batch("BEGIN IMMEDIATE; COMMIT").unwrap();
+    let visits: i64 = other
+        .query_row("SELECT visits FROM User", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(visits, 1);
+}
+//
+#[test]
+fn execute_returning() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    if conn.sqlite_version() < (3, 35, 0) {
+        return;
+    }
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Wes".into(),
+            picture: vec![],
+            visits: 1,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+
+    let row = tx
+        .execute_returning(
+            "UPDATE User SET visits = visits + 1 WHERE name = ? RETURNING id, visits",
+            &["Wes".to_owned().into()],
+        )
+        .unwrap();
+    assert!(matches!(row[0], Value::Int64(row_id) if row_id == id.into_i64()));
+    assert!(matches!(row[1], Value::Int64(2)));
+
+    let row = tx
+        .execute_returning(
+            "INSERT INTO User (name, picture, visits, balance, is_admin) \
+             VALUES ('Xan', X'', 0, 0.0, 0) RETURNING id, name",
+            &[],
+        )
+        .unwrap();
+    assert!(matches!(&row[1], Value::String(name) if name == "Xan"));
+
+    assert!(matches!(
+        tx.execute_returning("DELETE FROM User WHERE visits > 10 RETURNING id", &[]),
+        Err(orm::Error::NoRows)
+    ));
+}
+//
+#[test]
+fn for_each_chunks_large_in_lists() {
+    use orm::Filter;
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for visits in 0..3000 {
+        tx.create(User {
+            name: format!("user{}", visits),
+            picture: vec![],
+            visits,
+            balance: 0.0,
+            is_admin: visits % 2 == 0,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    // Descending, so that the chunks match rows out of id order.
+    let filter = Filter::is_in("visits", (0..2000i64).rev()).and(Filter::eq("is_admin", true));
+    assert!(filter.to_sql_chunks(orm::filter::MAX_PARAMS).len() > 1);
+    let mut visits = Vec::new();
+    tx.for_each::<User, _>(&filter, |user| {
+        visits.push(user.borrow().visits);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(visits, (0..2000).step_by(2).collect::<Vec<_>>());
+}
+//
+#[test]
+fn wal_checkpoint() {
+    use orm::{CheckpointMode, CheckpointResult};
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let wal_path = format!("{}-wal", path.display());
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    assert_eq!(
+        conn.wal_checkpoint(CheckpointMode::Passive).unwrap(),
+        CheckpointResult {
+            busy: false,
+            wal_pages: 0,
+            checkpointed_pages: 0,
+        }
+    );
+    conn.enable_wal().unwrap();
+    conn.set_wal_autocheckpoint(0).unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for visits in 0..2000 {
+        tx.create(User {
+            name: format!("user{}", visits),
+            picture: vec![0; 64],
+            visits,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+    assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+
+    let result = conn.wal_checkpoint(CheckpointMode::Passive).unwrap();
+    assert!(!result.busy);
+    assert!(result.checkpointed_pages > 0);
+    assert_eq!(result.checkpointed_pages, result.wal_pages);
+    // Truncate empties the WAL, so it has no pages to report.
+    assert!(!conn.wal_checkpoint(CheckpointMode::Truncate).unwrap().busy);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.query_scalar1::<i64>("SELECT COUNT(*) FROM User", &[])
+            .unwrap(),
+        [2000]
+    );
+}
+//
+#[cfg(feature = "uuid")]
+#[test]
+fn object_id_uuid() {
+    for n in [0, 1, 42, (1 << 48) - 1] {
+        let uuid = ObjectId::new(n).try_into_uuid().unwrap();
+        assert_eq!(uuid.get_version_num(), 7);
+        assert_eq!(uuid.get_variant(), uuid::Variant::RFC4122);
+        assert_eq!(ObjectId::from_uuid(uuid), ObjectId::new(n));
+    }
+    assert_eq!(
+        ObjectId::new(0x0123_4567_89ab)
+            .try_into_uuid()
+            .unwrap()
+            .to_string(),
+        "01234567-89ab-7000-8000-000000000000"
+    );
+    assert!(ObjectId::new(1).try_into_uuid().unwrap() < ObjectId::new(2).try_into_uuid().unwrap());
+
+    let uuid = uuid::Uuid::parse_str("018f6b3c-7d2e-7a1b-9c4d-5e6f7a8b9c0d").unwrap();
+    assert_eq!(ObjectId::from_uuid(uuid), ObjectId::new(0x018f_6b3c_7d2e));
+    for n in [-1, 1 << 48, i64::MIN, i64::MAX] {
+        assert_eq!(ObjectId::new(n).try_into_uuid(), None);
+    }
+}
+//
+#[test]
+fn schema_registry() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.register::<User>().unwrap();
+    conn.register::<Order>().unwrap();
+    assert!(conn.schema_for("User").is_some());
+    assert_eq!(
+        conn.schema_for("order_table").unwrap().get_type_name(),
+        "Order"
+    );
+    assert!(conn.schema_for("Order").is_none());
+    assert!(conn.schema_for("Profile").is_none());
+
+    let schema = conn.schema_for("User").unwrap().clone();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Yul".into(),
+            picture: vec![],
+            visits: 3,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    let table = tx.dyn_table(schema).unwrap();
+    assert!(matches!(table.get(id).unwrap()[2], Value::Int64(3)));
+}
+//
+#[test]
+fn query_timing() {
+    use orm::Filter;
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let reported_clone = reported.clone();
+    conn.set_slow_query_callback(
+        Duration::ZERO,
+        Box::new(move |timing| reported_clone.borrow_mut().push(timing.shape.clone())),
+    );
+    conn.enable_query_timing(2);
+
+    let tx = conn.new_transaction().unwrap();
+    for visits in 0..3 {
+        tx.create(User {
+            name: "Ann".into(),
+            picture: vec![],
+            visits,
+            balance: 0.0,
+            is_admin: false,
+        })
+        .unwrap();
+    }
+    let visits = |values: Vec<i64>| Filter::is_in("visits", values.into_iter().map(Value::from));
+    tx.for_each::<User, _>(&visits(vec![1]), |_| Ok(()))
+        .unwrap();
+    tx.for_each::<User, _>(&visits(vec![0, 2]), |_| Ok(()))
+        .unwrap();
+    tx.commit().unwrap();
+
+    let timings = conn.slow_queries(Duration::ZERO);
+    assert_eq!(timings.len(), 2);
+    let insert = timings
+        .iter()
+        .find(|timing| timing.shape.starts_with("INSERT INTO User"))
+        .unwrap();
+    assert_eq!(insert.count, 3);
+    assert_eq!(insert.histogram.iter().sum::<u64>(), 3);
+    let select = timings
+        .iter()
+        .find(|timing| timing.shape.starts_with("SELECT"))
+        .unwrap();
+    assert!(select.shape.contains("\"visits\" IN (?)"));
+    assert_eq!(select.count, 2);
+    assert!(select.max <= select.total);
+
+    assert_eq!(
+        *reported.borrow(),
+        vec![insert.shape.clone(), select.shape.clone()]
+    );
+    assert!(conn.slow_queries(Duration::from_secs(3600)).is_empty());
+}
+//
+#[test]
+fn list_tables_and_table_info() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE legacy (\
+                id INTEGER PRIMARY KEY AUTOINCREMENT, name VARCHAR(20) NOT NULL, qty INT DEFAULT 0\
+            )",
+            [],
+        )
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Order { is_tall: true }).unwrap();
+    tx.create(User {
+        name: "Ann".into(),
+        picture: vec![],
+        visits: 0,
+        balance: 0.0,
+        is_admin: false,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    // AUTOINCREMENT creates sqlite_sequence, which isn't listed.
+    assert_eq!(
+        conn.list_tables().unwrap(),
+        ["User", "legacy", "order_table"]
+    );
+
+    let info = conn.table_info("User").unwrap();
+    assert_eq!(info.get_table_name(), "User");
+    // yorm doesn't declare its columns NOT NULL, so they read as nullable.
+    assert_eq!(
+        info.column_name_list(", "),
+        User::describe().column_name_list(", ")
+    );
+    assert_eq!(info.get_types(), User::describe().get_types());
+    assert!(info.is_nth_column_nullable(0));
+    let legacy = conn.table_info("legacy").unwrap();
+    assert_eq!(legacy.column_name_list(", "), "name, qty");
+    assert_eq!(legacy.get_types(), [DataType::String, DataType::Int64]);
+    assert!(!legacy.is_nth_column_nullable(0));
+    assert!(legacy.is_nth_column_nullable(1));
+    assert_eq!(
+        DataType::from_declared_type("nvarchar(255)"),
+        DataType::String
+    );
+    assert_eq!(DataType::from_declared_type(""), DataType::Bytes);
+    assert_eq!(DataType::from_declared_type("DOUBLE"), DataType::Float64);
+    assert_eq!(
+        DataType::from_declared_type("DECIMAL(10,2)"),
+        DataType::Float64
+    );
+    match conn.table_info("Profile") {
+        Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "Profile"),
+        res => panic!("expected Error::TableNotFound, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn duration_fields() {
+    use std::time::Duration;
+
+    #[derive(Object)]
+    struct Job {
+        timeout: Duration,
+        interval: std::time::Duration,
+    }
+
+    assert_eq!(
+        format!("{:?}", Job::describe().get_types()),
+        "[Duration, Duration]"
+    );
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let job = tx
+        .create(Job {
+            timeout: Duration::new(3, 5),
+            interval: Duration::from_millis(1500),
+        })
+        .unwrap();
+    let id = job.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let job = tx.get::<Job>(id).unwrap();
+    assert_eq!(job.borrow().timeout, Duration::new(3, 5));
+    assert_eq!(job.borrow().interval, Duration::from_millis(1500));
+    let query = "SELECT timeout FROM Job WHERE id = ?";
+    let nanos = tx.query_scalar1::<i64>(query, &[id.into_i64().into()]);
+    assert_eq!(nanos.unwrap(), [3_000_000_005]);
+    let timeouts = tx.query_scalar1::<Duration>(query, &[id.into_i64().into()]);
+    assert_eq!(timeouts.unwrap(), [Duration::new(3, 5)]);
+
+    let res = tx.create(Job {
+        timeout: Duration::MAX,
+        interval: Duration::ZERO,
+    });
+    match res {
+        Err(orm::Error::ValueOutOfRange(err)) => assert_eq!(err.attr_name, "timeout"),
+        res => panic!("expected Error::ValueOutOfRange, got {}", fmt_res(&res)),
+    }
+    job.borrow_mut().interval = Duration::from_secs(u64::MAX);
+    drop(job);
+    match tx.commit() {
+        Err(orm::Error::ValueOutOfRange(err)) => {
+            assert_eq!(err.attr_name, "interval");
+            assert_eq!(err.column_type, DataType::Custom(&orm::data::DURATION));
+        }
+        res => panic!("expected Error::ValueOutOfRange, got {}", fmt_res(&res)),
+    }
+
+    // Written by another tool.
+    let tx = conn.new_transaction().unwrap();
+    let query = "UPDATE Job SET timeout = -5 WHERE id = ? RETURNING id";
+    tx.execute_returning(query, &[id.into_i64().into()])
+        .unwrap();
+    match tx.get::<Job>(id) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "timeout");
+            assert_eq!(err.got_type, "negative Int64");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn existing_table() {
+    #[derive(Object)]
+    #[table_name("legacy_item")]
+    #[yorm(existing_table)]
+    struct Item {
+        name: String,
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    match tx.create(Item { name: "a".into() }) {
+        Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "legacy_item"),
+        res => panic!("expected Error::TableNotFound, got {}", fmt_res(&res)),
+    }
+    tx.rollback().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute_batch(
+            "CREATE TABLE legacy_item (id INTEGER PRIMARY KEY, name VARCHAR(20), extra INT);
+             INSERT INTO legacy_item (id, name) VALUES (-7, 'minus seven'), (3, 'three'), (40, 'forty');",
+        )
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Item>(ObjectId::new(-7)).unwrap().borrow().name,
+        "minus seven"
+    );
+    tx.get::<Item>(ObjectId::new(3)).unwrap().borrow_mut().name = "THREE".into();
+    tx.get::<Item>(ObjectId::new(40)).unwrap().delete();
+    let created = tx.create(Item { name: "new".into() }).unwrap().id();
+    assert_eq!(created, ObjectId::new(41));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Item>(ObjectId::new(3)).unwrap().borrow().name,
+        "THREE"
+    );
+    assert!(matches!(
+        tx.get::<Item>(ObjectId::new(40)),
+        Err(orm::Error::NotFound(_))
+    ));
+    assert_eq!(tx.get::<Item>(created).unwrap().borrow().name, "new");
+    tx.commit().unwrap();
+
+    // VARCHAR(20) and the extra column are left alone.
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let result = Item::describe().migration_auto_apply(&sqlite_conn).unwrap();
+    assert!(result.unchanged);
+}
+//
+#[test]
+fn load_or_create() {
+    #[derive(Object, Default)]
+    struct Counter {
+        hits: i64,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let (counter, created) = tx.load_or_create::<Counter>(ObjectId::new(7)).unwrap();
+    assert!(created);
+    assert_eq!(counter.id(), ObjectId::new(7));
+    assert_eq!(counter.borrow().hits, 0);
+    counter.borrow_mut().hits += 1;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let (counter, created) = tx.load_or_create::<Counter>(ObjectId::new(7)).unwrap();
+    assert!(!created);
+    assert_eq!(counter.borrow().hits, 1);
+    let (again, created) = tx.load_or_create::<Counter>(ObjectId::new(7)).unwrap();
+    assert!(!created);
+    counter.borrow_mut().hits = 2;
+    assert_eq!(again.borrow().hits, 2);
+    // New ids keep counting from the ones created explicitly.
+    assert_eq!(
+        tx.create(Counter { hits: 5 }).unwrap().id(),
+        ObjectId::new(8)
+    );
+
+    counter.delete();
+    assert!(matches!(
+        tx.load_or_create::<Counter>(ObjectId::new(7)),
+        Err(orm::Error::NotFound(_))
+    ));
+}
+//
+#[test]
+fn null_as_default() {
+    #[derive(Object)]
+    struct Imported {
+        #[null_as_default]
+        title: String,
+        #[null_as_default]
+        score: i64,
+        rank: i64,
+    }
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Imported {
+            title: "t".into(),
+            score: 1,
+            rank: 2,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute("UPDATE Imported SET title = NULL, score = NULL", [])
+        .unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let imported = tx.get::<Imported>(id).unwrap();
+    assert_eq!(imported.borrow().title, "");
+    assert_eq!(imported.borrow().score, 0);
+    assert_eq!(imported.borrow().rank, 2);
+    imported.borrow_mut().rank = 3;
+    drop(imported);
+    tx.commit().unwrap();
+    // Writing the object back stores the defaults, not NULL.
+    let title: Option<String> = sqlite_conn
+        .query_row("SELECT title FROM Imported", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(title.as_deref(), Some(""));
+
+    sqlite_conn
+        .execute("UPDATE Imported SET rank = NULL", [])
+        .unwrap();
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<Imported>(id) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "rank");
+            assert_eq!(err.got_type, "Null");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn unexpected_type_value() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    sqlite_conn
+        .execute(
+            "CREATE TABLE \"User\" (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, \
+             picture BLOB, visits BIGINT, balance REAL, is_admin TINYINT)",
+            [],
+        )
+        .unwrap();
+    sqlite_conn
+        .execute(
+            "INSERT INTO \"User\" VALUES (?, ?, ?, ?, ?, ?), (?, ?, ?, ?, ?, ?)",
+            params![
+                1,
+                &[0u8; 300][..],
+                &b""[..],
+                0,
+                0.0,
+                false,
+                2,
+                "Ann",
+                &b""[..],
+                "x".repeat(100),
+                0.0,
+                false
+            ],
+        )
+        .unwrap();
+    sqlite_conn.close().unwrap();
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<User>(ObjectId::new(1)) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "name");
+            assert_eq!(err.value, "<300 bytes>");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+    match tx.get::<User>(ObjectId::new(2)) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "visits");
+            assert_eq!(err.value, format!("'{}'... (100 chars)", "x".repeat(64)));
+            assert!(err.to_string().contains("got Text value 'xxx"));
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+}
+//
+#[test]
+fn update_if() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(User {
+            name: "Ann".into(),
+            picture: vec![],
+            visits: 1,
+            balance: 10.0,
+            is_admin: false,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let expected = [("visits", 1.into()), ("name", "Ann".to_owned().into())];
+    let set = [("visits", 2.into()), ("balance", 5.5.into())];
+    assert!(tx.update_if::<User>(id, &expected, &set).unwrap());
+    // The row changed under the expectation, so the second swap fails.
+    assert!(!tx.update_if::<User>(id, &expected, &set).unwrap());
+    assert!(!tx
+        .update_if::<User>(ObjectId::new(id.into_i64() + 1), &[], &set)
+        .unwrap());
+    assert!(tx
+        .update_if::<User>(id, &[("visits", 2.into())], &[])
+        .unwrap());
+
+    match tx.update_if::<User>(id, &[("nickname", Value::Null)], &set) {
+        Err(orm::Error::UnknownColumn(column)) => assert_eq!(column, "nickname"),
+        res => panic!("expected Error::UnknownColumn, got {}", fmt_res(&res)),
+    }
+    match tx.update_if::<User>(id, &expected, &[("id", 5.into())]) {
+        Err(orm::Error::UnknownColumn(column)) => assert_eq!(column, "id"),
+        res => panic!("expected Error::UnknownColumn, got {}", fmt_res(&res)),
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let user = tx.get::<User>(id).unwrap();
+    assert_eq!(user.borrow().visits, 2);
+    assert_eq!(user.borrow().balance, 5.5);
+}
+//
+#[test]
+fn commit_summary() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let user = |name: &str| User {
+        name: name.into(),
+        picture: vec![],
+        visits: 0,
+        balance: 0.0,
+        is_admin: false,
+    };
+    let ann = tx.create(user("Ann")).unwrap().id();
+    let bob = tx.create(user("Bob")).unwrap().id();
+    let dan = tx.create(user("Dan")).unwrap().id();
+    let order = tx.create(Order { is_tall: false }).unwrap().id();
+    let summary = tx.commit_summarized().unwrap();
+    assert_eq!(
+        summary.inserted,
+        [
+            ("User", ann),
+            ("User", bob),
+            ("User", dan),
+            ("order_table", order)
+        ]
+    );
+    assert!(summary.updated.is_empty());
+    assert!(summary.deleted.is_empty());
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(bob).unwrap().borrow_mut().visits += 1;
+    tx.get::<User>(ann).unwrap().borrow_mut().visits += 1;
+    tx.get::<User>(dan).unwrap().delete();
+    // Only read, so not written.
+    tx.get::<User>(ann).unwrap();
+    let carol = tx.create(user("Carol")).unwrap();
+    let carol_id = carol.id();
+    carol.delete();
+    let summary = tx.commit_summarized().unwrap();
+    assert_eq!(summary.inserted, [("User", carol_id)]);
+    assert_eq!(summary.updated, [("User", ann), ("User", bob)]);
+    assert_eq!(summary.deleted, [("User", dan), ("User", carol_id)]);
+    assert!(summary.duration > std::time::Duration::ZERO);
+}
+//
+#[test]
+fn transaction_exclusive() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let other = rusqlite::Connection::open(&path).unwrap();
+    other.busy_timeout(std::time::Duration::ZERO).unwrap();
+
+    let id = conn
+        .transaction_exclusive(|tx| {
+            let user = tx.create(User {
+                name: "Ed".into(),
+                picture: vec![],
+                visits: 0,
+                balance: 0.0,
+                is_admin: false,
+            })?;
+            // Unlike with BEGIN IMMEDIATE, reading is locked out too.
+            assert!(other
+                .query_row("SELECT count(*) FROM User", [], |row| row.get::<_, i64>(0))
+                .is_err());
+            tx.get_for_update::<User>(user.id())?;
+            Ok(user.id())
+        })
+        .unwrap();
+
+    let res = conn.transaction_exclusive(|tx| {
+        tx.get::<User>(id)?.borrow_mut().visits = 5;
+        tx.get::<User>(ObjectId::new(id.into_i64() + 1))?;
+        Ok(())
+    });
+    assert!(matches!(res, Err(orm::Error::NotFound(_))));
+
+    let visits: i64 = other
+        .query_row("SELECT visits FROM User", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(visits, 0);
+}
+//
+#[test]
+fn page_size() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    for page_size in [0, 256, 1000, 131072] {
+        match conn.set_page_size(page_size) {
+            Err(orm::Error::InvalidPageSize(size)) => assert_eq!(size, page_size),
+            res => panic!("expected Error::InvalidPageSize, got {:?}", res),
+        }
+    }
+    assert!(conn.set_page_size(8192).unwrap());
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Order { is_tall: true }).unwrap();
+    tx.commit().unwrap();
+    drop(conn);
+
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    assert!(!conn.set_page_size(1024).unwrap());
+    let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+    let page_size: i64 = sqlite_conn
+        .query_row("PRAGMA page_size", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(page_size, 8192);
+}
+//
+#[test]
+fn identifier_limits() {
+    use orm::object::{SchemaBuilder, MAX_COLUMNS, MAX_IDENTIFIER_LEN};
+
+    const LONG_NAME: &str = "a_table_name_which_goes_on_and_on_and_on_and_on_and_on_and_on_\
+        and_on_and_on_and_on_and_on_and_on_and_on_and_on_and_on_and_on_and_on";
+    assert!(LONG_NAME.len() > MAX_IDENTIFIER_LEN);
+    #[derive(Object)]
+    #[yorm(table = LONG_NAME)]
+    struct Long {
+        value: i64,
+    }
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    match tx.create(Long { value: 1 }) {
+        Err(orm::Error::IdentifierTooLong(err)) => {
+            assert_eq!(err.identifier, LONG_NAME);
+            assert_eq!(err.limit, MAX_IDENTIFIER_LEN);
+        }
+        res => panic!("expected Error::IdentifierTooLong, got {}", fmt_res(&res)),
+    }
+
+    let long_column = "c".repeat(MAX_IDENTIFIER_LEN + 1);
+    let schema = SchemaBuilder::new("wide")
+        .column(
+            Box::leak(long_column.clone().into_boxed_str()),
+            DataType::Int64,
+        )
+        .build();
+    match tx.dyn_table(schema) {
+        Err(orm::Error::IdentifierTooLong(err)) => assert_eq!(err.identifier, long_column),
+        res => panic!("expected Error::IdentifierTooLong, got {}", res.is_ok()),
+    }
+
+    let mut builder = SchemaBuilder::new("wide");
+    for i in 0..=MAX_COLUMNS {
+        builder = builder.column(
+            Box::leak(format!("c{}", i).into_boxed_str()),
+            DataType::Int64,
+        );
+    }
+    match tx.dyn_table(builder.build()) {
+        Err(orm::Error::TooManyColumns(err)) => {
+            assert_eq!(err.table_name, "wide");
+            assert_eq!(err.columns, MAX_COLUMNS + 1);
+        }
+        res => panic!("expected Error::TooManyColumns, got {}", res.is_ok()),
+    }
+
+    tx.create(Order { is_tall: true }).unwrap();
+    assert!(matches!(
+        tx.rename_table("order_table", &long_column),
+        Err(orm::Error::IdentifierTooLong(_))
+    ));
+}
+//
+#[test]
+fn table_naming() {
+    use orm::{AnyTx, TableNaming};
+
+    assert_eq!(TableNaming::TypeName.table_name("OrderItem"), "OrderItem");
+    assert_eq!(TableNaming::SnakeCase.table_name("OrderItem"), "order_item");
+    assert_eq!(
+        TableNaming::SnakeCase.table_name("HTTPRequest"),
+        "http_request"
+    );
+    assert_eq!(
+        TableNaming::SnakeCasePlural.table_name("OrderItem"),
+        "order_items"
+    );
+    assert_eq!(
+        TableNaming::SnakeCasePlural.table_name("Address"),
+        "addresses"
+    );
+    assert_eq!(
+        TableNaming::SnakeCasePlural.table_name("Category"),
+        "categories"
+    );
+    assert_eq!(TableNaming::SnakeCasePlural.table_name("Survey"), "surveys");
+
+    #[derive(Object)]
+    struct OrderItem {
+        quantity: i64,
+    }
+
+    let mut conn = Connection::open_in_memory()
+        .unwrap()
+        .with_table_naming(TableNaming::SnakeCasePlural);
+    conn.register::<OrderItem>().unwrap();
+    assert!(conn.schema_for("order_items").is_some());
+
+    let tx = conn.new_transaction().unwrap();
+    let item = tx.create(OrderItem { quantity: 2 }).unwrap();
+    let item_id = item.id();
+    let any: AnyTx = item.into_any();
+    assert_eq!(any.table_name(), "order_items");
+    drop(any);
+    tx.get::<OrderItem>(item_id).unwrap().borrow_mut().quantity = 3;
+    let summary = tx.commit_summarized().unwrap();
+    assert_eq!(summary.updated, vec![("order_items", item_id)]);
+
+    // An explicit #[table_name] wins over the naming.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Order { is_tall: true }).unwrap();
+    tx.commit().unwrap();
+
+    let mut tables = conn.list_tables().unwrap();
+    tables.sort();
+    assert_eq!(tables, vec!["order_items", "order_table"]);
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<OrderItem>(item_id).unwrap().borrow().quantity, 3);
+    drop(tx);
+
+    // Types registered before the naming is set are renamed along.
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.register::<OrderItem>().unwrap();
+    conn.register::<Order>().unwrap();
+    assert!(conn.schema_for("OrderItem").is_some());
+    let conn = conn.with_table_naming(TableNaming::SnakeCasePlural);
+    assert!(conn.schema_for("OrderItem").is_none());
+    assert!(conn.schema_for("order_items").is_some());
+    assert!(conn.schema_for("order_table").is_some());
+}
+//
+// Tx has interior mutability, but its hash is only the id, which never changes.
+#[allow(clippy::mutable_key_type)]
+#[test]
+fn tx_eq_by_id() {
+    use std::collections::{HashMap, HashSet};
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let first = tx.create(Order { is_tall: true }).unwrap();
+    let second = tx.create(Order { is_tall: false }).unwrap();
+    let first_again = tx.get::<Order>(first.id()).unwrap();
+    assert!(first == first_again);
+    assert!(first != second);
+
+    let orders = [
+        first_again,
+        tx.get(second.id()).unwrap(),
+        tx.get(first.id()).unwrap(),
+    ]
+    .into_iter()
+    .collect::<HashSet<_>>();
+    assert_eq!(orders.len(), 2);
+    assert!(orders.contains(&first) && orders.contains(&second));
+
+    let mut heights = HashMap::new();
+    heights.insert(tx.get::<Order>(first.id()).unwrap(), "tall");
+    heights.insert(second, "short");
+    assert_eq!(heights[&tx.get::<Order>(first.id()).unwrap()], "tall");
+}
+//
+#[cfg(feature = "tracing")]
+#[test]
+fn transaction_spans() {
+    use std::{
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    struct SpanData {
+        metadata: &'static Metadata<'static>,
+        parent: Option<u64>,
+        fields: HashMap<&'static str, String>,
+        refs: usize,
+        closed: bool,
+    }
+
+    #[derive(Default)]
+    struct Recorded {
+        spans: Vec<SpanData>,
+        // Parent span and sql of the statement events.
+        statements: Vec<(Option<u64>, String)>,
+        entered: Vec<u64>,
+    }
+
+    struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_owned());
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Recorded>>);
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+            let mut recorded = self.0.lock().unwrap();
+            let parent = match attrs.parent() {
+                Some(parent) => Some(parent.into_u64()),
+                None if attrs.is_contextual() => recorded.entered.last().copied(),
+                None => None,
+            };
+            let mut span = SpanData {
+                metadata: attrs.metadata(),
+                parent,
+                fields: HashMap::new(),
+                refs: 1,
+                closed: false,
+            };
+            attrs.record(&mut Fields(&mut span.fields));
+            recorded.spans.push(span);
+            span::Id::from_u64(recorded.spans.len() as u64)
+        }
+        fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+            let mut recorded = self.0.lock().unwrap();
+            let span = &mut recorded.spans[id.into_u64() as usize - 1];
+            values.record(&mut Fields(&mut span.fields));
+        }
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut recorded = self.0.lock().unwrap();
+            let parent = match event.parent() {
+                Some(parent) => Some(parent.into_u64()),
+                None if event.is_contextual() => recorded.entered.last().copied(),
+                None => None,
+            };
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            if let Some(sql) = fields.remove("sql") {
+                recorded.statements.push((parent, sql));
+            }
+        }
+        fn enter(&self, id: &span::Id) {
+            self.0.lock().unwrap().entered.push(id.into_u64());
+        }
+        fn exit(&self, _: &span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+        fn clone_span(&self, id: &span::Id) -> span::Id {
+            self.0.lock().unwrap().spans[id.into_u64() as usize - 1].refs += 1;
+            id.clone()
+        }
+        fn try_close(&self, id: span::Id) -> bool {
+            let mut recorded = self.0.lock().unwrap();
+            let span = &mut recorded.spans[id.into_u64() as usize - 1];
+            span.refs -= 1;
+            span.closed = span.refs == 0;
+            span.closed
+        }
+    }
+
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let request = tracing::info_span!("request");
+        let _entered = request.enter();
+
+        let tx = conn.new_transaction().unwrap();
+        tx.create(Order { is_tall: true }).unwrap();
+        let nested = tx.begin_nested("attempt").unwrap();
+        nested.rollback().unwrap();
+        tx.commit().unwrap();
+
+        conn.new_transaction().unwrap().rollback().unwrap();
+        // Also what happens when a panic unwinds through a transaction.
+        drop(conn.new_transaction().unwrap());
+    });
+
+    let recorded = recorder.0.lock().unwrap();
+    let span_ids = |name: &str| {
+        (1..=recorded.spans.len() as u64)
+            .filter(|&id| recorded.spans[id as usize - 1].metadata.name() == name)
+            .collect::<Vec<_>>()
+    };
+    let request = span_ids("request")[0];
+    let transactions = span_ids("yorm.transaction");
+    assert_eq!(transactions.len(), 4);
+    let span = |id: u64| &recorded.spans[id as usize - 1];
+    let field = |id: u64, name: &str| span(id).fields.get(name).cloned();
+
+    let (committed, nested) = (transactions[0], transactions[1]);
+    assert_eq!(span(committed).parent, Some(request));
+    assert_eq!(field(committed, "outcome").as_deref(), Some("commit"));
+    assert_eq!(field(committed, "inserted").as_deref(), Some("1"));
+    assert_eq!(field(committed, "updated").as_deref(), Some("0"));
+    assert!(field(committed, "duration_us").is_some());
+    assert!(recorded
+        .statements
+        .iter()
+        .any(|(parent, sql)| *parent == Some(committed) && sql.starts_with("INSERT INTO")));
+    assert!(recorded
+        .statements
+        .iter()
+        .any(|(parent, sql)| *parent == Some(committed) && sql == "COMMIT;"));
+
+    assert_eq!(span(nested).parent, Some(committed));
+    assert_eq!(field(nested, "savepoint").as_deref(), Some("attempt"));
+    assert_eq!(field(nested, "outcome").as_deref(), Some("rollback"));
+
+    assert_eq!(
+        field(transactions[2], "outcome").as_deref(),
+        Some("rollback")
+    );
+    assert_eq!(
+        field(transactions[3], "outcome").as_deref(),
+        Some("dropped")
+    );
+    assert_ne!(field(transactions[2], "id"), field(transactions[3], "id"));
+    assert!(transactions.iter().all(|&id| span(id).closed));
+}
+//
+#[test]
+fn get_or_insert() {
+    let user = |name: &str, visits: i64| User {
+        name: name.into(),
+        picture: Vec::new(),
+        visits,
+        balance: 0.0,
+        is_admin: false,
+    };
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let (alice, created) = tx
+        .get_or_insert("name", "Alice".to_owned(), user("Alice", 1))
+        .unwrap();
+    assert!(created);
+    let alice_id = alice.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let (alice, created) = tx
+        .get_or_insert("name", "Alice".to_owned(), user("Alice", 2))
+        .unwrap();
+    assert!(!created);
+    assert_eq!(alice.id(), alice_id);
+    assert_eq!(alice.borrow().visits, 1);
+    // The cached object is returned, with its modifications.
+    alice.borrow_mut().visits = 5;
+    let (again, _) = tx
+        .get_or_insert("name", "Alice".to_owned(), user("Alice", 3))
+        .unwrap();
+    assert_eq!(again.borrow().visits, 5);
+
+    let (bob, created) = tx
+        .get_or_insert("name", "Bob".to_owned(), user("Bob", 1))
+        .unwrap();
+    assert!(created);
+    assert_ne!(bob.id(), alice_id);
+
+    // A match removed in this transaction counts as none.
+    again.delete();
+    let (new_alice, created) = tx
+        .get_or_insert("name", "Alice".to_owned(), user("Alice", 7))
+        .unwrap();
+    assert!(created);
+    assert_ne!(new_alice.id(), alice_id);
+
+    match tx.get_or_insert("nickname", "Alice".to_owned(), user("Alice", 1)) {
+        Err(orm::Error::UnknownColumn(column)) => assert_eq!(column, "nickname"),
+        res => panic!("expected Error::UnknownColumn, got {}", res.is_ok()),
+    }
+
+    tx.commit().unwrap();
+
+    // Columns can also be given by their column name.
+    let tx = conn.new_transaction().unwrap();
+    let (order, created) = tx
+        .get_or_insert("IsTall", true, Order { is_tall: true })
+        .unwrap();
+    assert!(created);
+    let (same, created) = tx
+        .get_or_insert("is_tall", true, Order { is_tall: true })
+        .unwrap();
+    assert!(!created);
+    assert_eq!(same.id(), order.id());
+}
+//
+#[test]
+fn row_eq_and_hash() {
+    use orm::{row_eq, row_hash};
+
+    let user = |picture: Vec<u8>, balance: f64| User {
+        name: "Alice".into(),
+        picture,
+        visits: 1,
+        balance,
+        is_admin: false,
+    };
+    let a = user(vec![1, 2, 3], 1.5);
+    assert!(row_eq(&a, &user(vec![1, 2, 3], 1.5)));
+    assert_eq!(row_hash(&a), row_hash(&user(vec![1, 2, 3], 1.5)));
+
+    // Blobs are compared byte by byte, including their length.
+    for picture in [vec![1, 2, 4], vec![1, 2], vec![1, 2, 3, 0], vec![]] {
+        let b = user(picture, 1.5);
+        assert!(!row_eq(&a, &b));
+        assert_ne!(row_hash(&a), row_hash(&b));
+    }
+
+    // Floats are compared by their bits.
+    let nan = user(Vec::new(), f64::NAN);
+    assert!(row_eq(&nan, &user(Vec::new(), f64::NAN)));
+    assert_eq!(row_hash(&nan), row_hash(&user(Vec::new(), f64::NAN)));
+    let (zero, negative_zero) = (user(Vec::new(), 0.0), user(Vec::new(), -0.0));
+    assert!(!row_eq(&zero, &negative_zero));
+    assert_ne!(row_hash(&zero), row_hash(&negative_zero));
+
+    // Values of different types don't collide, nor do shifted fields.
+    #[derive(Object)]
+    struct Pair {
+        first: String,
+        second: String,
+    }
+    let pair = |first: &str, second: &str| Pair {
+        first: first.into(),
+        second: second.into(),
+    };
+    assert!(!row_eq(&pair("ab", "c"), &pair("a", "bc")));
+    assert_ne!(row_hash(&pair("ab", "c")), row_hash(&pair("a", "bc")));
+    assert_ne!(
+        row_hash(&Order { is_tall: true }),
+        row_hash(&Order { is_tall: false })
+    );
+    #[derive(Object)]
+    struct Number {
+        value: i64,
+    }
+    #[derive(Object)]
+    struct Flag {
+        value: bool,
+    }
+    assert_ne!(
+        row_hash(&Number { value: 1 }),
+        row_hash(&Flag { value: true })
+    );
+}
+//
+#[test]
+fn assume_tables_exist() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let mut tx = conn.new_transaction().unwrap();
+    tx.assume_tables_exist(true);
+    match tx.create(Order { is_tall: true }) {
+        Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "order_table"),
+        res => panic!("expected Error::TableNotFound, got {}", res.is_ok()),
+    }
+    match tx.get::<Order>(ObjectId::new(1)) {
+        Err(orm::Error::TableNotFound(err)) => assert_eq!(err.table_name, "order_table"),
+        res => panic!("expected Error::TableNotFound, got {}", res.is_ok()),
+    }
+
+    // ensure_tables still creates them, after which everything works.
+    orm::ensure_tables!(tx, Order).unwrap();
+    let id = tx.create(Order { is_tall: true }).unwrap().id();
+    let nested = tx.begin_nested("check").unwrap();
+    assert!(nested.get::<Order>(id).unwrap().borrow().is_tall);
+    nested.commit().unwrap();
+    tx.commit().unwrap();
+
+    // No lookup in sqlite_master before the statements.
+    let statements = Rc::new(RefCell::new(Vec::new()));
+    let recorded = statements.clone();
+    conn.set_sql_rewriter(Box::new(move |query| {
+        recorded.borrow_mut().push(query.to_owned());
+        query.to_owned()
+    }));
+    let mut tx = conn.new_transaction().unwrap();
+    tx.assume_tables_exist(true);
+    tx.get::<Order>(id).unwrap();
+    assert!(statements
+        .borrow()
+        .iter()
+        .all(|query| !query.contains("sqlite_master")));
+}
+//
+#[test]
+fn sqlite_version_required() {
+    use orm::object::{Index, SchemaBuilder};
+
+    let builder = || SchemaBuilder::new("versioned").column("value", DataType::Int64);
+    assert_eq!(builder().build().sqlite_version_required(), (3, 3, 0));
+    let partial = builder().index(Index {
+        columns: vec!["value"],
+        predicate: Some("value > 0"),
+    });
+    assert_eq!(partial.build().sqlite_version_required(), (3, 8, 0));
+    #[derive(Object)]
+    struct Generated {
+        _value: i64,
+        #[readonly]
+        _doubled: i64,
+    }
+    assert_eq!(Generated::describe().sqlite_version_required(), (3, 31, 0));
+    assert_eq!(
+        builder().existing_table().build().sqlite_version_required(),
+        (3, 35, 0)
+    );
+    let strict = builder().strict().existing_table().build();
+    assert_eq!(strict.sqlite_version_required(), (3, 37, 0));
+
+    strict.check_sqlite_version((3, 37, 0)).unwrap();
+    match strict.check_sqlite_version((3, 36, 9)) {
+        Err(orm::Error::IncompatibleSqliteVersion(err)) => {
+            assert_eq!(err.table_name, "versioned");
+            assert_eq!(err.required, (3, 37, 0));
+            assert_eq!(err.found, (3, 36, 9));
+            assert_eq!(
+                err.to_string(),
+                "table 'versioned' needs SQLite 3.37.0 or newer, found 3.36.9"
+            );
+        }
+        res => panic!(
+            "expected Error::IncompatibleSqliteVersion, got {:?}",
+            res.is_ok()
+        ),
+    }
+
+    // The SQLite yorm is built with is new enough for all of it.
+    let mut conn = Connection::open_in_memory().unwrap();
+    assert!(conn.sqlite_version() >= (3, 37, 0));
+    let tx = conn.new_transaction().unwrap();
+    tx.dyn_table(builder().strict().build()).unwrap();
+}
+//
+#[test]
+fn delete_many() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let ids = (0..1500)
+        .map(|i| {
+            tx.create(Order {
+                is_tall: i % 2 == 0,
+            })
+            .unwrap()
+            .id()
+        })
+        .collect::<Vec<_>>();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let kept = tx.get::<Order>(ids[1]).unwrap();
+    let removed = tx.get::<Order>(ids[0]).unwrap();
+    assert_eq!(tx.delete_many::<Order>(&[]).unwrap(), 0);
+    // More ids than fit in one statement, a repeated one and an unknown one.
+    let mut to_delete = ids[..1200]
+        .iter()
+        .copied()
+        .filter(|&id| id != ids[1])
+        .collect::<Vec<_>>();
+    to_delete.push(ids[0]);
+    to_delete.push(ObjectId::new(100_000));
+    assert_eq!(tx.delete_many::<Order>(&to_delete).unwrap(), 1199);
+
+    assert!(removed.state() == ObjectState::Removed);
+    assert!(kept.state() == ObjectState::Clean);
+    assert!(matches!(
+        tx.get::<Order>(ids[0]),
+        Err(orm::Error::NotFound(_))
+    ));
+    assert!(matches!(
+        tx.get::<Order>(ids[2]),
+        Err(orm::Error::NotFound(_))
+    ));
+    tx.get::<Order>(ids[1200]).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let count = tx
+        .query_scalar1::<i64>("SELECT COUNT(*) FROM order_table", &[])
+        .unwrap();
+    assert_eq!(count, [301]);
+}
+//
+#[test]
+fn filter_check_columns() {
+    use orm::Filter;
+
+    let schema = User::describe();
+    let filter =
+        Filter::ge("visits", 5i64).and(Filter::is_in("id", [1i64, 2]).or(!Filter::is_null("name")));
+    assert!(filter.check_columns(&schema).is_ok());
+
+    let misspelt = Filter::ge("visits", 5i64).and(!Filter::is_null("nmae"));
+    match misspelt.check_columns(&schema) {
+        Err(orm::Error::UnknownColumn(column)) => assert_eq!(column, "nmae"),
+        res => panic!("expected Error::UnknownColumn, got {}", fmt_res(&res)),
+    }
+    // Columns, not field names.
+    assert!(Filter::eq("is_tall", true)
+        .check_columns(&Order::describe())
+        .is_err());
+}
+//
+#[test]
+fn delete_many_hidden_by_visible_when() {
+    #[derive(Object)]
+    #[yorm(visible_when = "tenant_id = :current_tenant")]
+    struct Document {
+        tenant_id: i64,
+    }
+
+    struct Tenant(i64);
+
+    impl orm::SecurityContext for Tenant {
+        fn current_values(&self) -> std::collections::HashMap<String, Value<'static>> {
+            [("current_tenant".to_owned(), self.0.into())].into()
+        }
+    }
+
+    let mut conn = Connection::open_in_memory()
+        .unwrap()
+        .with_security_context(Tenant(1));
+    let tx = conn.new_transaction().unwrap();
+    let ids = [
+        tx.create(Document { tenant_id: 1 }).unwrap().id(),
+        tx.create(Document { tenant_id: 1 }).unwrap().id(),
+    ];
+    tx.commit().unwrap();
+
+    // The cached object still belongs to tenant 1, its row no longer does.
+    let tx = conn.new_transaction().unwrap();
+    let moved = tx.get::<Document>(ids[0]).unwrap();
+    let deleted = tx.get::<Document>(ids[1]).unwrap();
+    tx.execute_returning(
+        "UPDATE Document SET tenant_id = 2 WHERE id = ? RETURNING id",
+        &[ids[0].into_i64().into()],
+    )
+    .unwrap();
+    assert_eq!(tx.delete_many::<Document>(&ids).unwrap(), 1);
+    assert!(moved.state() == ObjectState::Clean);
+    assert!(deleted.state() == ObjectState::Removed);
+}
+//
 #[cfg(feature = "test-lifetimes-create")]
 #[test]
 fn lifetimes_create() {
@@ -832,3 +5197,28 @@ fn lifetimes_get() {
 
     eprintln!("is_tall: {}", order.borrow().is_tall);
 }
+
+#[cfg(feature = "test-lifetimes-upgrade")]
+#[test]
+fn lifetimes_upgrade() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+
+    let weak = tx.create(Order { is_tall: false }).unwrap().downgrade();
+    let order = weak.upgrade(&tx).unwrap();
+    tx.commit();
+
+    order.borrow_mut().is_tall = true;
+}
+
+#[cfg(feature = "test-lifetimes-rollback")]
+#[test]
+fn lifetimes_rollback() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+
+    let order = tx.create(Order { is_tall: false }).unwrap();
+    tx.rollback();
+
+    order.borrow_mut().is_tall = true;
+}